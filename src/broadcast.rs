@@ -1,6 +1,6 @@
-use crossbeam::channel::{bounded, Receiver as CBReceiver, Sender as CBSender};
+use crossbeam::channel::{bounded, Receiver as CBReceiver, Sender as CBSender, TryRecvError};
 
-use crate::{Evented, Interest, Reaction, Reactor, Result, System};
+use crate::{Event, Evented, Interest, Reaction, Reactor, Result, System};
 
 // -----------------------------------------------------------------------------
 //     - Broadcaster -
@@ -20,9 +20,10 @@ impl<T: Clone> Broadcaster<T> {
         inst
     }
     pub fn send(&mut self, val: T) {
+        // Send before poking so the receiver never wakes to an empty channel
         self.subscribers.iter_mut().for_each(|(e, tx)| {
-            e.poke();
             tx.send(val.clone());
+            e.poke();
         });
     }
 
@@ -88,14 +89,22 @@ impl<T> Reactor for Receiver<T> {
             Reaction::Event(ev) if ev.owner != self.evented.reactor_id => Reaction::Event(ev),
             Reaction::Value(()) | Reaction::Continue => Reaction::Continue,
             Reaction::Event(ev) => {
-                self.evented.consume_event();
-
-                let val = match self.rx.recv() {
-                    Ok(val) => Ok(val),
-                    Err(e) => Err(e.into()),
-                };
-
-                Reaction::Value(val)
+                if ev.read {
+                    self.evented.consume_event();
+                }
+
+                // One value per reaction: if more are queued the event
+                // is deferred to receive the next one before the next poll.
+                match self.rx.try_recv() {
+                    Ok(val) => {
+                        if !self.rx.is_empty() {
+                            System::defer(Event { read: false, write: false, owner: ev.owner });
+                        }
+                        Reaction::Value(Ok(val))
+                    }
+                    Err(TryRecvError::Empty) => Reaction::Continue,
+                    Err(e) => Reaction::Value(Err(e.into())),
+                }
             }
         }
     }
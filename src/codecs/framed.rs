@@ -0,0 +1,389 @@
+use std::io::{self, Read, Write};
+use std::io::ErrorKind::{UnexpectedEof, WouldBlock};
+use std::os::unix::io::AsRawFd;
+
+use super::{Decoder, Encoder};
+use crate::{Event, Interest, PollReactor, Reaction, Reactor, Result, System};
+
+const READ_SIZE: usize = 4096;
+const DEFAULT_READ_LIMIT: usize = 64 * 1024;
+
+// -----------------------------------------------------------------------------
+//     - Framed -
+// -----------------------------------------------------------------------------
+/// A stream combined with a codec.
+///
+/// As a reactor this reads from the stream on read events and yields
+/// one decoded frame per `Reaction::Value`. If more frames are buffered the
+/// event is deferred so the next frame follows before the next poll.
+///
+/// The stream is only read when no whole frame is buffered, and at most
+/// the read limit at a time, so a peer sending faster than frames are
+/// taken can't grow the buffer past a frame and the read limit.
+///
+/// Once the peer has closed the stream and every frame has been yielded,
+/// an `UnexpectedEof` error is yielded and `is_closed` returns true.
+/// The same goes for a frame that can't be decoded: the error is yielded
+/// and the stream is closed. Anything left to write is still written.
+pub struct Framed<S: AsRawFd, C> {
+    stream: PollReactor<S>,
+    codec: C,
+    read_buf: Vec<u8>,
+    read_limit: usize,
+    // The stream may hold more than has been read
+    unread: bool,
+    // An event is deferred to decode the next buffered frame
+    deferred: bool,
+    write_buf: Vec<u8>,
+    eof: bool,
+    closed: bool,
+}
+
+impl<S, C> Framed<S, C>
+where
+    S: AsRawFd + Read + Write,
+    C: Decoder,
+{
+    pub fn new(stream: PollReactor<S>, codec: C) -> Self {
+        Self {
+            stream,
+            codec,
+            read_buf: Vec::new(),
+            read_limit: DEFAULT_READ_LIMIT,
+            unread: false,
+            deferred: false,
+            write_buf: Vec::new(),
+            eof: false,
+            closed: false,
+        }
+    }
+
+    /// Read at most `limit` bytes per event. Defaults to 64KiB.
+    /// A larger frame is read over several events.
+    pub fn read_limit(mut self, limit: usize) -> Self {
+        self.read_limit = limit.max(1);
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.stream.id
+    }
+
+    pub fn get_ref(&self) -> &PollReactor<S> {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut PollReactor<S> {
+        &mut self.stream
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Bytes read but not yet decoded.
+    pub fn read_buffer(&self) -> &[u8] {
+        &self.read_buf
+    }
+
+    /// Bytes encoded but not yet written.
+    pub fn write_buffer(&self) -> &[u8] {
+        &self.write_buf
+    }
+
+    /// True once the peer has closed the stream and no frames are left,
+    /// or a frame couldn't be decoded.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn into_parts(self) -> (PollReactor<S>, C, Vec<u8>) {
+        (self.stream, self.codec, self.read_buf)
+    }
+
+    /// Switch to another codec, e.g. after a protocol upgrade.
+    /// Buffered bytes in both directions are kept.
+    pub fn map_codec<D, F>(self, f: F) -> Framed<S, D>
+    where
+        D: Decoder,
+        F: FnOnce(C) -> D,
+    {
+        Framed {
+            stream: self.stream,
+            codec: f(self.codec),
+            read_buf: self.read_buf,
+            read_limit: self.read_limit,
+            unread: self.unread,
+            deferred: self.deferred,
+            write_buf: self.write_buf,
+            eof: self.eof,
+            closed: self.closed,
+        }
+    }
+
+    /// Encode `item` and write as much as the stream accepts.
+    /// The rest is written on the next write event.
+    pub fn send<T>(&mut self, item: T) -> Result<()>
+    where
+        C: Encoder<T>,
+    {
+        self.codec.encode(item, &mut self.write_buf)?;
+        self.flush()?;
+        self.rearm()
+    }
+
+    /// Write buffered bytes until the stream would block.
+    pub fn flush(&mut self) -> Result<()> {
+        while !self.write_buf.is_empty() {
+            match self.stream.write(&self.write_buf) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                Ok(n) => {
+                    self.write_buf.drain(..n);
+                }
+                Err(ref e) if e.kind() == WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    // Read until the stream would block, or the read limit is reached
+    fn fill(&mut self) -> Result<()> {
+        let mut read = 0;
+        self.unread = true;
+
+        while read < self.read_limit {
+            let len = self.read_buf.len();
+            self.read_buf.resize(len + READ_SIZE.min(self.read_limit - read), 0);
+
+            match self.stream.read(&mut self.read_buf[len..]) {
+                Ok(0) => {
+                    self.read_buf.truncate(len);
+                    self.eof = true;
+                    self.unread = false;
+                    break;
+                }
+                Ok(n) => {
+                    self.read_buf.truncate(len + n);
+                    read += n;
+                }
+                Err(e) => {
+                    self.read_buf.truncate(len);
+                    self.unread = false;
+                    match e.kind() {
+                        WouldBlock => break,
+                        _ => return Err(e.into()),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn rearm(&mut self) -> Result<()> {
+        // Buffered frames are decoded by a deferred event first
+        let read = !self.eof && !self.closed && !self.deferred;
+        let write = !self.write_buf.is_empty();
+
+        match (read, write) {
+            (true, true) => self.stream.rearm(Interest::ReadWrite),
+            (true, false) => self.stream.rearm(Interest::Read),
+            (false, true) => self.stream.rearm(Interest::Write),
+            (false, false) => Ok(()),
+        }
+    }
+
+    fn decode(&mut self) -> Result<Option<C::Item>> {
+        match self.eof {
+            false => self.codec.decode(&mut self.read_buf),
+            true => self.codec.decode_eof(&mut self.read_buf),
+        }
+    }
+
+    fn next_frame(&mut self, ev: Event) -> Result<Option<C::Item>> {
+        if ev.write {
+            self.flush()?;
+        }
+
+        if ev.read && !self.eof {
+            self.unread = true;
+        }
+
+        // Only read once the buffered frames are taken
+        let mut frame = self.decode()?;
+        let fill = frame.is_none() && self.unread;
+        if fill {
+            self.fill()?;
+            frame = self.decode()?;
+        }
+
+        // Decode the next buffered frame without waiting for epoll.
+        // Anything left unread is reported by epoll once read is rearmed.
+        self.deferred = frame.is_some() && (!self.read_buf.is_empty() || self.eof);
+        if self.deferred {
+            System::defer(Event { read: false, write: false, owner: ev.owner });
+        }
+
+        if ev.read || ev.write || !self.deferred {
+            self.rearm()?;
+        }
+
+        Ok(frame)
+    }
+
+    // The peer may only have shut down its side of the stream, or sent
+    // something that couldn't be decoded, so what's left is still written
+    // until it's all gone or writing fails.
+    fn flush_closed(&mut self, ev: Event) -> Result<()> {
+        if !ev.write || self.write_buf.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = self.flush() {
+            self.write_buf.clear();
+            return Err(e);
+        }
+
+        self.rearm()
+    }
+}
+
+impl<S, C> Reactor for Framed<S, C>
+where
+    S: AsRawFd + Read + Write,
+    C: Decoder,
+{
+    type Input = ();
+    type Output = Result<C::Item>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(ev) if ev.owner != self.stream.id => Reaction::Event(ev),
+            Reaction::Event(ev) if self.closed => {
+                self.stream.update(&ev);
+                match self.flush_closed(ev) {
+                    Ok(()) => Reaction::Continue,
+                    Err(e) => Reaction::Value(Err(e)),
+                }
+            }
+            Reaction::Event(ev) => {
+                self.stream.update(&ev);
+                match self.next_frame(ev) {
+                    Ok(Some(frame)) => Reaction::Value(Ok(frame)),
+                    Ok(None) if self.eof => {
+                        self.closed = true;
+                        let _ = self.rearm();
+                        Reaction::Value(Err(io::Error::from(UnexpectedEof).into()))
+                    }
+                    Ok(None) => Reaction::Continue,
+                    Err(e) => {
+                        self.closed = true;
+                        let _ = self.rearm();
+                        Reaction::Value(Err(e))
+                    }
+                }
+            }
+            _ => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::os::unix::net::UnixStream as StdUnixStream;
+    use crate::net::uds::UnixStream;
+
+    // A byte followed by that many bytes
+    struct Short;
+
+    impl Decoder for Short {
+        type Item = Vec<u8>;
+
+        fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+            match buf.first() {
+                Some(&255) => Err(crate::Error::protocol("bad frame")),
+                Some(&len) if buf.len() > len as usize => {
+                    let frame = buf[1..=len as usize].to_vec();
+                    buf.drain(..=len as usize);
+                    Ok(Some(frame))
+                }
+                _ => Ok(None),
+            }
+        }
+    }
+
+    impl Encoder<&[u8]> for Short {
+        fn encode(&mut self, item: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+            buf.push(item.len() as u8);
+            buf.extend_from_slice(item);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn frames_in_and_out() {
+        System::builder().finish();
+        let (a, b) = StdUnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        b.set_nonblocking(true).unwrap();
+        let mut a = Framed::new(UnixStream::try_from(a).unwrap(), Short);
+        let mut b = Framed::new(UnixStream::try_from(b).unwrap(), Short);
+
+        a.send(&b"hello"[..]).unwrap();
+        a.send(&b"world"[..]).unwrap();
+        drop(a);
+
+        let ev = Event { read: true, write: false, owner: b.id() };
+        let frames = (0..3).map(|_| b.react(Reaction::Event(ev))).collect::<Vec<_>>();
+        assert!(matches!(&frames[0], Reaction::Value(Ok(f)) if f == b"hello"));
+        assert!(matches!(&frames[1], Reaction::Value(Ok(f)) if f == b"world"));
+        assert!(matches!(&frames[2], Reaction::Value(Err(_))));
+        assert!(b.is_closed());
+    }
+
+    #[test]
+    fn read_limit() {
+        System::builder().finish();
+        let (a, mut b) = StdUnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let mut a = Framed::new(UnixStream::try_from(a).unwrap(), Short).read_limit(10);
+        b.write_all(&[2, b'a', b'b'].repeat(100)).unwrap();
+
+        let ev = Event { read: true, write: false, owner: a.id() };
+        for _ in 0..100 {
+            match a.react(Reaction::Event(ev)) {
+                Reaction::Value(Ok(frame)) => assert_eq!(frame, b"ab"),
+                r => panic!("unexpected {:?}", r),
+            }
+            // Never more than one read's worth buffered
+            assert!(a.read_buffer().len() <= 10);
+        }
+    }
+
+    #[test]
+    fn decode_error_closes() {
+        System::builder().finish();
+        let (a, mut b) = StdUnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let mut a = Framed::new(UnixStream::try_from(a).unwrap(), Short);
+        b.write_all(&[255, 1, b'x']).unwrap();
+
+        let ev = Event { read: true, write: false, owner: a.id() };
+        assert!(matches!(a.react(Reaction::Event(ev)), Reaction::Value(Err(_))));
+        assert!(a.is_closed());
+        assert!(matches!(a.react(Reaction::Event(ev)), Reaction::Continue));
+
+        // Still writable, e.g. to say what went wrong
+        a.send(&b"bad"[..]).unwrap();
+        let mut buf = [0u8; 4];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"\x03bad");
+    }
+}
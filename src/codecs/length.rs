@@ -0,0 +1,305 @@
+use std::convert::TryInto;
+
+use super::{Decoder, Encoder};
+use crate::{Error, Result};
+
+const DEFAULT_MAX_FRAME: usize = 8 * 1024 * 1024;
+const MAX_VARINT_LEN: usize = 10;
+
+// -----------------------------------------------------------------------------
+//     - Length field -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LengthField {
+    U8,
+    U16,
+    U32,
+    U64,
+    /// Unsigned LEB128, as used by protobuf.
+    Varint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+// -----------------------------------------------------------------------------
+//     - Length delimited codec -
+// -----------------------------------------------------------------------------
+/// Frames prefixed with their length.
+///
+/// A frame looks like this:
+///
+/// ```text
+/// | offset bytes | length field | payload (length + adjustment bytes) |
+/// ```
+///
+/// Decoding yields the payload; the offset bytes and the length field are
+/// stripped. The defaults are a big endian `u32` length, no offset, no
+/// adjustment and a maximum frame size of 8 MiB.
+///
+/// ```
+/// # use netlib::codecs::{Decoder, LengthDelimitedCodec, LengthField, Endian};
+/// let mut codec = LengthDelimitedCodec::new()
+///     .length_field(LengthField::U16)
+///     .endian(Endian::Little);
+/// let mut buf = vec![2, 0, b'h', b'i'];
+/// assert_eq!(codec.decode(&mut buf).unwrap(), Some(b"hi".to_vec()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LengthDelimitedCodec {
+    field: LengthField,
+    endian: Endian,
+    offset: usize,
+    adjustment: isize,
+    max_frame: usize,
+}
+
+impl LengthDelimitedCodec {
+    pub fn new() -> Self {
+        Self {
+            field: LengthField::U32,
+            endian: Endian::Big,
+            offset: 0,
+            adjustment: 0,
+            max_frame: DEFAULT_MAX_FRAME,
+        }
+    }
+
+    pub fn length_field(mut self, field: LengthField) -> Self {
+        self.field = field;
+        self
+    }
+
+    /// Byte order of fixed width length fields.
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    /// Number of bytes before the length field.
+    /// These are skipped when decoding and written as zeros when encoding.
+    pub fn header_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Added to the length field to get the payload size, e.g. `-2` if a
+    /// `u16` length field counts itself.
+    pub fn length_adjustment(mut self, adjustment: isize) -> Self {
+        self.adjustment = adjustment;
+        self
+    }
+
+    /// The largest payload accepted, in bytes.
+    pub fn max_frame_size(mut self, max: usize) -> Self {
+        self.max_frame = max;
+        self
+    }
+
+    /// Look at the next frame without consuming it.
+    /// Returns the payload and the total size of the frame in `buf`.
+    pub fn peek<'a>(&self, buf: &'a [u8]) -> Result<Option<(&'a [u8], usize)>> {
+        let header = match buf.get(self.offset..) {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        let (len, field_len) = match self.read_length(header)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        let payload = (len as i128 + self.adjustment as i128)
+            .try_into()
+            .map_err(|_| Error::protocol("negative frame length"))
+            .and_then(|len: usize| match len > self.max_frame {
+                true => Err(Error::protocol("frame too large")),
+                false => Ok(len),
+            })?;
+
+        let start = self.offset + field_len;
+        match buf.get(start..start + payload) {
+            Some(frame) => Ok(Some((frame, start + payload))),
+            None => Ok(None),
+        }
+    }
+
+    fn read_length(&self, buf: &[u8]) -> Result<Option<(u64, usize)>> {
+        let width = match self.field {
+            LengthField::U8 => 1,
+            LengthField::U16 => 2,
+            LengthField::U32 => 4,
+            LengthField::U64 => 8,
+            LengthField::Varint => return read_varint(buf),
+        };
+
+        if buf.len() < width {
+            return Ok(None);
+        }
+
+        let mut bytes = [0u8; 8];
+        let len = match self.endian {
+            Endian::Big => {
+                bytes[8 - width..].copy_from_slice(&buf[..width]);
+                u64::from_be_bytes(bytes)
+            }
+            Endian::Little => {
+                bytes[..width].copy_from_slice(&buf[..width]);
+                u64::from_le_bytes(bytes)
+            }
+        };
+
+        Ok(Some((len, width)))
+    }
+
+    fn write_length(&self, len: u64, buf: &mut Vec<u8>) -> Result<()> {
+        let width = match self.field {
+            LengthField::U8 => 1,
+            LengthField::U16 => 2,
+            LengthField::U32 => 4,
+            LengthField::U64 => 8,
+            LengthField::Varint => {
+                write_varint(len, buf);
+                return Ok(());
+            }
+        };
+
+        if width < 8 && len >> (width * 8) != 0 {
+            return Err(Error::protocol("frame length does not fit the length field"));
+        }
+
+        match self.endian {
+            Endian::Big => buf.extend_from_slice(&len.to_be_bytes()[8 - width..]),
+            Endian::Little => buf.extend_from_slice(&len.to_le_bytes()[..width]),
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_varint(buf: &[u8]) -> Result<Option<(u64, usize)>> {
+    let mut value = 0u64;
+    for (i, byte) in buf.iter().take(MAX_VARINT_LEN).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+
+    match buf.len() >= MAX_VARINT_LEN {
+        true => Err(Error::protocol("varint too long")),
+        false => Ok(None),
+    }
+}
+
+fn write_varint(mut value: u64, buf: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        match value {
+            0 => break buf.push(byte),
+            _ => buf.push(byte | 0x80),
+        }
+    }
+}
+
+impl Decoder for LengthDelimitedCodec {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let (frame, len) = match self.peek(buf)? {
+            Some((frame, len)) => (frame.to_vec(), len),
+            None => return Ok(None),
+        };
+
+        buf.drain(..len);
+        Ok(Some(frame))
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for LengthDelimitedCodec {
+    fn encode(&mut self, payload: T, buf: &mut Vec<u8>) -> Result<()> {
+        let payload = payload.as_ref();
+        if payload.len() > self.max_frame {
+            return Err(Error::protocol("frame too large"));
+        }
+
+        let len = (payload.len() as i128 - self.adjustment as i128)
+            .try_into()
+            .map_err(|_| Error::protocol("negative frame length"))?;
+
+        let start = buf.len();
+        buf.resize(start + self.offset, 0);
+        if let Err(e) = self.write_length(len, buf) {
+            // Leave nothing of this frame behind
+            buf.truncate(start);
+            return Err(e);
+        }
+
+        buf.extend_from_slice(payload);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let fields = [LengthField::U8, LengthField::U16, LengthField::U32, LengthField::U64, LengthField::Varint];
+        for field in &fields {
+            for endian in &[Endian::Big, Endian::Little] {
+                let mut codec = LengthDelimitedCodec::new().length_field(*field).endian(*endian);
+                let mut buf = Vec::new();
+                codec.encode(vec![7u8; 200], &mut buf).unwrap();
+                codec.encode(b"", &mut buf).unwrap();
+
+                assert_eq!(codec.decode(&mut buf).unwrap(), Some(vec![7u8; 200]));
+                assert_eq!(codec.decode(&mut buf).unwrap(), Some(vec![]));
+                assert!(buf.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn length_does_not_fit() {
+        let mut codec = LengthDelimitedCodec::new().length_field(LengthField::U8).header_offset(2);
+        let mut buf = vec![1, 2, 3];
+        assert!(codec.encode(vec![0u8; 300], &mut buf).is_err());
+        assert_eq!(buf, [1, 2, 3]);
+    }
+
+    #[test]
+    fn offset_and_adjustment() {
+        // One type byte, then a u16 length that includes itself
+        let mut codec = LengthDelimitedCodec::new()
+            .length_field(LengthField::U16)
+            .header_offset(1)
+            .length_adjustment(-2);
+
+        let mut buf = vec![9, 0, 5, b'a', b'b'];
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.push(b'c');
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn too_large() {
+        let mut codec = LengthDelimitedCodec::new().max_frame_size(4);
+        let mut buf = vec![0, 0, 0, 5];
+        assert!(codec.decode(&mut buf).is_err());
+
+        let mut codec = LengthDelimitedCodec::new().length_field(LengthField::U8);
+        assert!(codec.encode(vec![0u8; 256], &mut Vec::new()).is_err());
+    }
+}
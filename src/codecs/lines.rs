@@ -0,0 +1,228 @@
+use std::marker::PhantomData;
+
+use super::{Decoder, Encoder};
+use crate::memchr::memchr;
+use crate::{Error, Result};
+
+// -----------------------------------------------------------------------------
+//     - Delimiter -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delimiter {
+    /// `\n`
+    Lf,
+    /// `\r\n`. When decoding, a bare `\n` is accepted as well.
+    CrLf,
+}
+
+impl Delimiter {
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Delimiter::Lf => b"\n",
+            Delimiter::CrLf => b"\r\n",
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Lines codec -
+// -----------------------------------------------------------------------------
+/// Splits a stream into lines, without the delimiter.
+///
+/// `LinesCodec::new()` yields `String`s and fails on invalid UTF-8,
+/// `LinesCodec::raw()` yields the bytes as they are.
+///
+/// With a maximum line length set, a longer line produces one error,
+/// after which the rest of that line is discarded and decoding resumes
+/// with the next line.
+#[derive(Debug, Clone)]
+pub struct LinesCodec<T = String> {
+    delimiter: Delimiter,
+    max_length: Option<usize>,
+    // Where to resume searching for the delimiter
+    next_index: usize,
+    discarding: bool,
+    _p: PhantomData<T>,
+}
+
+impl LinesCodec<String> {
+    pub fn new() -> Self {
+        Self::with_output()
+    }
+}
+
+impl LinesCodec<Vec<u8>> {
+    pub fn raw() -> Self {
+        Self::with_output()
+    }
+}
+
+impl Default for LinesCodec<String> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> LinesCodec<T> {
+    fn with_output() -> Self {
+        Self {
+            delimiter: Delimiter::Lf,
+            max_length: None,
+            next_index: 0,
+            discarding: false,
+            _p: PhantomData,
+        }
+    }
+
+    pub fn delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// The maximum length of a line, excluding the delimiter.
+    pub fn max_length(mut self, max: usize) -> Self {
+        self.max_length = Some(max);
+        self
+    }
+
+    fn next_line(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+        loop {
+            let max = self.max_length.unwrap_or(usize::MAX);
+
+            // Don't look further than the longest line (+ "\r\n")
+            // so a long line is caught early.
+            let end = match self.discarding {
+                true => buf.len(),
+                false => buf.len().min(max.saturating_add(2)),
+            };
+            let found = memchr(&buf[self.next_index..end], b'\n').map(|i| i + self.next_index);
+
+            match (self.discarding, found) {
+                (true, Some(i)) => {
+                    buf.drain(..=i);
+                    self.next_index = 0;
+                    self.discarding = false;
+                }
+                (true, None) => {
+                    buf.clear();
+                    self.next_index = 0;
+                    return Ok(None);
+                }
+                (false, Some(i)) => {
+                    let mut line = buf.drain(..=i).collect::<Vec<_>>();
+                    self.next_index = 0;
+                    line.pop();
+                    if self.delimiter == Delimiter::CrLf && line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+
+                    if line.len() > max {
+                        return Err(Error::protocol("line too long"));
+                    }
+
+                    return Ok(Some(line));
+                }
+                (false, None) if buf.len() > max.saturating_add(1) => {
+                    self.discarding = true;
+                    return Err(Error::protocol("line too long"));
+                }
+                (false, None) => {
+                    self.next_index = buf.len();
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn last_line(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+        match self.next_line(buf)? {
+            Some(line) => Ok(Some(line)),
+            None if buf.is_empty() => Ok(None),
+            None => {
+                // The last line doesn't need a delimiter
+                self.next_index = 0;
+                Ok(Some(buf.drain(..).collect()))
+            }
+        }
+    }
+}
+
+impl Decoder for LinesCodec<String> {
+    type Item = String;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<String>> {
+        self.next_line(buf)?.map(utf8).transpose()
+    }
+
+    fn decode_eof(&mut self, buf: &mut Vec<u8>) -> Result<Option<String>> {
+        self.last_line(buf)?.map(utf8).transpose()
+    }
+}
+
+impl Decoder for LinesCodec<Vec<u8>> {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.next_line(buf)
+    }
+
+    fn decode_eof(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.last_line(buf)
+    }
+}
+
+impl<T, U: AsRef<[u8]>> Encoder<U> for LinesCodec<T> {
+    fn encode(&mut self, line: U, buf: &mut Vec<u8>) -> Result<()> {
+        let line = line.as_ref();
+        if let Some(max) = self.max_length {
+            if line.len() > max {
+                return Err(Error::protocol("line too long"));
+            }
+        }
+
+        buf.extend_from_slice(line);
+        buf.extend_from_slice(self.delimiter.as_bytes());
+        Ok(())
+    }
+}
+
+fn utf8(line: Vec<u8>) -> Result<String> {
+    String::from_utf8(line).map_err(|_| Error::protocol("line is not valid utf-8"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_lines() {
+        let mut codec = LinesCodec::new().delimiter(Delimiter::CrLf);
+        let mut buf = b"hello\r\nwor".to_vec();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some("hello".to_string()));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"ld\nlast");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some("world".to_string()));
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), Some("last".to_string()));
+    }
+
+    #[test]
+    fn discard_long_line() {
+        let mut codec = LinesCodec::raw().max_length(4);
+        let mut buf = b"too long".to_vec();
+        assert!(codec.decode(&mut buf).is_err());
+        buf.extend_from_slice(b" still\nok\n");
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(b"ok".to_vec()));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut codec = LinesCodec::new();
+        let mut buf = b"\xff\n".to_vec();
+        assert!(codec.decode(&mut buf).is_err());
+
+        let mut out = Vec::new();
+        codec.encode("hi", &mut out).unwrap();
+        assert_eq!(out, b"hi\n");
+    }
+}
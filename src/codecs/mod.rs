@@ -0,0 +1,49 @@
+//! Codecs turn a stream of bytes into frames and back.
+//!
+//! A `Decoder` is handed the bytes read so far and either returns a frame
+//! (consuming its bytes), `Ok(None)` if more data is needed, or an error if
+//! the data is invalid. An `Encoder` appends a frame to the bytes to be written.
+//!
+//! `Framed` combines a stream with a codec into a reactor yielding frames.
+use crate::Result;
+
+mod framed;
+mod length;
+mod lines;
+
+pub use framed::Framed;
+pub use length::{Endian, LengthDelimitedCodec, LengthField};
+pub use lines::{Delimiter, LinesCodec};
+
+// -----------------------------------------------------------------------------
+//     - Decoder -
+// -----------------------------------------------------------------------------
+pub trait Decoder {
+    type Item;
+
+    /// Decode a frame from the front of `buf`, removing the bytes that were used.
+    ///
+    /// * `Ok(Some(frame))`: a frame was decoded
+    /// * `Ok(None)`: not enough data yet
+    /// * `Err(_)`: the data is invalid
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Self::Item>>;
+
+    /// Called once the stream is closed.
+    /// By default this decodes what is left and treats any remaining bytes
+    /// as a truncated frame.
+    fn decode_eof(&mut self, buf: &mut Vec<u8>) -> Result<Option<Self::Item>> {
+        match self.decode(buf)? {
+            Some(frame) => Ok(Some(frame)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(crate::Error::protocol("stream closed in the middle of a frame")),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Encoder -
+// -----------------------------------------------------------------------------
+pub trait Encoder<T> {
+    /// Append the encoded `item` to `buf`.
+    fn encode(&mut self, item: T, buf: &mut Vec<u8>) -> Result<()>;
+}
@@ -0,0 +1,587 @@
+use std::collections::VecDeque;
+use std::io::{self, IoSlice, Read, Write};
+use std::io::ErrorKind::{UnexpectedEof, WouldBlock, WriteZero};
+use std::mem;
+use std::os::unix::io::AsRawFd;
+
+use crate::{BufferPool, Event, Interest, PollReactor, Reaction, Reactor, Result, System};
+
+const READ_SIZE: usize = 4096;
+// Queued buffers written with a single `writev`
+const MAX_IOVECS: usize = 64;
+// Sends up to this size are copied into buffers from the pool
+const COALESCE_SIZE: usize = 1024;
+const DEFAULT_LOW_WATERMARK: usize = 16 * 1024;
+const DEFAULT_HIGH_WATERMARK: usize = 64 * 1024;
+
+// -----------------------------------------------------------------------------
+//     - Connection event -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// This many bytes were added to the read buffer.
+    Read(usize),
+    /// The write queue reached the high watermark and has since been
+    /// flushed down to the low watermark: it's time to send more.
+    Drained,
+}
+
+// -----------------------------------------------------------------------------
+//     - Connection -
+// -----------------------------------------------------------------------------
+/// A stream with a read buffer and a write queue.
+///
+/// As a reactor this reads into the buffer on read events, yielding how
+/// much was read, and flushes the queue on write events. The buffered
+/// bytes stay until they are `consume`d. Once the buffer holds the read
+/// limit nothing more is read, leaving the peer to wait, until some of it
+/// is consumed.
+///
+/// Sent bytes that can't be written right away are queued and written as
+/// the stream accepts them, however little at a time. Queued buffers are
+/// written together with `writev`, so many small messages cost one call.
+/// Queueing is never refused, but once the queue reaches the high
+/// watermark `is_writable` returns false until it's flushed down to the
+/// low watermark, which yields `ConnectionEvent::Drained`.
+///
+/// The read buffer is taken from the `BufferPool` when there is something
+/// to read and given back once everything is consumed. Small sends are
+/// copied into buffers from the pool, several to a buffer, which go back
+/// once written, so an idle connection holds no buffers.
+///
+/// Once the peer has closed the stream an `UnexpectedEof` error is yielded
+/// and `is_closed` returns true. Anything still queued is written after
+/// that, as the peer may only have closed its side.
+pub struct Connection<T: AsRawFd> {
+    stream: PollReactor<T>,
+    read_buf: Vec<u8>,
+    read_limit: usize,
+    write_queue: VecDeque<Queued>,
+    // Written bytes of the buffer at the front of the queue
+    write_pos: usize,
+    queued: usize,
+    low_watermark: usize,
+    high_watermark: usize,
+    // Above the high watermark, and not yet back down to the low one
+    paused: bool,
+    drained: bool,
+    eof: bool,
+    closed: bool,
+}
+
+// A queued buffer, and whether it came from the pool
+struct Queued {
+    buf: Vec<u8>,
+    pooled: bool,
+}
+
+impl<T: AsRawFd + Read + Write> Connection<T> {
+    pub fn new(stream: PollReactor<T>) -> Self {
+        Self {
+            stream,
+            read_buf: Vec::new(),
+            read_limit: BufferPool::buffer_size(),
+            write_queue: VecDeque::new(),
+            write_pos: 0,
+            queued: 0,
+            low_watermark: DEFAULT_LOW_WATERMARK,
+            high_watermark: DEFAULT_HIGH_WATERMARK,
+            paused: false,
+            drained: false,
+            eof: false,
+            closed: false,
+        }
+    }
+
+    /// Stop reading once the read buffer holds `limit` bytes. Defaults to
+    /// the size of the pool's buffers; a larger buffer grows past it and
+    /// isn't given back to the pool.
+    pub fn read_limit(mut self, limit: usize) -> Self {
+        self.read_limit = limit.max(1);
+        self
+    }
+
+    /// The write queue watermarks, in bytes. Default to 16KiB and 64KiB.
+    pub fn watermarks(mut self, low: usize, high: usize) -> Self {
+        self.low_watermark = low.min(high);
+        self.high_watermark = high;
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.stream.id
+    }
+
+    pub fn get_ref(&self) -> &PollReactor<T> {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut PollReactor<T> {
+        &mut self.stream
+    }
+
+    /// The stream, and what was read but not consumed.
+    /// Anything still queued is lost.
+    pub fn into_parts(self) -> (PollReactor<T>, Vec<u8>) {
+        (self.stream, self.read_buf)
+    }
+
+    /// Bytes read but not yet consumed.
+    pub fn read_buffer(&self) -> &[u8] {
+        &self.read_buf
+    }
+
+    /// Remove the first `n` bytes of the read buffer.
+    pub fn consume(&mut self, n: usize) -> Result<()> {
+        let full = self.read_buf.len() >= self.read_limit;
+        self.read_buf.drain(..n.min(self.read_buf.len()));
+        self.release_read_buffer();
+
+        // Reading stopped at the limit: start again
+        match full && self.read_buf.len() < self.read_limit {
+            true => self.rearm(),
+            false => Ok(()),
+        }
+    }
+
+    /// The number of bytes queued but not yet written.
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    /// False from the time the write queue reaches the high watermark
+    /// until it's back down to the low watermark.
+    pub fn is_writable(&self) -> bool {
+        !self.paused
+    }
+
+    /// True once the peer has closed the stream.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Queue `data` and write as much as the stream accepts.
+    /// The rest is written on write events.
+    pub fn send(&mut self, data: impl Into<Vec<u8>>) -> Result<()> {
+        let data = data.into();
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.queued += data.len();
+        self.enqueue(data);
+        if self.queued >= self.high_watermark {
+            self.paused = true;
+        }
+
+        self.write_queued()?;
+        // Sending more is what `Drained` would ask for
+        self.drained = false;
+        self.rearm()
+    }
+
+    /// Write queued bytes until the stream would block.
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_queued()?;
+        self.rearm()
+    }
+
+    // Small sends are appended to the last pooled buffer if there's room,
+    // or copied into a new one
+    fn enqueue(&mut self, data: Vec<u8>) {
+        let small = data.len() <= COALESCE_SIZE;
+        if let Some(last) = self.write_queue.back_mut().filter(|last| last.pooled && small) {
+            if last.buf.capacity() - last.buf.len() >= data.len() {
+                last.buf.extend_from_slice(&data);
+                return;
+            }
+        }
+
+        let queued = match small && data.len() <= BufferPool::buffer_size() {
+            true => {
+                let mut buf = BufferPool::take();
+                buf.extend_from_slice(&data);
+                Queued { buf, pooled: true }
+            }
+            false => Queued { buf: data, pooled: false },
+        };
+
+        self.write_queue.push_back(queued);
+    }
+
+    fn write_queued(&mut self) -> Result<()> {
+        while !self.write_queue.is_empty() {
+            let mut slices = [IoSlice::new(&[]); MAX_IOVECS];
+            let mut bufs = self.write_queue.iter().map(|queued| &queued.buf[..]);
+            let first = bufs.next().map(|buf| &buf[self.write_pos..]);
+            let count = first.into_iter().chain(bufs)
+                .zip(slices.iter_mut())
+                .map(|(buf, slice)| *slice = IoSlice::new(buf))
+                .count();
+
+            match self.stream.write_vectored(&slices[..count]) {
+                Ok(0) => return Err(io::Error::from(WriteZero).into()),
+                Ok(n) => self.advance(n),
+                Err(ref e) if e.kind() == WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if self.paused && self.queued <= self.low_watermark {
+            self.paused = false;
+            self.drained = true;
+        }
+
+        Ok(())
+    }
+
+    // Remove `n` written bytes from the front of the queue
+    fn advance(&mut self, mut n: usize) {
+        self.queued -= n;
+        while let Some(front) = self.write_queue.front() {
+            let remaining = front.buf.len() - self.write_pos;
+            if n < remaining {
+                self.write_pos += n;
+                break;
+            }
+
+            n -= remaining;
+            self.write_pos = 0;
+            match self.write_queue.pop_front() {
+                Some(queued) if queued.pooled => BufferPool::give(queued.buf),
+                _ => {}
+            }
+        }
+    }
+
+    // Read until the stream would block or the buffer is full
+    fn fill(&mut self) -> Result<usize> {
+        let mut total = 0;
+
+        if self.read_buf.capacity() == 0 {
+            self.read_buf = BufferPool::take();
+        }
+
+        while self.read_buf.len() < self.read_limit {
+            let len = self.read_buf.len();
+            if self.read_buf.capacity() - len < READ_SIZE && self.read_buf.capacity() < self.read_limit {
+                self.read_buf.reserve(len.max(READ_SIZE));
+            }
+
+            let end = self.read_buf.capacity().min(self.read_limit);
+            self.read_buf.resize(end, 0);
+            let res = self.stream.as_mut().read(&mut self.read_buf[len..]);
+            self.read_buf.truncate(len + *res.as_ref().unwrap_or(&0));
+
+            match res {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(n) => total += n,
+                Err(ref e) if e.kind() == WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        self.release_read_buffer();
+        Ok(total)
+    }
+
+    fn release_read_buffer(&mut self) {
+        if self.read_buf.is_empty() && self.read_buf.capacity() > 0 {
+            BufferPool::give(mem::take(&mut self.read_buf));
+        }
+    }
+
+    fn rearm(&self) -> Result<()> {
+        let read = !self.eof && self.read_buf.len() < self.read_limit;
+        let write = self.queued > 0;
+
+        match (read, write) {
+            (true, true) => self.stream.rearm(Interest::ReadWrite),
+            (true, false) => self.stream.rearm(Interest::Read),
+            (false, true) => self.stream.rearm(Interest::Write),
+            (false, false) => Ok(()),
+        }
+    }
+
+    // The peer may only have shut down its side of the stream, so what's
+    // queued is still written, until it's all gone or writing fails.
+    fn flush_closed(&mut self, ev: Event) -> Result<()> {
+        if !ev.write || self.write_queue.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = self.write_queued() {
+            self.write_queue.clear();
+            self.write_pos = 0;
+            self.queued = 0;
+            return Err(e);
+        }
+
+        self.rearm()
+    }
+
+    fn next_event(&mut self, ev: Event) -> Result<Option<ConnectionEvent>> {
+        if ev.write {
+            self.write_queued()?;
+        }
+
+        let read = match ev.read && !self.eof {
+            true => self.fill()?,
+            false => 0,
+        };
+
+        if ev.read || ev.write {
+            self.rearm()?;
+        }
+
+        let event = match (read, self.drained) {
+            (0, false) => return Ok(None),
+            (0, true) => {
+                self.drained = false;
+                ConnectionEvent::Drained
+            }
+            (n, _) => ConnectionEvent::Read(n),
+        };
+
+        if self.drained || self.eof {
+            // Yield the rest without waiting for epoll
+            System::defer(Event { read: false, write: false, owner: ev.owner });
+        }
+
+        Ok(Some(event))
+    }
+}
+
+impl<T: AsRawFd + Read + Write> Reactor for Connection<T> {
+    type Input = ();
+    type Output = Result<ConnectionEvent>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(ev) if ev.owner != self.stream.id => Reaction::Event(ev),
+            Reaction::Event(ev) if self.closed => {
+                self.stream.update(&ev);
+                match self.flush_closed(ev) {
+                    Ok(()) => Reaction::Continue,
+                    Err(e) => Reaction::Value(Err(e)),
+                }
+            }
+            Reaction::Event(ev) => {
+                self.stream.update(&ev);
+                match self.next_event(ev) {
+                    Ok(Some(event)) => Reaction::Value(Ok(event)),
+                    Ok(None) if self.eof => {
+                        self.closed = true;
+                        Reaction::Value(Err(io::Error::from(UnexpectedEof).into()))
+                    }
+                    Ok(None) => Reaction::Continue,
+                    Err(e) => Reaction::Value(Err(e)),
+                }
+            }
+            _ => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::convert::TryFrom;
+    use std::os::unix::net::UnixStream as StdUnixStream;
+    use std::rc::Rc;
+    use crate::net::uds::UnixStream;
+
+    fn pair() -> (Connection<StdUnixStream>, StdUnixStream) {
+        System::builder().finish();
+        let (a, b) = StdUnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        b.set_nonblocking(true).unwrap();
+        (Connection::new(UnixStream::try_from(a).unwrap()), b)
+    }
+
+    #[test]
+    fn read_limit() {
+        let (conn, mut peer) = pair();
+        let mut conn = conn.read_limit(4);
+        peer.write_all(b"helloworld").unwrap();
+        drop(peer);
+
+        let ev = Event { read: true, write: false, owner: conn.id() };
+        let mut read = Vec::new();
+        loop {
+            match conn.react(Reaction::Event(ev)) {
+                Reaction::Value(Ok(ConnectionEvent::Read(n))) => {
+                    assert!(conn.read_buffer().len() <= 4);
+                    read.extend_from_slice(&conn.read_buffer()[..n]);
+                    conn.consume(n).unwrap();
+                }
+                Reaction::Value(Err(_)) => break,
+                r => panic!("unexpected {:?}", r),
+            }
+        }
+
+        assert_eq!(read, b"helloworld");
+        assert!(conn.is_closed());
+
+        // One buffer, lent for every read and given back when consumed
+        let stats = BufferPool::stats();
+        assert_eq!((stats.misses, stats.hits, stats.idle), (1, 2, 1));
+    }
+
+    #[test]
+    fn watermarks_and_partial_writes() {
+        let (conn, mut peer) = pair();
+        let mut conn = conn.watermarks(16 * 1024, 64 * 1024);
+
+        // More than the socket buffer holds
+        let data = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        for chunk in data.chunks(100 * 1024) {
+            conn.send(chunk).unwrap();
+        }
+        assert!(!conn.is_writable());
+        assert!(conn.queued() > 0);
+
+        let ev = Event { read: false, write: true, owner: conn.id() };
+        let mut received = Vec::new();
+        let mut drained = false;
+        let mut buf = vec![0u8; 64 * 1024];
+        while received.len() < data.len() {
+            match peer.read(&mut buf) {
+                Ok(n) => received.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == WouldBlock => {}
+                Err(e) => panic!("{}", e),
+            }
+
+            if let Reaction::Value(event) = conn.react(Reaction::Event(ev)) {
+                assert_eq!(event.unwrap(), ConnectionEvent::Drained);
+                assert!(conn.queued() <= 16 * 1024);
+                drained = true;
+            }
+        }
+
+        assert!(drained);
+        assert!(conn.is_writable());
+        assert_eq!(conn.queued(), 0);
+        assert!(received == data);
+    }
+
+    #[test]
+    fn flush_after_eof() {
+        let (mut conn, mut peer) = pair();
+        while conn.queued() == 0 {
+            conn.send(vec![b'-'; 64 * 1024]).unwrap();
+        }
+        conn.send(&b"bye"[..]).unwrap();
+        peer.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let ev = Event { read: true, write: false, owner: conn.id() };
+        assert!(matches!(conn.react(Reaction::Event(ev)), Reaction::Value(Err(_))));
+        assert!(conn.is_closed());
+
+        let ev = Event { read: false, write: true, owner: conn.id() };
+        let mut received = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        while conn.queued() > 0 {
+            if let Ok(n) = peer.read(&mut buf) {
+                received.extend_from_slice(&buf[..n]);
+            }
+            assert!(matches!(conn.react(Reaction::Event(ev)), Reaction::Continue));
+        }
+
+        while let Ok(n) = peer.read(&mut buf) {
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert!(received.ends_with(b"-bye"));
+    }
+
+    #[test]
+    fn pooled_write_buffers() {
+        let (mut conn, mut peer) = pair();
+        conn.send(vec![b'-'; 16 * 1024]).unwrap();
+        (0..10).for_each(|_| conn.send(&b"hi"[..]).unwrap());
+        assert_eq!(conn.queued(), 0);
+
+        let mut buf = vec![0u8; 32 * 1024];
+        let n = peer.read(&mut buf).unwrap();
+        assert_eq!(n, 16 * 1024 + 20);
+
+        // Only the small sends went through the pool,
+        // each in the buffer the one before gave back
+        let stats = BufferPool::stats();
+        assert_eq!((stats.misses, stats.hits), (1, 9));
+        assert_eq!((stats.returned, stats.discarded), (10, 0));
+    }
+
+    // Counts the calls to `write_vectored`
+    struct Counting(StdUnixStream, Rc<Cell<usize>>);
+
+    impl AsRawFd for Counting {
+        fn as_raw_fd(&self) -> i32 {
+            self.0.as_raw_fd()
+        }
+    }
+
+    impl Read for Counting {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Counting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+            self.1.set(self.1.get() + 1);
+            self.0.write_vectored(bufs)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    #[test]
+    fn coalesced_writes() {
+        System::builder().finish();
+        let (a, mut peer) = StdUnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        peer.set_nonblocking(true).unwrap();
+        let writes = Rc::new(Cell::new(0));
+        let stream = PollReactor::new(Counting(a, writes.clone()), Interest::Read).unwrap();
+        let mut conn = Connection::new(stream);
+
+        // Fill the socket so the messages are queued
+        while conn.queued() == 0 {
+            conn.send(vec![b'-'; 64 * 1024]).unwrap();
+        }
+
+        let messages = (0..500).map(|i| format!("message {}\n", i)).collect::<Vec<_>>();
+        messages.iter().for_each(|msg| conn.send(msg.as_bytes()).unwrap());
+
+        let mut received = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        while let Ok(n) = peer.read(&mut buf) {
+            received.extend_from_slice(&buf[..n]);
+        }
+
+        // One write event, and a `writev` per 64 queued buffers
+        writes.set(0);
+        let ev = Event { read: false, write: true, owner: conn.id() };
+        conn.react(Reaction::Event(ev));
+        assert_eq!(conn.queued(), 0);
+        assert!(writes.get() <= 501 / MAX_IOVECS + 1);
+
+        while let Ok(n) = peer.read(&mut buf) {
+            received.extend_from_slice(&buf[..n]);
+        }
+
+        let text = String::from_utf8(received).unwrap();
+        assert_eq!(text.trim_start_matches('-'), messages.concat());
+    }
+}
@@ -18,6 +18,16 @@ pub enum Error {
     Io(IoError),
     TryRecv(TryRecvError),
     Recv(RecvError),
+    /// The peer sent something that doesn't follow the protocol.
+    Protocol(String),
+    /// An HTTP message that couldn't be parsed.
+    Http(crate::http::ParseError),
+}
+
+impl Error {
+    pub(crate) fn protocol(msg: impl Into<String>) -> Self {
+        Error::Protocol(msg.into())
+    }
 }
 
 // -----------------------------------------------------------------------------
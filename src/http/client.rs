@@ -0,0 +1,527 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpStream as StdTcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use super::parse::{BodyDecoder, BodyKind, HeadParser};
+use super::{Request, Response};
+use crate::codecs::{Decoder, Encoder, Framed};
+use crate::net::tcp::TcpStream;
+use crate::{Error, Event, Reaction, Reactor, Result, System, Timer};
+
+const DEFAULT_MAX_BODY: usize = 64 * 1024 * 1024;
+
+pub type RequestId = u64;
+
+// -----------------------------------------------------------------------------
+//     - Client codec -
+// -----------------------------------------------------------------------------
+/// Encodes requests and decodes responses.
+///
+/// Interim (1xx) responses are skipped, except for `101 Switching Protocols`.
+/// A response without a length is read until the connection closes.
+#[derive(Debug)]
+pub struct ClientCodec {
+    head: HeadParser,
+    pending: Option<(Response, BodyDecoder)>,
+    head_requests: VecDeque<bool>,
+    max_body: usize,
+}
+
+impl ClientCodec {
+    pub fn new() -> Self {
+        Self {
+            head: HeadParser::default(),
+            pending: None,
+            head_requests: VecDeque::new(),
+            max_body: DEFAULT_MAX_BODY,
+        }
+    }
+
+    /// The largest response body accepted, in bytes.
+    pub fn max_body_size(mut self, max: usize) -> Self {
+        self.max_body = max;
+        self
+    }
+
+    fn complete(&mut self, mut response: Response, body: Vec<u8>) -> Response {
+        self.head_requests.pop_front();
+        response.body = body;
+        response
+    }
+}
+
+impl Default for ClientCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ClientCodec {
+    type Item = Response;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Response>> {
+        while self.pending.is_none() {
+            let (start, headers) = match self.head.parse(buf)? {
+                Some(head) => head,
+                None => return Ok(None),
+            };
+
+            let (version, status, reason) = Response::parse_start(&start)?;
+            if status < 200 && status != 101 {
+                continue;
+            }
+
+            let response = Response { status, reason, version, headers, body: Vec::new() };
+            let head_only = self.head_requests.front().copied().unwrap_or(false);
+            let kind = match head_only || response.bodiless() {
+                true => BodyKind::Empty,
+                false => BodyKind::from_headers(&response.headers, BodyKind::UntilClose)?,
+            };
+
+            self.pending = Some((response, BodyDecoder::new(kind, self.max_body)?));
+        }
+
+        let body = match self.pending.as_mut().map(|(_, body)| body.decode(buf)) {
+            Some(body) => body?,
+            None => return Ok(None),
+        };
+
+        match (body, self.pending.take()) {
+            (Some(body), Some((response, _))) => Ok(Some(self.complete(response, body))),
+            (_, pending) => {
+                self.pending = pending;
+                Ok(None)
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut Vec<u8>) -> Result<Option<Response>> {
+        if let Some(response) = self.decode(buf)? {
+            return Ok(Some(response));
+        }
+
+        match self.pending.take() {
+            Some((response, mut body)) => {
+                let body = body.decode_eof(buf)?.unwrap_or_default();
+                Ok(Some(self.complete(response, body)))
+            }
+            None if buf.is_empty() => Ok(None),
+            None => Err(Error::protocol("connection closed in the middle of a response")),
+        }
+    }
+}
+
+impl Encoder<Request> for ClientCodec {
+    fn encode(&mut self, request: Request, buf: &mut Vec<u8>) -> Result<()> {
+        self.head_requests.push_back(request.method == "HEAD");
+        request.encode(buf);
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Http client -
+// -----------------------------------------------------------------------------
+struct Connection {
+    framed: Framed<StdTcpStream, ClientCodec>,
+    // At least one response was read on this connection
+    reused: bool,
+}
+
+struct Inflight {
+    id: RequestId,
+    request: Request,
+    timer: Option<Timer>,
+    reused: bool,
+    retried: bool,
+}
+
+/// Sends requests to a single server and yields the responses,
+/// tagged with the id returned by `send`.
+///
+/// Requests are sent one at a time, in order, over one connection that
+/// is kept open as long as both sides allow it. A request is queued with
+/// `send`, or by passing it in as a `Reaction::Value`.
+///
+/// If a reused connection turns out to have been closed by the server,
+/// an idempotent request is retried once on a new connection.
+/// A request that times out fails with `ErrorKind::TimedOut` and
+/// its connection is closed.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use netlib::{Reactor, System};
+/// # use netlib::http::{HttpClient, Request};
+/// fn run(requests: impl Reactor<Input = (), Output = Request>) {
+///     System::builder().finish();
+///     let mut client = HttpClient::new("127.0.0.1:8080").unwrap().timeout(Duration::from_secs(5));
+///     client.send(Request::new("GET", "/status"));
+///
+///     let client = requests
+///         .chain(client)
+///         .map(|(id, response)| println!("{}: {:?}", id, response.map(|r| r.status)));
+///     System::start(client);
+/// }
+/// ```
+pub struct HttpClient {
+    id: u64,
+    addr: SocketAddr,
+    host: String,
+    timeout: Option<Duration>,
+    next_id: RequestId,
+    queue: VecDeque<(RequestId, Request, Option<Duration>)>,
+    inflight: Option<Inflight>,
+    connection: Option<Connection>,
+    results: VecDeque<(RequestId, Result<Response>)>,
+}
+
+impl HttpClient {
+    /// A client for the server at `authority` (`host:port`), which is also
+    /// used as the `Host` header. The connection is made on the first request.
+    pub fn new(authority: &str) -> Result<Self> {
+        let addr = authority
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "could not resolve address"))?;
+
+        let inst = Self {
+            id: System::reserve(),
+            addr,
+            host: authority.to_string(),
+            timeout: None,
+            next_id: 0,
+            queue: VecDeque::new(),
+            inflight: None,
+            connection: None,
+            results: VecDeque::new(),
+        };
+
+        Ok(inst)
+    }
+
+    /// The default timeout of a request, from when it is sent
+    /// until the whole response is read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Queue a request with the default timeout.
+    pub fn send(&mut self, request: Request) -> RequestId {
+        self.send_timeout(request, self.timeout)
+    }
+
+    /// Queue a request with its own timeout.
+    pub fn send_timeout(&mut self, request: Request, timeout: Option<Duration>) -> RequestId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queue.push_back((id, request, timeout));
+        self.send_next();
+        id
+    }
+
+    /// The number of requests waiting for a response.
+    pub fn pending(&self) -> usize {
+        self.queue.len() + self.inflight.is_some() as usize
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    fn send_next(&mut self) {
+        while self.inflight.is_none() {
+            let (id, mut request, timeout) = match self.queue.pop_front() {
+                Some(next) => next,
+                None => return,
+            };
+
+            if !request.headers.contains("host") {
+                request.headers.insert("Host", self.host.clone());
+            }
+
+            let timer = match timeout.map(|t| Timer::new(t.max(Duration::from_nanos(1)), None)).transpose() {
+                Ok(timer) => timer,
+                Err(e) => {
+                    self.push_result(id, Err(e));
+                    continue;
+                }
+            };
+
+            let inflight = Inflight { id, request, timer, reused: false, retried: false };
+            self.dispatch(inflight);
+        }
+    }
+
+    fn dispatch(&mut self, mut inflight: Inflight) {
+        if self.connection.is_none() {
+            match TcpStream::connect(self.addr) {
+                Ok(stream) => {
+                    let framed = Framed::new(stream, ClientCodec::new());
+                    self.connection = Some(Connection { framed, reused: false });
+                }
+                Err(e) => return self.push_result(inflight.id, Err(e)),
+            }
+        }
+
+        let connection = self.connection.as_mut().expect("connected above");
+        inflight.reused = connection.reused;
+        let res = connection.framed.send(inflight.request.clone());
+        self.inflight = Some(inflight);
+
+        if let Err(e) = res {
+            self.fail(e);
+        }
+    }
+
+    // The in-flight request failed along with its connection
+    fn fail(&mut self, e: Error) {
+        self.connection = None;
+        let mut inflight = match self.inflight.take() {
+            Some(inflight) => inflight,
+            None => return,
+        };
+
+        if inflight.reused && !inflight.retried && is_stale(&e) && is_idempotent(&inflight.request) {
+            inflight.retried = true;
+            return self.dispatch(inflight);
+        }
+
+        self.push_result(inflight.id, Err(e));
+        self.send_next();
+    }
+
+    fn complete(&mut self, response: Response) {
+        let inflight = match self.inflight.take() {
+            Some(inflight) => inflight,
+            None => {
+                self.connection = None;
+                return;
+            }
+        };
+
+        match response.keep_alive() && inflight.request.keep_alive() {
+            true => self.connection.iter_mut().for_each(|c| c.reused = true),
+            false => self.connection = None,
+        }
+
+        self.push_result(inflight.id, Ok(response));
+        self.send_next();
+    }
+
+    // Results are yielded one per reaction; the deferred event
+    // makes sure the next one follows.
+    fn push_result(&mut self, id: RequestId, result: Result<Response>) {
+        self.results.push_back((id, result));
+        System::defer(Event { read: false, write: false, owner: self.id });
+    }
+
+    fn next_result(&mut self) -> Reaction<(RequestId, Result<Response>)> {
+        match self.results.pop_front() {
+            Some(result) => Reaction::Value(result),
+            None => Reaction::Continue,
+        }
+    }
+}
+
+fn is_stale(e: &Error) -> bool {
+    match e {
+        Error::Io(e) => matches!(
+            e.kind(),
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe
+        ),
+        _ => false,
+    }
+}
+
+fn is_idempotent(request: &Request) -> bool {
+    matches!(request.method.as_str(), "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE")
+}
+
+impl Drop for HttpClient {
+    fn drop(&mut self) {
+        System::free(self.id);
+    }
+}
+
+impl Reactor for HttpClient {
+    type Input = Request;
+    type Output = (RequestId, Result<Response>);
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Value(request) => {
+                self.send(request);
+                self.next_result()
+            }
+            Reaction::Event(ev) if ev.owner == self.id => self.next_result(),
+            Reaction::Event(ev) => {
+                let timed_out = self
+                    .inflight
+                    .as_ref()
+                    .and_then(|i| i.timer.as_ref())
+                    .map(|t| t.reactor_id == ev.owner)
+                    .unwrap_or(false);
+
+                if timed_out {
+                    self.connection = None;
+                    if let Some(inflight) = self.inflight.take() {
+                        let e = io::Error::new(ErrorKind::TimedOut, "request timed out");
+                        self.push_result(inflight.id, Err(e.into()));
+                    }
+                    self.send_next();
+                    return self.next_result();
+                }
+
+                let connection = match &mut self.connection {
+                    Some(c) if c.framed.id() == ev.owner => c,
+                    _ => return Reaction::Event(ev),
+                };
+
+                match connection.framed.react(Reaction::Event(ev)) {
+                    Reaction::Value(Ok(response)) => self.complete(response),
+                    Reaction::Value(Err(e)) => self.fail(e),
+                    _ => {}
+                }
+
+                self.next_result()
+            }
+            Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::net::TcpListener as StdTcpListener;
+    use std::rc::Rc;
+    use std::thread;
+    use crate::http::ServerCodec;
+
+    // Passes events on to the client
+    struct Idle;
+
+    impl Reactor for Idle {
+        type Input = ();
+        type Output = Request;
+
+        fn react(&mut self, reaction: Reaction<()>) -> Reaction<Request> {
+            match reaction {
+                Reaction::Event(ev) => Reaction::Event(ev),
+                _ => Reaction::Continue,
+            }
+        }
+    }
+
+    #[test]
+    fn decode_responses() {
+        let mut codec = ClientCodec::new();
+        let mut out = Vec::new();
+        codec.encode(Request::new("HEAD", "/"), &mut out).unwrap();
+        codec.encode(Request::new("GET", "/"), &mut out).unwrap();
+        assert_eq!(&out[..18], b"HEAD / HTTP/1.1\r\n\r");
+
+        let mut buf = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n\
+                        HTTP/1.1 100 Continue\r\n\r\n\
+                        HTTP/1.0 200 OK\r\n\r\nuntil close"
+            .to_vec();
+
+        let head = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(head.body.is_empty());
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        let rest = codec.decode_eof(&mut buf).unwrap().unwrap();
+        assert_eq!(rest.body, b"until close");
+        assert!(!rest.keep_alive());
+    }
+
+    // Serves `count` requests on the first connection only
+    fn serve(count: usize) -> SocketAddr {
+        let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut codec = ServerCodec::new();
+            let mut buf = Vec::new();
+            let mut out = Vec::new();
+
+            for _ in 0..count {
+                let request = loop {
+                    if let Some(request) = codec.decode(&mut buf).unwrap() {
+                        break request;
+                    }
+                    let mut chunk = [0u8; 1024];
+                    let n = stream.read(&mut chunk).unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                };
+
+                let response = Response::new(200).header("Transfer-Encoding", "chunked").body(request.path);
+                codec.encode(response, &mut out).unwrap();
+                stream.write_all(&out).unwrap();
+                out.clear();
+            }
+
+            // Keep the connection open
+            thread::sleep(Duration::from_secs(2));
+        });
+
+        addr
+    }
+
+    #[test]
+    fn reuse_connection() {
+        System::builder().finish();
+        let addr = serve(2);
+
+        let mut client = HttpClient::new(&addr.to_string()).unwrap().timeout(Duration::from_secs(1));
+        let first = client.send(Request::new("GET", "/a"));
+        let second = client.send(Request::new("POST", "/b").body("data"));
+        assert_eq!(client.pending(), 2);
+
+        let responses = Rc::new(RefCell::new(Vec::new()));
+        let r = responses.clone();
+        let client = Idle.chain(client).map(move |(id, res)| {
+            r.borrow_mut().push((id, res.unwrap().body));
+            if r.borrow().len() == 2 {
+                System::stop();
+            }
+        });
+
+        System::start(client).unwrap();
+        assert_eq!(*responses.borrow(), vec![(first, b"/a".to_vec()), (second, b"/b".to_vec())]);
+    }
+
+    #[test]
+    fn request_timeout() {
+        System::builder().finish();
+        let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+
+        let mut client = HttpClient::new(&listener.local_addr().unwrap().to_string()).unwrap();
+        client.send_timeout(Request::new("GET", "/"), Some(Duration::from_millis(50)));
+
+        let client = Idle.chain(client).map(|(_, res)| {
+            match res {
+                Err(Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+                _ => panic!("expected a timeout"),
+            }
+            System::stop();
+        });
+
+        System::start(client).unwrap();
+    }
+
+    #[test]
+    fn ids_are_freed() {
+        System::builder().finish();
+        let id = System::reserve();
+        System::free(id);
+
+        drop(Timer::new(Duration::from_secs(1), None).unwrap());
+        drop(HttpClient::new("127.0.0.1:1").unwrap().timeout(Duration::from_secs(1)));
+        assert_eq!(System::reserve(), id);
+    }
+}
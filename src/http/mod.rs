@@ -0,0 +1,365 @@
+//! HTTP/1.1.
+//!
+//! The parser is incremental: `ServerCodec` decodes requests (and
+//! `ClientCodec` responses) as bytes arrive, so it works with
+//! `codecs::Framed`, handles pipelined messages and reads bodies framed by
+//! `Content-Length` or chunked encoding.
+//!
+//! `HttpServer` is a reactor that takes the connections accepted by a
+//! `TcpListener` and answers each request with the response from a handler:
+//!
+//! ```no_run
+//! # use netlib::System;
+//! # use netlib::net::tcp::TcpListener;
+//! # use netlib::http::{HttpServer, Response};
+//! # use netlib::Reactor;
+//! System::builder().finish();
+//! let server = TcpListener::bind("127.0.0.1:9000").unwrap()
+//!     .chain(HttpServer::new(|req| Response::new(200).body(req.path.clone())));
+//! System::start(server);
+//! ```
+//!
+//! `HttpClient` is the other side: a reactor sending requests to one
+//! server over a reused connection, yielding the responses.
+//!
+//! `SseServer` streams Server-Sent Events from a broadcast receiver.
+use std::fmt;
+
+mod client;
+mod parse;
+mod server;
+mod sse;
+
+pub use client::{ClientCodec, HttpClient, RequestId};
+pub use server::{HttpServer, ServerCodec};
+pub use sse::{SseEvent, SseServer};
+
+use crate::{Error, Result};
+
+// -----------------------------------------------------------------------------
+//     - Version -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    fn parse(s: &str) -> Result<Self> {
+        match s {
+            "HTTP/1.1" => Ok(Version::Http11),
+            "HTTP/1.0" => Ok(Version::Http10),
+            _ => Err(bad_request("unsupported http version")),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Parse error -
+// -----------------------------------------------------------------------------
+/// Why a message couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The start line and headers are over the size or count limit.
+    HeadTooLarge,
+    /// The body is over the size limit.
+    BodyTooLarge,
+    /// The message is malformed or uses something that isn't supported.
+    BadRequest(String),
+}
+
+impl ParseError {
+    /// The status to answer a request with.
+    pub fn status(&self) -> u16 {
+        match self {
+            ParseError::HeadTooLarge => 431,
+            ParseError::BodyTooLarge => 413,
+            ParseError::BadRequest(_) => 400,
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Http(e)
+    }
+}
+
+fn bad_request(msg: &str) -> Error {
+    Error::Http(ParseError::BadRequest(msg.to_string()))
+}
+
+// -----------------------------------------------------------------------------
+//     - Headers -
+// -----------------------------------------------------------------------------
+/// Header fields in the order they were added.
+/// Names are compared case insensitively.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers(Vec<(String, String)>);
+
+impl Headers {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// True if `name` is a comma separated list containing `token`.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|v| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.push((name.into(), value.into()));
+    }
+
+    /// Replace every value of `name` with `value`.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.0.push((name, value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn write(&self, buf: &mut Vec<u8>) {
+        for (name, value) in &self.0 {
+            buf.extend_from_slice(name.as_bytes());
+            buf.extend_from_slice(b": ");
+            buf.extend_from_slice(value.as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
+    }
+}
+
+/// Whether the connection stays open after a message with these headers.
+fn keep_alive(version: Version, headers: &Headers) -> bool {
+    match version {
+        Version::Http11 => !headers.has_token("connection", "close"),
+        Version::Http10 => headers.has_token("connection", "keep-alive"),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Request -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// The request target, e.g. `/index.html?q=1`.
+    pub path: String,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            method: method.into(),
+            path: path.into(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// True unless the client asked for the connection to be closed
+    /// (or, for HTTP/1.0, didn't ask for it to be kept open).
+    pub fn keep_alive(&self) -> bool {
+        keep_alive(self.version, &self.headers)
+    }
+
+    fn parse_start(line: &str) -> Result<(String, String, Version)> {
+        let mut parts = line.split(' ');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(path), Some(version), None) if !method.is_empty() && !path.is_empty() => {
+                Ok((method.to_string(), path.to_string(), Version::parse(version)?))
+            }
+            _ => Err(bad_request("malformed request line")),
+        }
+    }
+
+    /// Write the request line, headers and body, adding `Content-Length`
+    /// if there is a body and neither a length nor chunked encoding.
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let chunked = self.headers.has_token("transfer-encoding", "chunked");
+        let expects_body = matches!(self.method.as_str(), "POST" | "PUT" | "PATCH");
+
+        buf.extend_from_slice(format!("{} {} {}\r\n", self.method, self.path, self.version).as_bytes());
+        self.headers.write(buf);
+        if !chunked && (expects_body || !self.body.is_empty()) && !self.headers.contains("content-length") {
+            buf.extend_from_slice(format!("Content-Length: {}\r\n", self.body.len()).as_bytes());
+        }
+        buf.extend_from_slice(b"\r\n");
+
+        match chunked {
+            true => parse::write_chunked(&self.body, buf),
+            false => buf.extend_from_slice(&self.body),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Response -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// A response with the standard reason phrase for `status`.
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            reason: reason(status).to_string(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn keep_alive(&self) -> bool {
+        keep_alive(self.version, &self.headers)
+    }
+
+    fn parse_start(line: &str) -> Result<(Version, u16, String)> {
+        let mut parts = line.splitn(3, ' ');
+        let version = Version::parse(parts.next().unwrap_or_default())?;
+        let status = parts
+            .next()
+            .filter(|s| s.len() == 3)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| bad_request("malformed status line"))?;
+        let reason = parts.next().unwrap_or_default().to_string();
+        Ok((version, status, reason))
+    }
+
+    /// 1xx, 204 and 304 responses never have a body.
+    fn bodiless(&self) -> bool {
+        self.status < 200 || self.status == 204 || self.status == 304
+    }
+
+    /// Write the status line and headers, adding `Content-Length` if
+    /// there is neither a length nor chunked encoding. With `head_only`
+    /// the body is left out, as in a reply to `HEAD`.
+    fn encode(&self, head_only: bool, buf: &mut Vec<u8>) {
+        let chunked = self.headers.has_token("transfer-encoding", "chunked");
+
+        buf.extend_from_slice(format!("{} {} {}\r\n", self.version, self.status, self.reason).as_bytes());
+        self.headers.write(buf);
+        if !chunked && !self.bodiless() && !self.headers.contains("content-length") {
+            buf.extend_from_slice(format!("Content-Length: {}\r\n", self.body.len()).as_bytes());
+        }
+        buf.extend_from_slice(b"\r\n");
+
+        match (head_only || self.bodiless(), chunked) {
+            (true, _) => {}
+            (false, true) => parse::write_chunked(&self.body, buf),
+            (false, false) => buf.extend_from_slice(&self.body),
+        }
+    }
+}
+
+/// The standard reason phrase of a status code.
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,308 @@
+use super::{bad_request, Headers, ParseError};
+use crate::memchr::memchr;
+use crate::Result;
+
+pub(crate) const MAX_HEAD: usize = 64 * 1024;
+pub(crate) const MAX_HEADERS: usize = 100;
+
+// -----------------------------------------------------------------------------
+//     - Head -
+// -----------------------------------------------------------------------------
+/// Finds the end of a message head (request or status line and headers).
+#[derive(Debug, Default)]
+pub(crate) struct HeadParser {
+    // Where to resume looking for the empty line
+    scanned: usize,
+}
+
+impl HeadParser {
+    /// Parse and remove the head from `buf`, returning the
+    /// start line and headers once the whole head is buffered.
+    pub(crate) fn parse(&mut self, buf: &mut Vec<u8>) -> Result<Option<(String, Headers)>> {
+        let end = loop {
+            let nl = match memchr(&buf[self.scanned..], b'\n') {
+                Some(i) => self.scanned + i,
+                None if buf.len() > MAX_HEAD => return Err(ParseError::HeadTooLarge.into()),
+                None => {
+                    self.scanned = buf.len();
+                    return Ok(None);
+                }
+            };
+
+            let line_start = self.scanned;
+            self.scanned = nl + 1;
+
+            // Skip empty lines before the start line
+            if nl == 0 || (nl == 1 && buf[0] == b'\r') {
+                buf.drain(..=nl);
+                self.scanned = 0;
+                continue;
+            }
+
+            let line = trim_cr(&buf[line_start..nl]);
+            if line.is_empty() {
+                break nl + 1;
+            }
+        };
+
+        self.scanned = 0;
+        let head = buf.drain(..end).collect::<Vec<_>>();
+        let head = std::str::from_utf8(&head).map_err(|_| bad_request("message head is not valid utf-8"))?;
+
+        let mut lines = head.lines();
+        let start = lines.next().unwrap_or_default().to_string();
+        let mut headers = Headers::new();
+
+        for line in lines.filter(|l| !l.is_empty()) {
+            if headers.len() == MAX_HEADERS {
+                return Err(ParseError::HeadTooLarge.into());
+            }
+
+            let colon = line.find(':').ok_or_else(|| bad_request("malformed header"))?;
+            let (name, value) = (&line[..colon], &line[colon + 1..]);
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(bad_request("malformed header name"));
+            }
+            headers.append(name, value.trim());
+        }
+
+        Ok(Some((start, headers)))
+    }
+}
+
+fn trim_cr(line: &[u8]) -> &[u8] {
+    match line.last() {
+        Some(b'\r') => &line[..line.len() - 1],
+        _ => line,
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Body -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum BodyKind {
+    Empty,
+    Length(usize),
+    Chunked,
+    /// Everything until the connection closes (responses only).
+    UntilClose,
+}
+
+impl BodyKind {
+    /// The body framing from the headers, or `default` if neither
+    /// `Transfer-Encoding` nor `Content-Length` is present.
+    pub(crate) fn from_headers(headers: &Headers, default: BodyKind) -> Result<Self> {
+        if let Some(te) = headers.get("transfer-encoding") {
+            let last = te.rsplit(',').next().unwrap_or_default().trim();
+            return match last.eq_ignore_ascii_case("chunked") {
+                true => Ok(BodyKind::Chunked),
+                false => Err(bad_request("unsupported transfer encoding")),
+            };
+        }
+
+        let mut lengths = headers.get_all("content-length").flat_map(|v| v.split(','));
+        match lengths.next() {
+            None => Ok(default),
+            Some(len) => {
+                let len = len.trim();
+                if lengths.any(|other| other.trim() != len) {
+                    return Err(bad_request("conflicting content length"));
+                }
+
+                len.parse()
+                    .map(BodyKind::Length)
+                    .map_err(|_| bad_request("invalid content length"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Chunk {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailers,
+}
+
+/// Reads a message body, removing what it uses from the buffer.
+#[derive(Debug)]
+pub(crate) struct BodyDecoder {
+    kind: BodyKind,
+    chunk: Chunk,
+    body: Vec<u8>,
+    max: usize,
+}
+
+impl BodyDecoder {
+    pub(crate) fn new(kind: BodyKind, max: usize) -> Result<Self> {
+        if let BodyKind::Length(len) = kind {
+            if len > max {
+                return Err(ParseError::BodyTooLarge.into());
+            }
+        }
+
+        let inst = Self {
+            kind,
+            chunk: Chunk::Size,
+            body: Vec::new(),
+            max,
+        };
+
+        Ok(inst)
+    }
+
+    /// Returns the body once complete.
+    pub(crate) fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+        match self.kind {
+            BodyKind::Empty => Ok(Some(Vec::new())),
+            BodyKind::Length(len) => {
+                let missing = len - self.body.len();
+                let n = missing.min(buf.len());
+                self.body.extend(buf.drain(..n));
+                match self.body.len() == len {
+                    true => Ok(Some(std::mem::take(&mut self.body))),
+                    false => Ok(None),
+                }
+            }
+            BodyKind::UntilClose => {
+                self.body.append(buf);
+                match self.body.len() > self.max {
+                    true => Err(ParseError::BodyTooLarge.into()),
+                    false => Ok(None),
+                }
+            }
+            BodyKind::Chunked => self.decode_chunked(buf),
+        }
+    }
+
+    /// The connection closed: a body read until close is now complete.
+    pub(crate) fn decode_eof(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+        match self.kind {
+            BodyKind::UntilClose => {
+                self.decode(buf)?;
+                Ok(Some(std::mem::take(&mut self.body)))
+            }
+            _ => match self.decode(buf)? {
+                Some(body) => Ok(Some(body)),
+                None => Err(bad_request("connection closed in the middle of a body")),
+            },
+        }
+    }
+
+    fn decode_chunked(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+        loop {
+            match self.chunk {
+                Chunk::Size => {
+                    let line = match take_line(buf)? {
+                        Some(line) => line,
+                        None => return Ok(None),
+                    };
+
+                    let size = line.split(|b| *b == b';').next().unwrap_or_default();
+                    let size = std::str::from_utf8(size)
+                        .ok()
+                        .and_then(|s| usize::from_str_radix(s.trim(), 16).ok())
+                        .ok_or_else(|| bad_request("invalid chunk size"))?;
+
+                    if self.body.len().saturating_add(size) > self.max {
+                        return Err(ParseError::BodyTooLarge.into());
+                    }
+
+                    self.chunk = match size {
+                        0 => Chunk::Trailers,
+                        size => Chunk::Data(size),
+                    };
+                }
+                Chunk::Data(remaining) => {
+                    if buf.is_empty() {
+                        return Ok(None);
+                    }
+
+                    let n = remaining.min(buf.len());
+                    self.body.extend(buf.drain(..n));
+                    self.chunk = match remaining - n {
+                        0 => Chunk::DataEnd,
+                        remaining => Chunk::Data(remaining),
+                    };
+                }
+                Chunk::DataEnd => match take_line(buf)? {
+                    Some(line) if line.is_empty() => self.chunk = Chunk::Size,
+                    Some(_) => return Err(bad_request("missing CRLF after chunk")),
+                    None => return Ok(None),
+                },
+                // Trailers are read and discarded
+                Chunk::Trailers => match take_line(buf)? {
+                    Some(line) if line.is_empty() => {
+                        self.chunk = Chunk::Size;
+                        return Ok(Some(std::mem::take(&mut self.body)));
+                    }
+                    Some(_) => {}
+                    None => return Ok(None),
+                },
+            }
+        }
+    }
+}
+
+fn take_line(buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+    match memchr(buf, b'\n') {
+        Some(i) => {
+            let mut line = buf.drain(..=i).collect::<Vec<_>>();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            Ok(Some(line))
+        }
+        None if buf.len() > MAX_HEAD => Err(bad_request("line too long")),
+        None => Ok(None),
+    }
+}
+
+/// Append `body` as a single chunk followed by the last chunk.
+pub(crate) fn write_chunked(body: &[u8], buf: &mut Vec<u8>) {
+    if !body.is_empty() {
+        buf.extend_from_slice(format!("{:x}\r\n", body.len()).as_bytes());
+        buf.extend_from_slice(body);
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf.extend_from_slice(b"0\r\n\r\n");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn head_in_pieces() {
+        let mut parser = HeadParser::default();
+        let mut buf = b"\r\nGET / HTTP/1.1\r\nHost: a\r\n".to_vec();
+        assert!(parser.parse(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(b"X-Thing:  b \r\n\r\nrest");
+        let (start, headers) = parser.parse(&mut buf).unwrap().unwrap();
+        assert_eq!(start, "GET / HTTP/1.1");
+        assert_eq!(headers.get("host"), Some("a"));
+        assert_eq!(headers.get("x-thing"), Some("b"));
+        assert_eq!(buf, b"rest");
+    }
+
+    #[test]
+    fn chunked_body() {
+        let mut body = BodyDecoder::new(BodyKind::Chunked, 1024).unwrap();
+        let mut buf = b"5;ext=1\r\nhello\r\n6\r\n wor".to_vec();
+        assert_eq!(body.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"ld\r\n0\r\nTrailer: x\r\n\r\nnext");
+        assert_eq!(body.decode(&mut buf).unwrap(), Some(b"hello world".to_vec()));
+        assert_eq!(buf, b"next");
+    }
+
+    #[test]
+    fn conflicting_lengths() {
+        let mut headers = Headers::new();
+        headers.append("Content-Length", "3");
+        headers.append("Content-Length", "4");
+        assert!(BodyKind::from_headers(&headers, BodyKind::Empty).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::TcpStream as StdTcpStream;
+
+use super::parse::{BodyDecoder, BodyKind, HeadParser};
+use super::{Request, Response, Version};
+use crate::codecs::{Decoder, Encoder, Framed};
+use crate::net::tcp::{Accepted, ConnectionSlot, TcpStream};
+use crate::{Error, Interest, Reaction, Reactor, Result};
+
+const DEFAULT_MAX_BODY: usize = 8 * 1024 * 1024;
+
+// -----------------------------------------------------------------------------
+//     - Server codec -
+// -----------------------------------------------------------------------------
+/// Decodes requests and encodes responses.
+///
+/// Responses have to be encoded in the order the requests were decoded:
+/// the codec remembers which requests were `HEAD` so their responses
+/// are sent without a body.
+#[derive(Debug)]
+pub struct ServerCodec {
+    head: HeadParser,
+    pending: Option<(Request, BodyDecoder)>,
+    head_requests: VecDeque<bool>,
+    max_body: usize,
+}
+
+impl ServerCodec {
+    pub fn new() -> Self {
+        Self {
+            head: HeadParser::default(),
+            pending: None,
+            head_requests: VecDeque::new(),
+            max_body: DEFAULT_MAX_BODY,
+        }
+    }
+
+    /// The largest request body accepted, in bytes.
+    pub fn max_body_size(mut self, max: usize) -> Self {
+        self.max_body = max;
+        self
+    }
+}
+
+impl Default for ServerCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for ServerCodec {
+    type Item = Request;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Request>> {
+        if self.pending.is_none() {
+            let (start, headers) = match self.head.parse(buf)? {
+                Some(head) => head,
+                None => return Ok(None),
+            };
+
+            let (method, path, version) = Request::parse_start(&start)?;
+            let body = BodyDecoder::new(BodyKind::from_headers(&headers, BodyKind::Empty)?, self.max_body)?;
+            let request = Request { method, path, version, headers, body: Vec::new() };
+            self.pending = Some((request, body));
+        }
+
+        let body = match self.pending.as_mut().map(|(_, body)| body.decode(buf)) {
+            Some(body) => body?,
+            None => return Ok(None),
+        };
+
+        match (body, self.pending.take()) {
+            (Some(body), Some((mut request, _))) => {
+                request.body = body;
+                self.head_requests.push_back(request.method == "HEAD");
+                Ok(Some(request))
+            }
+            (None, pending) => {
+                self.pending = pending;
+                Ok(None)
+            }
+            (Some(_), None) => unreachable!(),
+        }
+    }
+}
+
+impl Encoder<Response> for ServerCodec {
+    fn encode(&mut self, response: Response, buf: &mut Vec<u8>) -> Result<()> {
+        let head_only = self.head_requests.pop_front().unwrap_or(false);
+        response.encode(head_only, buf);
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Http server -
+// -----------------------------------------------------------------------------
+struct Connection {
+    framed: Framed<StdTcpStream, ServerCodec>,
+    // No more requests are handled, the connection is closed
+    // once the write buffer is empty.
+    closing: bool,
+    _slot: Option<ConnectionSlot>,
+}
+
+/// Serves the connections accepted by a `TcpListener`, answering every
+/// request with the response returned by the handler.
+///
+/// Connections are kept open between requests unless the client or the
+/// response asks for `Connection: close`. Pipelined requests are answered
+/// in order. A request that can't be parsed is answered with the status
+/// of its `ParseError`, e.g. `400 Bad Request`, and the connection is
+/// closed once the response is written.
+///
+/// A connection that can't be accepted or added yields its error, and
+/// the server carries on. Events that don't belong to a connection are
+/// passed on.
+pub struct HttpServer<F> {
+    handler: F,
+    max_body: usize,
+    connections: HashMap<u64, Connection>,
+}
+
+impl<F> HttpServer<F>
+where
+    F: FnMut(Request) -> Response,
+{
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            max_body: DEFAULT_MAX_BODY,
+            connections: HashMap::new(),
+        }
+    }
+
+    /// The largest request body accepted, in bytes.
+    /// Larger requests are answered with `413 Payload Too Large`.
+    pub fn max_body_size(mut self, max: usize) -> Self {
+        self.max_body = max;
+        self
+    }
+
+    /// The number of open connections.
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    fn add(&mut self, stream: StdTcpStream, slot: Option<ConnectionSlot>) -> Result<()> {
+        let stream = TcpStream::new(stream, Interest::Read)?;
+        let framed = Framed::new(stream, ServerCodec::new().max_body_size(self.max_body));
+        self.connections.insert(framed.id(), Connection { framed, closing: false, _slot: slot });
+        Ok(())
+    }
+
+    fn respond(&mut self, id: u64, request: Result<Request>) -> Result<()> {
+        let con = match self.connections.get_mut(&id) {
+            Some(con) => con,
+            None => return Ok(()),
+        };
+
+        let response = match request {
+            Ok(_) | Err(Error::Http(_)) if con.closing => return Ok(()),
+            Ok(request) => {
+                let keep_alive = request.keep_alive();
+                let version = request.version;
+                let mut response = (self.handler)(request);
+
+                if !keep_alive {
+                    response.headers.insert("Connection", "close");
+                } else if version == Version::Http10 && !response.headers.contains("connection") {
+                    response.headers.insert("Connection", "keep-alive");
+                }
+                response
+            }
+            Err(Error::Http(e)) => Response::new(e.status()).header("Connection", "close"),
+            Err(e) => return Err(e),
+        };
+
+        if !response.keep_alive() {
+            con.closing = true;
+        }
+
+        con.framed.send(response)
+    }
+}
+
+impl<F> Reactor for HttpServer<F>
+where
+    F: FnMut(Request) -> Response,
+{
+    type Input = Result<Accepted>;
+    type Output = Error;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Value(Ok((stream, _, slot))) => match self.add(stream, slot) {
+                Ok(()) => Reaction::Continue,
+                Err(e) => Reaction::Value(e),
+            },
+            Reaction::Value(Err(e)) => Reaction::Value(e),
+            Reaction::Event(ev) => {
+                let con = match self.connections.get_mut(&ev.owner) {
+                    Some(con) => con,
+                    None => return Reaction::Event(ev),
+                };
+
+                let res = match con.framed.react(Reaction::Event(ev)) {
+                    Reaction::Value(request) => self.respond(ev.owner, request),
+                    _ => Ok(()),
+                };
+
+                let done = match self.connections.get(&ev.owner) {
+                    Some(con) => (con.framed.is_closed() || con.closing) && con.framed.write_buffer().is_empty(),
+                    None => false,
+                };
+
+                if res.is_err() || done {
+                    self.connections.remove(&ev.owner);
+                }
+
+                Reaction::Continue
+            }
+            Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::sync::mpsc;
+    use std::thread;
+    use crate::net::tcp::TcpListener;
+    use crate::System;
+
+    #[test]
+    fn decode_pipelined() {
+        let mut codec = ServerCodec::new();
+        let mut buf = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nPOST /b HTTP/1.0\r\nContent-Length: 5\r\n\r\nhel".to_vec();
+
+        let first = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!((first.method.as_str(), first.path.as_str()), ("GET", "/a"));
+        assert!(first.keep_alive());
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"lo");
+        let second = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(second.body, b"hello");
+        assert_eq!(second.version, Version::Http10);
+        assert!(!second.keep_alive());
+        assert!(buf.is_empty());
+
+        let mut buf = b"GET / HTTP/2\r\n\r\n".to_vec();
+        assert!(ServerCodec::new().decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn parse_error_status() {
+        let status = |mut codec: ServerCodec, buf: &[u8]| match codec.decode(&mut buf.to_vec()) {
+            Err(Error::Http(e)) => e.status(),
+            res => panic!("unexpected {:?}", res),
+        };
+
+        assert_eq!(status(ServerCodec::new(), &[b'a'; 70 * 1024]), 431);
+        let post = b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n";
+        assert_eq!(status(ServerCodec::new().max_body_size(10), post), 413);
+        assert_eq!(status(ServerCodec::new(), b"GET /\r\n\r\n"), 400);
+    }
+
+    #[test]
+    fn encode_response() {
+        let mut codec = ServerCodec::new();
+        let mut buf = b"HEAD / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n".to_vec();
+        codec.decode(&mut buf).unwrap();
+        codec.decode(&mut buf).unwrap();
+
+        let mut out = Vec::new();
+        codec.encode(Response::new(200).body("hi"), &mut out).unwrap();
+        assert_eq!(out, b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n");
+
+        out.clear();
+        let chunked = Response::new(200).header("Transfer-Encoding", "chunked").body("hi");
+        codec.encode(chunked, &mut out).unwrap();
+        assert_eq!(out, &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn serve_keep_alive() {
+        let (tx, rx) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            System::builder().finish();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+
+            let server = listener.chain(HttpServer::new(|req| {
+                if req.path == "/stop" {
+                    System::stop();
+                }
+                let mut body = req.path.into_bytes();
+                body.extend(req.body);
+                Response::new(200).body(body)
+            }));
+
+            System::start(server);
+        });
+
+        let mut client = StdTcpStream::connect(rx.recv().unwrap()).unwrap();
+        client
+            .write_all(
+                b"GET /a HTTP/1.1\r\n\r\n\
+                  POST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nxyz\r\n0\r\n\r\n\
+                  GET /stop HTTP/1.1\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n/a\
+             HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n/bxyz\
+             HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 5\r\n\r\n/stop"
+        );
+    }
+
+    #[test]
+    fn reject_large_body() {
+        let (tx, rx) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            System::builder().finish();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+
+            let server = listener.chain(HttpServer::new(|_| {
+                System::stop();
+                Response::new(200)
+            }).max_body_size(10));
+
+            System::start(server);
+        });
+
+        let addr = rx.recv().unwrap();
+        let mut client = StdTcpStream::connect(addr).unwrap();
+        client.write_all(b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        let mut client = StdTcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+    }
+}
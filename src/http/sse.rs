@@ -0,0 +1,418 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::TcpStream as StdTcpStream;
+use std::time::Duration;
+
+use super::{Headers, Request, Response, ServerCodec};
+use crate::broadcast::Receiver;
+use crate::codecs::{Decoder, Encoder, Framed};
+use crate::net::tcp::{Accepted, ConnectionSlot, TcpStream};
+use crate::{Error, Interest, Reaction, Reactor, Result, Timer};
+
+const DEFAULT_REPLAY: usize = 128;
+const DEFAULT_MAX_BUFFERED: usize = 1024 * 1024;
+
+// -----------------------------------------------------------------------------
+//     - Event -
+// -----------------------------------------------------------------------------
+/// An event for an `EventSource`.
+///
+/// ```
+/// # use netlib::http::SseEvent;
+/// let event = SseEvent::new("line 1\nline 2").event("update").id("7");
+/// assert_eq!(event.to_string(), "event: update\ndata: line 1\ndata: line 2\nid: 7\n\n");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+    pub id: Option<String>,
+    pub retry: Option<Duration>,
+}
+
+impl SseEvent {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The event type, `message` if not set.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// The id, sent back by a reconnecting client as `Last-Event-ID`.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// How long the client waits before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+impl std::fmt::Display for SseEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // A line break would end the field early
+        let single_line = |s: &str| s.replace(|c| c == '\r' || c == '\n', "");
+
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        for line in self.data.split("\r\n").flat_map(|l| l.split(|c| c == '\r' || c == '\n')) {
+            writeln!(f, "data: {}", line)?;
+        }
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", single_line(id).replace('\0', ""))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        writeln!(f)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Codec -
+// -----------------------------------------------------------------------------
+enum Out<'a> {
+    Head(&'a Headers),
+    Event(&'a SseEvent),
+    Comment(&'a str),
+}
+
+// Requests in, an event stream out
+struct SseCodec(ServerCodec);
+
+impl Decoder for SseCodec {
+    type Item = Request;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Request>> {
+        self.0.decode(buf)
+    }
+}
+
+impl Encoder<Response> for SseCodec {
+    fn encode(&mut self, response: Response, buf: &mut Vec<u8>) -> Result<()> {
+        self.0.encode(response, buf)
+    }
+}
+
+impl Encoder<Out<'_>> for SseCodec {
+    fn encode(&mut self, out: Out, buf: &mut Vec<u8>) -> Result<()> {
+        match out {
+            // No length: the body lasts as long as the connection
+            Out::Head(headers) => {
+                buf.extend_from_slice(b"HTTP/1.1 200 OK\r\n");
+                buf.extend_from_slice(b"Content-Type: text/event-stream\r\nCache-Control: no-cache\r\n");
+                headers.write(buf);
+                buf.extend_from_slice(b"\r\n");
+            }
+            Out::Event(event) => buf.extend_from_slice(event.to_string().as_bytes()),
+            Out::Comment(comment) => buf.extend_from_slice(format!(": {}\n\n", comment).as_bytes()),
+        }
+        Ok(())
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Sse server -
+// -----------------------------------------------------------------------------
+struct Client {
+    framed: Framed<StdTcpStream, SseCodec>,
+    streaming: bool,
+    closing: bool,
+    _slot: Option<ConnectionSlot>,
+}
+
+/// Serves Server-Sent Events to the connections of a `TcpListener`.
+///
+/// Every `GET` request starts an event stream; every event received from
+/// the broadcast receiver is sent to all streams. The last events are kept
+/// so a client reconnecting with `Last-Event-ID` gets the events after that
+/// id (or all kept events if the id is no longer known).
+///
+/// A client that doesn't keep up, leaving more than `max_buffered` bytes
+/// unwritten, is disconnected.
+///
+/// A connection that can't be accepted or added yields its error, as does
+/// a failure to receive from the broadcast receiver, and the server
+/// carries on.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use netlib::{Reactor, System};
+/// # use netlib::broadcast::Broadcaster;
+/// # use netlib::net::tcp::TcpListener;
+/// # use netlib::http::{SseEvent, SseServer};
+/// System::builder().finish();
+/// let mut updates = Broadcaster::<SseEvent>::new(1024);
+/// let sse = SseServer::new(updates.receiver().unwrap())
+///     .keepalive(Duration::from_secs(15))
+///     .unwrap();
+/// // ... hand `updates` to whatever produces events
+/// System::start(TcpListener::bind("127.0.0.1:9000").unwrap().chain(sse));
+/// ```
+pub struct SseServer {
+    receiver: Receiver<SseEvent>,
+    keepalive: Option<Timer>,
+    headers: Headers,
+    replay: VecDeque<SseEvent>,
+    replay_size: usize,
+    max_buffered: usize,
+    clients: HashMap<u64, Client>,
+}
+
+impl SseServer {
+    pub fn new(receiver: Receiver<SseEvent>) -> Self {
+        Self {
+            receiver,
+            keepalive: None,
+            headers: Headers::new(),
+            replay: VecDeque::new(),
+            replay_size: DEFAULT_REPLAY,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Send a comment to every stream at this interval, so idle
+    /// connections aren't closed by proxies.
+    pub fn keepalive(mut self, interval: Duration) -> Result<Self> {
+        self.keepalive = Some(Timer::new(interval, Some(interval))?);
+        Ok(self)
+    }
+
+    /// The number of events kept for replay.
+    pub fn replay_size(mut self, size: usize) -> Self {
+        self.replay_size = size;
+        self.replay.truncate(size);
+        self
+    }
+
+    /// The most bytes buffered for a client before it's disconnected.
+    pub fn max_buffered(mut self, max: usize) -> Self {
+        self.max_buffered = max;
+        self
+    }
+
+    /// Add a header to the stream response, e.g. for CORS.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// The number of connected clients.
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    fn add(&mut self, stream: StdTcpStream, slot: Option<ConnectionSlot>) -> Result<()> {
+        let stream = TcpStream::new(stream, Interest::Read)?;
+        let framed = Framed::new(stream, SseCodec(ServerCodec::new()));
+        let client = Client { framed, streaming: false, closing: false, _slot: slot };
+        self.clients.insert(client.framed.id(), client);
+        Ok(())
+    }
+
+    fn publish(&mut self, event: SseEvent) {
+        let max = self.max_buffered;
+        self.clients.retain(|_, client| {
+            !client.streaming || (client.framed.send(Out::Event(&event)).is_ok() && client.framed.write_buffer().len() <= max)
+        });
+
+        if self.replay_size > 0 {
+            if self.replay.len() == self.replay_size {
+                self.replay.pop_front();
+            }
+            self.replay.push_back(event);
+        }
+    }
+
+    fn ping(&mut self) {
+        if let Some(timer) = &mut self.keepalive {
+            let _ = timer.consume_event();
+        }
+
+        let max = self.max_buffered;
+        self.clients.retain(|_, client| {
+            !client.streaming || (client.framed.send(Out::Comment("keepalive")).is_ok() && client.framed.write_buffer().len() <= max)
+        });
+    }
+
+    fn request(&mut self, id: u64, request: Result<Request>) -> Result<()> {
+        let client = match self.clients.get_mut(&id) {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+
+        match request {
+            Ok(_) if client.streaming || client.closing => Ok(()),
+            Ok(request) if request.method == "GET" => {
+                client.streaming = true;
+                client.framed.send(Out::Head(&self.headers))?;
+
+                let last_id = match request.headers.get("last-event-id") {
+                    Some(id) => id,
+                    None => return Ok(()),
+                };
+
+                let after = self.replay.iter().position(|e| e.id.as_deref() == Some(last_id));
+                let missed = self.replay.iter().skip(after.map(|i| i + 1).unwrap_or(0));
+                for event in missed {
+                    client.framed.send(Out::Event(event))?;
+                }
+                Ok(())
+            }
+            Ok(_) => {
+                client.closing = true;
+                client.framed.send(Response::new(405).header("Allow", "GET").header("Connection", "close"))
+            }
+            Err(Error::Http(e)) if !client.streaming && !client.closing => {
+                client.closing = true;
+                client.framed.send(Response::new(e.status()).header("Connection", "close"))
+            }
+            Err(Error::Http(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Reactor for SseServer {
+    type Input = Result<Accepted>;
+    type Output = Error;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        let ev = match reaction {
+            Reaction::Value(Ok((stream, _, slot))) => {
+                return match self.add(stream, slot) {
+                    Ok(()) => Reaction::Continue,
+                    Err(e) => Reaction::Value(e),
+                };
+            }
+            Reaction::Value(Err(e)) => return Reaction::Value(e),
+            Reaction::Event(ev) => ev,
+            Reaction::Continue => return Reaction::Continue,
+        };
+
+        let ev = match self.receiver.react(Reaction::Event(ev)) {
+            Reaction::Event(ev) => ev,
+            Reaction::Value(Ok(event)) => {
+                self.publish(event);
+                return Reaction::Continue;
+            }
+            Reaction::Value(Err(e)) => return Reaction::Value(e),
+            Reaction::Continue => return Reaction::Continue,
+        };
+
+        if self.keepalive.as_ref().map(|t| t.reactor_id) == Some(ev.owner) {
+            self.ping();
+            return Reaction::Continue;
+        }
+
+        let client = match self.clients.get_mut(&ev.owner) {
+            Some(client) => client,
+            None => return Reaction::Event(ev),
+        };
+
+        let res = match client.framed.react(Reaction::Event(ev)) {
+            Reaction::Value(request) => self.request(ev.owner, request),
+            _ => Ok(()),
+        };
+
+        let done = match self.clients.get(&ev.owner) {
+            Some(client) => {
+                (client.framed.is_closed() || client.closing) && client.framed.write_buffer().is_empty()
+                    || client.framed.write_buffer().len() > self.max_buffered
+            }
+            None => false,
+        };
+
+        if res.is_err() || done {
+            self.clients.remove(&ev.owner);
+        }
+
+        Reaction::Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::sync::mpsc;
+    use std::thread;
+    use crate::broadcast::Broadcaster;
+    use crate::net::tcp::TcpListener;
+    use crate::System;
+
+    #[test]
+    fn format_fields() {
+        let event = SseEvent::new("a\r\nb\rc").id("1\n2").retry(Duration::from_secs(3));
+        assert_eq!(event.to_string(), "data: a\ndata: b\ndata: c\nid: 12\nretry: 3000\n\n");
+        assert_eq!(SseEvent::new("").to_string(), "data: \n\n");
+    }
+
+    // Stops the system once the last client is gone
+    struct StopWhenIdle(SseServer, bool);
+
+    impl Reactor for StopWhenIdle {
+        type Input = Result<Accepted>;
+        type Output = Error;
+
+        fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Error> {
+            let reaction = self.0.react(reaction);
+            match self.0.client_count() {
+                0 if self.1 => System::stop(),
+                0 => {}
+                _ => self.1 = true,
+            }
+            reaction
+        }
+    }
+
+    #[test]
+    fn replay_and_stream() {
+        let (tx, rx) = mpsc::channel();
+
+        let server = thread::spawn(move || {
+            System::builder().finish();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut broadcaster = Broadcaster::new(16);
+            let sse = SseServer::new(broadcaster.receiver().unwrap())
+                .replay_size(2)
+                .keepalive(Duration::from_millis(20))
+                .unwrap();
+
+            for id in 1..=3 {
+                broadcaster.send(SseEvent::new(format!("event {}", id)).id(id.to_string()));
+            }
+
+            tx.send((listener.local_addr().unwrap(), broadcaster)).unwrap();
+            System::start(listener.chain(StopWhenIdle(sse, false))).unwrap();
+        });
+
+        let (addr, mut broadcaster) = rx.recv().unwrap();
+        let mut stream = StdTcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\nLast-Event-ID: 2\r\n\r\n").unwrap();
+        let mut lines = BufReader::new(stream).lines().map(|l| l.unwrap());
+
+        assert_eq!(lines.next().unwrap(), "HTTP/1.1 200 OK");
+        assert!(lines.by_ref().any(|l| l.is_empty()));
+        assert_eq!(lines.next().unwrap(), "data: event 3");
+        assert_eq!(lines.next().unwrap(), "id: 3");
+        assert_eq!(lines.next().unwrap(), "");
+
+        assert_eq!(lines.next().unwrap(), ": keepalive");
+        broadcaster.send(SseEvent::new("live"));
+        assert!(lines.any(|l| l == "data: live"));
+
+        drop(lines);
+        server.join().unwrap();
+    }
+}
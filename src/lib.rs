@@ -3,16 +3,25 @@ pub mod net;
 pub mod broadcast;
 pub mod queue;
 pub mod memchr;
+pub mod restart;
+pub mod codecs;
+pub mod http;
+pub mod websocket;
+pub mod resp;
+pub mod telnet;
+pub mod socks5;
 
+mod connection;
 mod errors;
 mod reactor;
 mod system;
-mod codecs;
 
 pub use reactor::{Reaction, Reactor, PollReactor};
+pub use connection::{Connection, ConnectionEvent};
 pub use system::{Interest, System, SysEvent};
 pub use system::evented::Evented;
 pub use system::timer::Timer;
+pub use system::pool::{BufferPool, PoolStats};
 pub use errors::{Error, Result, os_err};
 
 #[derive(Debug, Clone, Copy)]
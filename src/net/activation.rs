@@ -0,0 +1,212 @@
+//! Socket activation: adopt sockets bound by a supervisor (e.g. systemd)
+//! and passed through `LISTEN_FDS`, `LISTEN_PID` and `LISTEN_FDNAMES`.
+//!
+//! ```no_run
+//! # use netlib::System;
+//! # use netlib::net::activation;
+//! System::builder().finish();
+//! for fd in activation::listen_fds(true).unwrap() {
+//!     if fd.name() == Some("web") {
+//!         let listener = fd.tcp_listener().unwrap();
+//!     }
+//! }
+//! ```
+use std::env;
+use std::io::{Error as IoError, ErrorKind};
+use std::os::unix::io::RawFd;
+
+use libc::c_int;
+
+use super::socket::getsockopt;
+use super::tcp::TcpListener;
+use super::udp::UdpSocket;
+use super::uds::UnixListener;
+use crate::Result;
+
+/// The first passed file descriptor; the rest follow sequentially.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+/// Take the sockets passed to this process.
+///
+/// Returns an empty list if none were passed, or if they were meant for
+/// another process (`LISTEN_PID` differs).
+/// If `unset_env` is true the variables are removed, so they aren't
+/// inherited by child processes.
+pub fn listen_fds(unset_env: bool) -> Result<Vec<ListenFd>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    let names = env::var("LISTEN_FDNAMES").ok();
+
+    if unset_env {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+
+    let fds = parse(pid.as_deref(), fds.as_deref(), names.as_deref(), std::process::id())?;
+
+    for fd in &fds {
+        let _ = unsafe { libc::fcntl(fd.fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+
+    Ok(fds)
+}
+
+fn parse(pid: Option<&str>, fds: Option<&str>, names: Option<&str>, own_pid: u32) -> Result<Vec<ListenFd>> {
+    let invalid = |msg| IoError::new(ErrorKind::InvalidInput, msg);
+
+    let pid = match pid {
+        Some(pid) => pid.parse::<u32>().map_err(|_| invalid("invalid LISTEN_PID"))?,
+        None => return Ok(Vec::new()),
+    };
+
+    if pid != own_pid {
+        return Ok(Vec::new());
+    }
+
+    let count = match fds.map(str::parse::<usize>) {
+        Some(Ok(count)) if count <= (RawFd::MAX - LISTEN_FDS_START) as usize => count as RawFd,
+        Some(_) => return Err(invalid("invalid LISTEN_FDS").into()),
+        None => return Ok(Vec::new()),
+    };
+
+    let names = names.map(|n| n.split(':').collect::<Vec<_>>()).unwrap_or_default();
+
+    let fds = (0..count)
+        .zip(names.into_iter().chain(std::iter::repeat("")))
+        .map(|(i, name)| ListenFd {
+            fd: LISTEN_FDS_START + i,
+            name: Some(name).filter(|n| !n.is_empty()).map(String::from),
+        })
+        .collect();
+
+    Ok(fds)
+}
+
+// -----------------------------------------------------------------------------
+//     - ListenFd -
+// -----------------------------------------------------------------------------
+/// A socket passed by the supervisor.
+#[derive(Debug, PartialEq)]
+pub struct ListenFd {
+    fd: RawFd,
+    name: Option<String>,
+}
+
+impl ListenFd {
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// The name from `LISTEN_FDNAMES`, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Adopt a listening TCP socket.
+    pub fn tcp_listener(self) -> Result<TcpListener> {
+        self.expect(&[libc::AF_INET, libc::AF_INET6], libc::SOCK_STREAM, true)?;
+        unsafe { TcpListener::from_raw_fd(self.fd) }
+    }
+
+    /// Adopt a listening Unix stream socket.
+    pub fn unix_listener(self) -> Result<UnixListener> {
+        self.expect(&[libc::AF_UNIX], libc::SOCK_STREAM, true)?;
+        unsafe { UnixListener::from_raw_fd(self.fd) }
+    }
+
+    /// Adopt a UDP socket.
+    pub fn udp_socket(self) -> Result<UdpSocket> {
+        self.expect(&[libc::AF_INET, libc::AF_INET6], libc::SOCK_DGRAM, false)?;
+        unsafe { UdpSocket::from_raw_fd(self.fd) }
+    }
+
+    fn expect(&self, domains: &[c_int], ty: c_int, listening: bool) -> Result<()> {
+        let fd = &self.fd;
+        let actual_ty: c_int = getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE)?;
+        let domain: c_int = getsockopt(fd, libc::SOL_SOCKET, libc::SO_DOMAIN)?;
+        let accepting: c_int = getsockopt(fd, libc::SOL_SOCKET, libc::SO_ACCEPTCONN)?;
+
+        let matches = actual_ty == ty && domains.contains(&domain) && (accepting != 0) == listening;
+
+        match matches {
+            true => Ok(()),
+            false => Err(IoError::new(ErrorKind::InvalidInput, "unexpected socket type").into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+    use crate::System;
+
+    #[test]
+    fn parse_env() {
+        let fds = parse(Some("42"), Some("2"), Some("web"), 42).unwrap();
+        assert_eq!(fds[0], ListenFd { fd: 3, name: Some("web".into()) });
+        assert_eq!(fds[1], ListenFd { fd: 4, name: None });
+        assert!(parse(Some("41"), Some("2"), None, 42).unwrap().is_empty());
+        assert!(parse(None, None, None, 42).unwrap().is_empty());
+        assert!(parse(Some("42"), Some("-1"), None, 42).is_err());
+        assert!(parse(Some("42"), Some("2147483647"), None, 42).is_err());
+    }
+
+    // Runs in the child process spawned by `activate_child`
+    #[test]
+    fn child() {
+        let port = match env::var("NETLIB_ACTIVATION_PORT") {
+            Ok(port) => port.parse::<u16>().unwrap(),
+            Err(_) => return,
+        };
+
+        System::builder().finish();
+        let mut fds = listen_fds(true).unwrap();
+        assert_eq!(fds.len(), 1);
+        assert_eq!(fds[0].name(), Some("web"));
+        assert!(env::var("LISTEN_FDS").is_err());
+
+        let fd = fds.pop().unwrap();
+        let raw = fd.fd();
+        assert!(ListenFd { fd: raw, name: None }.udp_socket().is_err());
+        let listener = fd.tcp_listener().unwrap();
+        assert_eq!(listener.local_addr().unwrap().port(), port);
+    }
+
+    #[test]
+    fn activate_child() {
+        System::builder().finish();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = listener.as_raw_fd();
+        let port = listener.local_addr().unwrap().port().to_string();
+
+        // LISTEN_PID has to be the child's pid, which the shell knows and
+        // keeps when it execs. Everything else is set up before forking.
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(&["-c", "LISTEN_PID=$$ exec \"$0\" \"$@\""])
+            .arg(env::current_exe().unwrap())
+            .args(&["--exact", "net::activation::test::child", "--test-threads=1"])
+            .env("LISTEN_FDS", "1")
+            .env("LISTEN_FDNAMES", "web")
+            .env("NETLIB_ACTIVATION_PORT", port);
+
+        // Only async-signal-safe calls after fork
+        unsafe {
+            cmd.pre_exec(move || {
+                if fd == LISTEN_FDS_START {
+                    libc::fcntl(fd, libc::F_SETFD, 0);
+                } else {
+                    libc::dup2(fd, LISTEN_FDS_START);
+                }
+                Ok(())
+            });
+        }
+
+        let output = cmd.output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+    }
+}
@@ -0,0 +1,426 @@
+//! Forward TCP connections to another address (a layer 4 proxy).
+//!
+//! `Proxy` is a stage after a `TcpListener`: every accepted connection is
+//! paired with a new connection to the target and bytes are moved both
+//! ways until both sides are done. Where possible the bytes never leave
+//! the kernel: they are moved with `splice(2)` through a pipe.
+//!
+//! ```no_run
+//! # use netlib::{Reactor, System};
+//! # use netlib::net::forward::Proxy;
+//! # use netlib::net::tcp::TcpListener;
+//! System::builder().finish();
+//! let forwarder = TcpListener::bind("0.0.0.0:8080").unwrap()
+//!     .chain(Proxy::new("10.0.0.2:80").unwrap());
+//! System::start(forwarder);
+//! ```
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream as StdTcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+
+use crate::net::tcp::{Accepted, ConnectionSlot, TcpStream};
+use crate::{Interest, Reaction, Reactor, Result};
+
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+// -----------------------------------------------------------------------------
+//     - Pipe -
+// -----------------------------------------------------------------------------
+// Both ends of a non-blocking pipe
+struct Pipe {
+    read: RawFd,
+    write: RawFd,
+}
+
+impl Pipe {
+    fn new(size: usize) -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } == -1 {
+            return Err(crate::os_err());
+        }
+
+        // The default capacity is fine if this fails
+        unsafe { libc::fcntl(fds[1], libc::F_SETPIPE_SZ, size as libc::c_int) };
+        Ok(Self { read: fds[0], write: fds[1] })
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+    let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+    match unsafe { libc::splice(from, ptr::null_mut(), to, ptr::null_mut(), len, flags) } {
+        -1 => Err(crate::os_err()),
+        n => Ok(n as usize),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Half -
+// -----------------------------------------------------------------------------
+enum Channel {
+    Splice(Pipe),
+    Copy { buf: Vec<u8>, pos: usize },
+}
+
+/// One direction of a relayed connection.
+///
+/// Nothing more is read from the source until everything read so far has
+/// been written to the destination, so a slow reader holds back a fast
+/// writer. Once the source is done and everything is written, the
+/// destination is shut down for writing, so the other direction keeps
+/// going until it's done as well.
+pub(crate) struct Half {
+    channel: Channel,
+    size: usize,
+    // Bytes read but not written yet
+    pending: usize,
+    eof: bool,
+    shut: bool,
+}
+
+impl Half {
+    /// Move the bytes with `splice` if `splice` is true and a pipe can
+    /// be created, copying them otherwise.
+    pub(crate) fn new(size: usize, splice: bool) -> Self {
+        let channel = match splice {
+            true => Pipe::new(size).map(Channel::Splice).ok(),
+            false => None,
+        };
+
+        Self {
+            channel: channel.unwrap_or(Channel::Copy { buf: Vec::new(), pos: 0 }),
+            size,
+            pending: 0,
+            eof: false,
+            shut: false,
+        }
+    }
+
+    pub(crate) fn wants_read(&self) -> bool {
+        !self.eof && self.pending == 0
+    }
+
+    pub(crate) fn wants_write(&self) -> bool {
+        self.pending > 0
+    }
+
+    /// True once the destination is shut down.
+    pub(crate) fn is_done(&self) -> bool {
+        self.shut
+    }
+
+    fn fill(&mut self, src: &mut TcpStream) -> io::Result<usize> {
+        match &mut self.channel {
+            Channel::Splice(pipe) => splice(src.as_raw_fd(), pipe.write, self.size),
+            Channel::Copy { buf, pos } => {
+                buf.resize(self.size, 0);
+                *pos = 0;
+                src.as_mut().read(buf)
+            }
+        }
+    }
+
+    fn drain(&mut self, dst: &mut TcpStream) -> io::Result<usize> {
+        match &mut self.channel {
+            Channel::Splice(pipe) => splice(pipe.read, dst.as_raw_fd(), self.pending),
+            Channel::Copy { buf, pos } => {
+                let n = dst.as_mut().write(&buf[*pos..*pos + self.pending])?;
+                *pos += n;
+                Ok(n)
+            }
+        }
+    }
+
+    /// Move bytes from `src` to `dst` until one of them would block.
+    pub(crate) fn pump(&mut self, src: &mut TcpStream, dst: &mut TcpStream) -> Result<()> {
+        loop {
+            if self.pending > 0 {
+                match self.drain(dst) {
+                    Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero).into()),
+                    Ok(n) => self.pending -= n,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                }
+                continue;
+            }
+
+            if self.eof {
+                if !self.shut {
+                    self.shut = true;
+                    // The peer may be gone already
+                    let _ = dst.as_ref().shutdown(Shutdown::Write);
+                }
+                return Ok(());
+            }
+
+            match self.fill(src) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.pending = n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Pump both directions between `a` and `b` and rearm both streams for
+/// what they are waiting on. True once both directions are done.
+pub(crate) fn relay(a: &mut TcpStream, b: &mut TcpStream, a_to_b: &mut Half, b_to_a: &mut Half) -> Result<bool> {
+    a_to_b.pump(a, b)?;
+    b_to_a.pump(b, a)?;
+    rearm(a, a_to_b.wants_read(), b_to_a.wants_write())?;
+    rearm(b, b_to_a.wants_read(), a_to_b.wants_write())?;
+    Ok(a_to_b.is_done() && b_to_a.is_done())
+}
+
+fn rearm(stream: &TcpStream, read: bool, write: bool) -> Result<()> {
+    match (read, write) {
+        (true, true) => stream.rearm(Interest::ReadWrite),
+        (true, false) => stream.rearm(Interest::Read),
+        (false, true) => stream.rearm(Interest::Write),
+        // Waiting on the other stream
+        (false, false) => Ok(()),
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Proxy -
+// -----------------------------------------------------------------------------
+struct Pair {
+    client: TcpStream,
+    upstream: TcpStream,
+    connected: bool,
+    up: Half,
+    down: Half,
+    _slot: Option<ConnectionSlot>,
+}
+
+/// Forward every accepted connection to a target.
+///
+/// The connection to the target is made when a client is accepted; if it
+/// fails the client is closed. A connection that resets or fails closes
+/// its pair.
+///
+/// Yields `Ok(())` for every pair that is done, and the error of every
+/// client that can't be accepted or pair that fails, including a failed
+/// connection to the target.
+pub struct Proxy {
+    target: SocketAddr,
+    buffer_size: usize,
+    splice: bool,
+    pairs: HashMap<u64, Pair>,
+    // Upstream ids to the id of their client
+    upstreams: HashMap<u64, u64>,
+}
+
+impl Proxy {
+    /// The target is resolved once, here.
+    pub fn new(target: impl ToSocketAddrs) -> Result<Self> {
+        let target = target
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "could not resolve address"))?;
+
+        let proxy = Self {
+            target,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            splice: true,
+            pairs: HashMap::new(),
+            upstreams: HashMap::new(),
+        };
+        Ok(proxy)
+    }
+
+    /// The most bytes held per direction of a connection, in the pipe or
+    /// in the buffer. Defaults to 64KiB.
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size.max(1);
+        self
+    }
+
+    /// Move bytes with `splice(2)`. Enabled by default; without it, or if
+    /// no pipe can be created, bytes are copied through a buffer.
+    pub fn splice(mut self, enabled: bool) -> Self {
+        self.splice = enabled;
+        self
+    }
+
+    /// The number of client connections, connected upstream or not.
+    pub fn connection_count(&self) -> usize {
+        self.pairs.len()
+    }
+
+    fn add(&mut self, stream: StdTcpStream, slot: Option<ConnectionSlot>) -> Result<()> {
+        let upstream = TcpStream::connect(self.target)?;
+        let client = TcpStream::new(stream, Interest::Read)?;
+
+        let pair = Pair {
+            client,
+            upstream,
+            connected: false,
+            up: Half::new(self.buffer_size, self.splice),
+            down: Half::new(self.buffer_size, self.splice),
+            _slot: slot,
+        };
+
+        self.upstreams.insert(pair.upstream.id, pair.client.id);
+        self.pairs.insert(pair.client.id, pair);
+        Ok(())
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some(pair) = self.pairs.remove(&id) {
+            self.upstreams.remove(&pair.upstream.id);
+        }
+    }
+}
+
+impl Pair {
+    // True once the pair is done
+    fn react(&mut self, upstream_event: bool, writable: bool) -> Result<bool> {
+        if !self.connected {
+            // The client waits until the upstream connection is made
+            if !upstream_event {
+                return Ok(false);
+            }
+
+            if let Some(e) = self.upstream.as_ref().take_error()? {
+                return Err(e.into());
+            }
+
+            if !writable {
+                self.upstream.rearm(Interest::ReadWrite)?;
+                return Ok(false);
+            }
+
+            self.connected = true;
+        }
+
+        relay(&mut self.client, &mut self.upstream, &mut self.up, &mut self.down)
+    }
+}
+
+impl Reactor for Proxy {
+    type Input = Result<Accepted>;
+    type Output = Result<()>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        let ev = match reaction {
+            Reaction::Value(Ok((stream, _, slot))) => {
+                return match self.add(stream, slot) {
+                    Ok(()) => Reaction::Continue,
+                    Err(e) => Reaction::Value(Err(e)),
+                };
+            }
+            Reaction::Value(Err(e)) => return Reaction::Value(Err(e)),
+            Reaction::Event(ev) => ev,
+            Reaction::Continue => return Reaction::Continue,
+        };
+
+        let (id, upstream_event) = match self.upstreams.get(&ev.owner) {
+            Some(&id) => (id, true),
+            None if self.pairs.contains_key(&ev.owner) => (ev.owner, false),
+            None => return Reaction::Event(ev),
+        };
+
+        let pair = self.pairs.get_mut(&id).expect("pair");
+        match pair.react(upstream_event, ev.write) {
+            Ok(false) => Reaction::Continue,
+            res => {
+                self.remove(id);
+                Reaction::Value(res.map(|_| ()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{TcpListener as StdTcpListener, TcpStream as StdTcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+    use crate::net::tcp::TcpListener;
+    use crate::System;
+
+    // Reads everything, then answers with the length and closes
+    fn counting_server() -> SocketAddr {
+        let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut received = Vec::new();
+                stream.read_to_end(&mut received).unwrap();
+                stream.write_all(received.len().to_string().as_bytes()).unwrap();
+            }
+        });
+        addr
+    }
+
+    // The forwarder's address, and what it yields
+    fn forwarder(target: SocketAddr, splice: bool) -> (SocketAddr, mpsc::Receiver<Result<()>>) {
+        let (tx, rx) = mpsc::channel();
+        let (results, yielded) = mpsc::channel();
+        thread::spawn(move || {
+            System::builder().finish();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+            let proxy = Proxy::new(target).unwrap().buffer_size(4096).splice(splice);
+            System::start(listener.chain(proxy).map(move |res| {
+                let _ = results.send(res);
+            }))
+            .unwrap();
+        });
+        (rx.recv().unwrap(), yielded)
+    }
+
+    #[test]
+    fn half_close() {
+        let target = counting_server();
+
+        for &splice in &[true, false] {
+            let (proxy, yielded) = forwarder(target, splice);
+            let mut client = StdTcpStream::connect(proxy).unwrap();
+
+            // More than the buffer, so the writer is held back
+            let payload = vec![7u8; 1024 * 1024];
+            let writer = {
+                let mut client = client.try_clone().unwrap();
+                thread::spawn(move || {
+                    client.write_all(&payload).unwrap();
+                    client.shutdown(Shutdown::Write).unwrap();
+                })
+            };
+
+            // The answer only comes after the shutdown made it through
+            let mut answer = String::new();
+            client.read_to_string(&mut answer).unwrap();
+            writer.join().unwrap();
+            assert_eq!(answer, "1048576");
+            assert!(yielded.recv().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn unreachable_target() {
+        // Nothing listens on a port that was just released
+        let target = StdTcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (proxy, yielded) = forwarder(target, true);
+
+        let mut client = StdTcpStream::connect(proxy).unwrap();
+        let mut buf = Vec::new();
+        assert!(client.read_to_end(&mut buf).map(|n| n == 0).unwrap_or(true));
+        assert!(yielded.recv().unwrap().is_err());
+    }
+}
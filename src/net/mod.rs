@@ -1,3 +1,9 @@
 pub mod tcp;
+pub mod udp;
 pub mod uds;
+pub mod activation;
+pub mod proxy;
+pub mod forward;
 mod socket;
+
+pub use socket::SocketBuilder;
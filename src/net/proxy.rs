@@ -0,0 +1,482 @@
+//! The PROXY protocol (v1 and v2), as sent by load balancers ahead of
+//! the client's data.
+//!
+//! `ProxyProtocol` is a stage after a `TcpListener`: it reads the header
+//! off every accepted connection and passes the connection on with the
+//! real client address.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use netlib::{Reactor, System};
+//! # use netlib::http::{HttpServer, Response};
+//! # use netlib::net::proxy::ProxyProtocol;
+//! # use netlib::net::tcp::TcpListener;
+//! System::builder().finish();
+//! let server = TcpListener::bind("127.0.0.1:8080").unwrap()
+//!     .chain(ProxyProtocol::new().timeout(Duration::from_secs(5)))
+//!     .map(|res| res.map(|(stream, client, _header, slot)| (stream, client, slot)))
+//!     .chain(HttpServer::new(|_request| Response::new(200)));
+//! System::start(server);
+//! ```
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream as StdTcpStream};
+use std::time::Duration;
+
+use crate::net::tcp::{Accepted, ConnectionSlot};
+use crate::{Error, Interest, Reaction, Reactor, Result, System, Timer};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_FIXED: usize = 16;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// -----------------------------------------------------------------------------
+//     - Tlv -
+// -----------------------------------------------------------------------------
+/// A type-length-value field of a v2 header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tlv {
+    pub kind: u8,
+    pub value: Vec<u8>,
+}
+
+impl Tlv {
+    /// The application protocol, e.g. `h2`.
+    pub const ALPN: u8 = 0x01;
+    /// The host name the client asked for (SNI).
+    pub const AUTHORITY: u8 = 0x02;
+    /// A checksum of the header. Not verified.
+    pub const CRC32C: u8 = 0x03;
+    pub const NOOP: u8 = 0x04;
+    /// An id for the connection, set by the load balancer.
+    pub const UNIQUE_ID: u8 = 0x05;
+    /// TLS details, with TLVs of their own.
+    pub const SSL: u8 = 0x20;
+    pub const NETNS: u8 = 0x30;
+}
+
+// -----------------------------------------------------------------------------
+//     - Proxy header -
+// -----------------------------------------------------------------------------
+/// A parsed PROXY protocol header.
+///
+/// ```
+/// # use netlib::net::proxy::ProxyHeader;
+/// let (header, len) = ProxyHeader::parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /").unwrap().unwrap();
+/// assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+/// assert_eq!(len, 45);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyHeader {
+    /// 1 or 2.
+    pub version: u8,
+    /// The client. `None` if the connection wasn't proxied (a health check
+    /// by the load balancer itself) or the protocol is unknown.
+    pub source: Option<SocketAddr>,
+    /// The address the client connected to.
+    pub destination: Option<SocketAddr>,
+    /// Always empty for v1.
+    pub tlvs: Vec<Tlv>,
+}
+
+impl ProxyHeader {
+    /// Parse the header at the front of `buf`, returning it and its length,
+    /// or `Ok(None)` if more data is needed.
+    pub fn parse(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>> {
+        let len = header_len(buf)?;
+        if len == 0 || len > buf.len() {
+            return Ok(None);
+        }
+
+        let complete = match buf[0] {
+            b'P' => buf[..len].ends_with(b"\r\n"),
+            _ => len >= V2_FIXED && len == V2_FIXED + u16::from_be_bytes([buf[14], buf[15]]) as usize,
+        };
+
+        match (complete, buf[0]) {
+            (false, _) => Ok(None),
+            (true, b'P') => parse_v1(&buf[..len]).map(|header| Some((header, len))),
+            (true, _) => parse_v2(&buf[..len]).map(|header| Some((header, len))),
+        }
+    }
+
+    /// The value of the first TLV of this kind.
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs.iter().find(|tlv| tlv.kind == kind).map(|tlv| tlv.value.as_slice())
+    }
+
+    /// The host name the client asked for, if the load balancer sent it.
+    pub fn authority(&self) -> Option<&str> {
+        self.tlv(Tlv::AUTHORITY).and_then(|v| std::str::from_utf8(v).ok())
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::protocol(format!("invalid PROXY header: {}", msg))
+}
+
+// How many bytes at the front of `buf` belong to the header: all of them
+// until its end is known. Reading no further than that leaves the
+// client's data in the socket.
+fn header_len(buf: &[u8]) -> Result<usize> {
+    let prefix = |expected: &[u8]| expected.starts_with(&buf[..buf.len().min(expected.len())]);
+
+    if buf.is_empty() {
+        Ok(0)
+    } else if prefix(V1_PREFIX) && buf[0] == b'P' {
+        match buf.windows(2).position(|w| w == b"\r\n") {
+            Some(i) if i + 2 <= V1_MAX => Ok(i + 2),
+            None if buf.len() < V1_MAX => Ok(buf.len()),
+            _ => Err(invalid("line too long")),
+        }
+    } else if prefix(V2_SIGNATURE) {
+        match buf.len() < V2_FIXED {
+            true => Ok(buf.len()),
+            false => Ok(buf.len().min(V2_FIXED + u16::from_be_bytes([buf[14], buf[15]]) as usize)),
+        }
+    } else {
+        Err(Error::protocol("missing PROXY header"))
+    }
+}
+
+fn parse_v1(line: &[u8]) -> Result<ProxyHeader> {
+    let line = std::str::from_utf8(&line[V1_PREFIX.len()..line.len() - 2]).map_err(|_| invalid("not ascii"))?;
+    let mut header = ProxyHeader { version: 1, source: None, destination: None, tlvs: Vec::new() };
+
+    let parts = line.split(' ').collect::<Vec<_>>();
+    let (src, dst, sport, dport) = match parts.as_slice() {
+        ["UNKNOWN", ..] => return Ok(header),
+        ["TCP4", src, dst, sport, dport] | ["TCP6", src, dst, sport, dport] => (src, dst, sport, dport),
+        _ => return Err(invalid("unexpected fields")),
+    };
+
+    let ip = |ip: &str| -> Result<IpAddr> {
+        match parts[0] {
+            "TCP4" => ip.parse::<Ipv4Addr>().map(IpAddr::V4),
+            _ => ip.parse::<Ipv6Addr>().map(IpAddr::V6),
+        }
+        .map_err(|_| invalid("bad address"))
+    };
+    let port = |port: &str| port.parse::<u16>().map_err(|_| invalid("bad port"));
+
+    header.source = Some(SocketAddr::new(ip(src)?, port(sport)?));
+    header.destination = Some(SocketAddr::new(ip(dst)?, port(dport)?));
+    Ok(header)
+}
+
+fn parse_v2(buf: &[u8]) -> Result<ProxyHeader> {
+    let mut header = ProxyHeader { version: 2, source: None, destination: None, tlvs: Vec::new() };
+    let (version, command, family) = (buf[12] >> 4, buf[12] & 0x0f, buf[13] >> 4);
+    let body = &buf[V2_FIXED..];
+
+    if version != 2 {
+        return Err(invalid("unsupported version"));
+    }
+
+    let addr_len = match (command, family) {
+        // LOCAL: the load balancer's own connection, ignore the addresses
+        (0, _) => return Ok(header),
+        (1, 0) => 0,
+        (1, 1) => 12,
+        (1, 2) => 36,
+        (1, 3) => 216,
+        (1, _) => return Err(invalid("unknown address family")),
+        _ => return Err(invalid("unknown command")),
+    };
+
+    if body.len() < addr_len {
+        return Err(invalid("address block too short"));
+    }
+
+    let (addrs, mut tlvs) = body.split_at(addr_len);
+    let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+    match family {
+        1 => {
+            let ip = |at: usize| IpAddr::V4(Ipv4Addr::new(addrs[at], addrs[at + 1], addrs[at + 2], addrs[at + 3]));
+            header.source = Some(SocketAddr::new(ip(0), port(8)));
+            header.destination = Some(SocketAddr::new(ip(4), port(10)));
+        }
+        2 => {
+            let ip = |at: usize| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&addrs[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            header.source = Some(SocketAddr::new(ip(0), port(32)));
+            header.destination = Some(SocketAddr::new(ip(16), port(34)));
+        }
+        // Unix sockets and unspecified: no socket address
+        _ => {}
+    }
+
+    while !tlvs.is_empty() {
+        if tlvs.len() < 3 {
+            return Err(invalid("truncated TLV"));
+        }
+
+        let len = u16::from_be_bytes([tlvs[1], tlvs[2]]) as usize;
+        if tlvs.len() < 3 + len {
+            return Err(invalid("truncated TLV"));
+        }
+
+        header.tlvs.push(Tlv { kind: tlvs[0], value: tlvs[3..3 + len].to_vec() });
+        tlvs = &tlvs[3 + len..];
+    }
+
+    Ok(header)
+}
+
+// -----------------------------------------------------------------------------
+//     - Proxy protocol stage -
+// -----------------------------------------------------------------------------
+struct Pending {
+    stream: StdTcpStream,
+    peer: SocketAddr,
+    slot: Option<ConnectionSlot>,
+    buf: Vec<u8>,
+    timer: Timer,
+}
+
+/// Reads the PROXY header of every connection accepted by a `TcpListener`
+/// and yields the connection with the client's address (the peer address
+/// if the header has none), the header, and the connection's slot.
+///
+/// Only the header is read, so the client's data is left for whoever
+/// handles the connection next. A connection without a valid header, or
+/// that hasn't sent it within the timeout, is closed and yields the error.
+pub struct ProxyProtocol {
+    timeout: Duration,
+    pending: HashMap<u64, Pending>,
+    // Timer id to connection id
+    timers: HashMap<u64, u64>,
+}
+
+impl ProxyProtocol {
+    pub fn new() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            pending: HashMap::new(),
+            timers: HashMap::new(),
+        }
+    }
+
+    /// How long a connection has to send its header. Defaults to 10 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The number of connections that haven't sent their header yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn add(&mut self, stream: StdTcpStream, peer: SocketAddr, slot: Option<ConnectionSlot>) -> Result<()> {
+        let id = System::reserve();
+        let timer = match Timer::new(self.timeout.max(Duration::from_nanos(1)), None) {
+            Ok(timer) => timer,
+            Err(e) => {
+                System::free(id);
+                return Err(e);
+            }
+        };
+
+        // Arming a readable socket triggers an event straight away
+        if let Err(e) = System::arm(&stream, Interest::Read, id) {
+            // Dropping the timer frees its own id
+            System::free(id);
+            return Err(e);
+        }
+
+        self.timers.insert(timer.reactor_id, id);
+        self.pending.insert(id, Pending { stream, peer, slot, buf: Vec::new(), timer });
+        Ok(())
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Pending> {
+        let pending = self.pending.remove(&id)?;
+        self.timers.remove(&pending.timer.reactor_id);
+        System::free(id);
+        Some(pending)
+    }
+
+    fn read_header(&mut self, id: u64) -> Result<Option<ProxyHeader>> {
+        let pending = self.pending.get_mut(&id).expect("pending connection");
+        let mut peeked = [0u8; 512];
+
+        loop {
+            let n = match pending.stream.peek(&mut peeked) {
+                Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+
+            // Take as much as belongs to the header
+            let buffered = pending.buf.len();
+            pending.buf.extend_from_slice(&peeked[..n]);
+            let take = header_len(&pending.buf)? - buffered;
+            pending.buf.truncate(buffered + take);
+            pending.stream.read_exact(&mut peeked[..take])?;
+
+            if let Some((header, _)) = ProxyHeader::parse(&pending.buf)? {
+                return Ok(Some(header));
+            }
+
+            if take < n {
+                return Err(invalid("incomplete"));
+            }
+        }
+    }
+}
+
+impl Default for ProxyProtocol {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reactor for ProxyProtocol {
+    type Input = Result<Accepted>;
+    type Output = Result<(StdTcpStream, SocketAddr, ProxyHeader, Option<ConnectionSlot>)>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        let id = match reaction {
+            Reaction::Value(Ok((stream, peer, slot))) => match self.add(stream, peer, slot) {
+                Ok(()) => return Reaction::Continue,
+                Err(e) => return Reaction::Value(Err(e)),
+            },
+            Reaction::Value(Err(e)) => return Reaction::Value(Err(e)),
+            Reaction::Continue => return Reaction::Continue,
+            Reaction::Event(ev) if self.pending.contains_key(&ev.owner) => ev.owner,
+            Reaction::Event(ev) => {
+                return match self.timers.get(&ev.owner).copied() {
+                    Some(id) => {
+                        self.remove(id);
+                        let e = io::Error::new(ErrorKind::TimedOut, "no PROXY header within the timeout");
+                        Reaction::Value(Err(e.into()))
+                    }
+                    None => Reaction::Event(ev),
+                };
+            }
+        };
+
+        match self.read_header(id) {
+            Ok(None) => {
+                let pending = &self.pending[&id];
+                match System::rearm(&pending.stream, Interest::Read, id) {
+                    Ok(()) => Reaction::Continue,
+                    Err(e) => {
+                        self.remove(id);
+                        Reaction::Value(Err(e))
+                    }
+                }
+            }
+            Ok(Some(header)) => {
+                let pending = self.remove(id).expect("pending connection");
+                if let Err(e) = System::disarm(&pending.stream) {
+                    return Reaction::Value(Err(e));
+                }
+
+                let client = header.source.unwrap_or(pending.peer);
+                Reaction::Value(Ok((pending.stream, client, header, pending.slot)))
+            }
+            Err(e) => {
+                self.remove(id);
+                Reaction::Value(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use crate::net::tcp::TcpListener;
+
+    fn v2(command: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x20 | command, family << 4 | 1]);
+        buf.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        buf.extend_from_slice(body);
+        buf
+    }
+
+    #[test]
+    fn parse_v1() {
+        let line = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n";
+        let (header, len) = ProxyHeader::parse(line).unwrap().unwrap();
+        assert_eq!(len, line.len());
+        assert_eq!(header.source, Some("[2001:db8::1]:4000".parse().unwrap()));
+        assert_eq!(header.destination, Some("[2001:db8::2]:443".parse().unwrap()));
+
+        let (header, _) = ProxyHeader::parse(b"PROXY UNKNOWN\r\n").unwrap().unwrap();
+        assert_eq!(header.source, None);
+
+        assert_eq!(ProxyHeader::parse(b"PROXY TCP4 1.2.3.4").unwrap(), None);
+        assert_eq!(ProxyHeader::parse(b"PRO").unwrap(), None);
+        assert!(ProxyHeader::parse(b"PROXY TCP4 1.2.3.4 5.6.7.8 1 x\r\n").is_err());
+        assert!(ProxyHeader::parse(b"GET / HTTP/1.1\r\n").is_err());
+        assert!(ProxyHeader::parse(&[b'P'; 200]).is_err());
+    }
+
+    #[test]
+    fn parse_v2() {
+        let mut body = vec![192, 0, 2, 1, 198, 51, 100, 1, 0x1f, 0x90, 0x01, 0xbb];
+        body.extend_from_slice(&[Tlv::AUTHORITY, 0, 11]);
+        body.extend_from_slice(b"example.com");
+        body.extend_from_slice(&[Tlv::NOOP, 0, 0]);
+        let mut buf = v2(1, 1, &body);
+        buf.extend_from_slice(b"data");
+
+        assert_eq!(ProxyHeader::parse(&buf[..20]).unwrap(), None);
+        let (header, len) = ProxyHeader::parse(&buf).unwrap().unwrap();
+        assert_eq!(len, buf.len() - 4);
+        assert_eq!(header.source, Some("192.0.2.1:8080".parse().unwrap()));
+        assert_eq!(header.destination, Some("198.51.100.1:443".parse().unwrap()));
+        assert_eq!(header.authority(), Some("example.com"));
+        assert_eq!(header.tlvs.len(), 2);
+
+        let (header, _) = ProxyHeader::parse(&v2(0, 1, &body)).unwrap().unwrap();
+        assert_eq!(header.source, None);
+
+        assert!(ProxyHeader::parse(&v2(1, 1, &body[..8])).is_err());
+        assert!(ProxyHeader::parse(&v2(1, 1, &body[..14])).is_err());
+    }
+
+    #[test]
+    fn stage_leaves_data() {
+        System::builder().finish();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = StdTcpStream::connect(addr).unwrap();
+        client.write_all(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nhello").unwrap();
+        let _silent = StdTcpStream::connect(addr).unwrap();
+
+        let mut results = Vec::new();
+        let stage = listener.chain(ProxyProtocol::new().timeout(Duration::from_millis(50))).map(move |res| {
+            results.push(res.map(|(mut stream, client, _, _)| {
+                let mut buf = [0u8; 5];
+                stream.set_nonblocking(false).unwrap();
+                stream.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf, b"hello");
+                client
+            }));
+
+            if results.len() == 2 {
+                assert_eq!(results[0].as_ref().unwrap(), &"192.0.2.1:56324".parse().unwrap());
+                match &results[1] {
+                    Err(Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+                    _ => panic!("expected a timeout"),
+                }
+                System::stop();
+            }
+        });
+
+        System::start(stage).unwrap();
+    }
+}
@@ -1,60 +1,620 @@
-use crate::Result;
-use std::net::SocketAddr;
+use std::convert::TryFrom;
+use std::io::{Error as IoError, ErrorKind};
+use std::mem::{size_of, zeroed};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr, Ipv6Addr, TcpListener as StdTcpListener, TcpStream as StdTcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::time::Duration;
 
-use libc::{c_int, c_void, socklen_t, sockaddr};
+use libc::{c_int, c_void, socklen_t, sockaddr, sockaddr_storage};
 
-use crate::res;
+use super::tcp::{TcpListener, TcpListeners, TcpStream};
+use crate::{res, Interest, Result};
 
-fn setsockopt<T>(sock: &Socket, opt: c_int, val: c_int, payload: T) -> Result<()> {
+pub(super) fn setsockopt<T>(sock: &impl AsRawFd, opt: c_int, val: c_int, payload: T) -> Result<()> {
     unsafe {
         let payload = &payload as *const T as *const c_void;
         let _ = res!(libc::setsockopt(
-            sock.as_inner(),
+            sock.as_raw_fd(),
             opt,
             val,
             payload,
-            std::mem::size_of::<T>() as socklen_t,
+            size_of::<T>() as socklen_t,
         ));
         Ok(())
     }
 }
 
-fn into_inner(addr: &SocketAddr) -> (*const sockaddr, socklen_t) {
-    match *addr {
-        SocketAddr::V4(ref a) => (
-            a as *const _ as *const _,
-            std::mem::size_of_val(a) as socklen_t,
-        ),
-        SocketAddr::V6(ref a) => (
-            a as *const _ as *const _,
-            std::mem::size_of_val(a) as socklen_t,
-        ),
+pub(super) fn getsockopt<T: Copy>(sock: &impl AsRawFd, opt: c_int, val: c_int) -> Result<T> {
+    unsafe {
+        let mut payload: T = zeroed();
+        let mut len = size_of::<T>() as socklen_t;
+        let _ = res!(libc::getsockopt(
+            sock.as_raw_fd(),
+            opt,
+            val,
+            &mut payload as *mut T as *mut c_void,
+            &mut len,
+        ));
+        Ok(payload)
+    }
+}
+
+// Whole seconds, rounded up so a sub-second duration isn't turned into 0
+fn secs(duration: Duration) -> c_int {
+    let secs = duration.as_secs() + (duration.subsec_nanos() > 0) as u64;
+    secs.min(c_int::MAX as u64) as c_int
+}
+
+// A value too large for the option is refused rather than wrapped
+fn int<T>(value: T, option: &str) -> Result<c_int>
+where
+    c_int: TryFrom<T>,
+{
+    match c_int::try_from(value) {
+        Ok(value) => Ok(value),
+        Err(_) => Err(IoError::new(ErrorKind::InvalidInput, format!("{} is too large", option)).into()),
     }
 }
 
+fn resolve<A: ToSocketAddrs>(addr: A) -> Result<Vec<SocketAddr>> {
+    let addrs = addr.to_socket_addrs()?.collect::<Vec<_>>();
+    match addrs.is_empty() {
+        false => Ok(addrs),
+        true => Err(IoError::new(ErrorKind::InvalidInput, "could not resolve to any addresses").into()),
+    }
+}
+
+pub(super) fn into_inner(addr: &SocketAddr) -> (sockaddr_storage, socklen_t) {
+    let mut storage: sockaddr_storage = unsafe { zeroed() };
+
+    let len = match addr {
+        SocketAddr::V4(a) => {
+            let sin = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: a.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(a.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe { *(&mut storage as *mut _ as *mut libc::sockaddr_in) = sin };
+            size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(a) => {
+            let sin6 = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: a.port().to_be(),
+                sin6_flowinfo: a.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: a.ip().octets(),
+                },
+                sin6_scope_id: a.scope_id(),
+            };
+            unsafe { *(&mut storage as *mut _ as *mut libc::sockaddr_in6) = sin6 };
+            size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, len as socklen_t)
+}
+
+pub(super) fn from_inner(storage: &sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as c_int {
+        libc::AF_INET => {
+            let sin = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes());
+            Some(SocketAddrV4::new(ip, u16::from_be(sin.sin_port)).into())
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            let port = u16::from_be(sin6.sin6_port);
+            Some(SocketAddrV6::new(ip, port, sin6.sin6_flowinfo, sin6.sin6_scope_id).into())
+        }
+        _ => None,
+    }
+}
+
+/// Accept a connection with `accept4`, so the stream is
+/// non-blocking and close-on-exec from the start.
+pub(super) fn accept4(listener: &impl AsRawFd) -> std::io::Result<(StdTcpStream, SocketAddr)> {
+    let mut storage: sockaddr_storage = unsafe { zeroed() };
+    let mut len = size_of::<sockaddr_storage>() as socklen_t;
+    let flags = libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+
+    let fd = unsafe {
+        libc::accept4(
+            listener.as_raw_fd(),
+            &mut storage as *mut _ as *mut sockaddr,
+            &mut len,
+            flags,
+        )
+    };
+
+    if fd == -1 {
+        return Err(crate::os_err());
+    }
+
+    let stream = unsafe { StdTcpStream::from_raw_fd(fd) };
+    let addr = from_inner(&storage)
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidData, "invalid peer address"))?;
+
+    Ok((stream, addr))
+}
+
+// -----------------------------------------------------------------------------
+//     - Socket -
+// -----------------------------------------------------------------------------
 pub(super) struct Socket(pub c_int);
 
 impl Socket {
-    pub fn new(addr: Result<&SocketAddr>) -> Result<Self> {
-        let addr = addr?;
+    pub fn new(addr: &SocketAddr, ty: c_int) -> Result<Self> {
         let family = match addr {
             SocketAddr::V4(..) => libc::AF_INET,
             SocketAddr::V6(..) => libc::AF_INET6,
         };
 
-        let (addr_ptr, addr_len) = into_inner(&addr);
+        let flags = libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+        let socket = Self(unsafe { res!(libc::socket(family, ty | flags, 0)) });
+        Ok(socket)
+    }
 
-        let socket = Self(unsafe { res!(libc::socket(family, libc::SOCK_STREAM, 0)) });
-        setsockopt(&socket, libc::SOL_SOCKET, libc::SO_REUSEADDR, 1 as c_int)?;
-        setsockopt(&socket, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1 as c_int)?;
+    pub fn bind(&self, addr: &SocketAddr) -> Result<()> {
+        let (storage, len) = into_inner(addr);
+        let addr_ptr = &storage as *const _ as *const sockaddr;
+        let _ = res!(unsafe { libc::bind(self.as_inner(), addr_ptr, len) });
+        Ok(())
+    }
 
-        let _ = res!(unsafe { libc::bind(socket.as_inner(), addr_ptr, addr_len) });
-        let _ = res!(unsafe { libc::listen(socket.as_inner(), 128) });
+    pub fn listen(&self, backlog: c_int) -> Result<()> {
+        let _ = res!(unsafe { libc::listen(self.as_inner(), backlog) });
+        Ok(())
+    }
 
-        Ok(socket)
+    /// Start a non-blocking connect.
+    /// `EINPROGRESS` is not an error: the socket becomes writable once the
+    /// connection is established (or failed, see `SO_ERROR`).
+    pub fn connect(&self, addr: &SocketAddr) -> Result<()> {
+        let (storage, len) = into_inner(addr);
+        let addr_ptr = &storage as *const _ as *const sockaddr;
+        match unsafe { libc::connect(self.as_inner(), addr_ptr, len) } {
+            -1 => {
+                let err = crate::os_err();
+                match err.raw_os_error() {
+                    Some(libc::EINPROGRESS) => Ok(()),
+                    _ => Err(crate::Error::Io(err)),
+                }
+            }
+            _ => Ok(()),
+        }
     }
 
     fn as_inner(&self) -> libc::c_int {
         self.0
     }
 }
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl IntoRawFd for Socket {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.0;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Drop -
+// -----------------------------------------------------------------------------
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Socket builder -
+// -----------------------------------------------------------------------------
+/// Configure socket options before binding a listener or connecting a stream.
+///
+/// ```
+/// # use netlib::System;
+/// # use netlib::net::SocketBuilder;
+/// System::builder().finish();
+/// let listener = SocketBuilder::new()
+///     .backlog(1024)
+///     .nodelay(true)
+///     .bind("127.0.0.1:0")
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SocketBuilder {
+    backlog: c_int,
+    reuse_addr: bool,
+    reuse_port: bool,
+    nodelay: Option<bool>,
+    keepalive: Option<bool>,
+    keepalive_idle: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_count: Option<u32>,
+    linger: Option<Option<Duration>>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    only_v6: Option<bool>,
+    fastopen: Option<u32>,
+    defer_accept: Option<Duration>,
+    accept_budget: Option<usize>,
+    max_connections: Option<usize>,
+}
+
+impl SocketBuilder {
+    /// Create a new builder.
+    /// `SO_REUSEADDR` and `SO_REUSEPORT` are on by default and the backlog is 128.
+    pub fn new() -> Self {
+        Self {
+            backlog: 128,
+            reuse_addr: true,
+            reuse_port: true,
+            nodelay: None,
+            keepalive: None,
+            keepalive_idle: None,
+            keepalive_interval: None,
+            keepalive_count: None,
+            linger: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            only_v6: None,
+            fastopen: None,
+            defer_accept: None,
+            accept_budget: None,
+            max_connections: None,
+        }
+    }
+
+    /// The maximum length of the queue of pending connections.
+    /// The kernel caps it at `net.core.somaxconn`.
+    pub fn backlog(&mut self, backlog: u32) -> &mut Self {
+        self.backlog = c_int::try_from(backlog).unwrap_or(c_int::MAX);
+        self
+    }
+
+    /// Set `SO_REUSEADDR` on listeners.
+    pub fn reuse_addr(&mut self, reuse: bool) -> &mut Self {
+        self.reuse_addr = reuse;
+        self
+    }
+
+    /// Set `SO_REUSEPORT` on listeners.
+    pub fn reuse_port(&mut self, reuse: bool) -> &mut Self {
+        self.reuse_port = reuse;
+        self
+    }
+
+    /// Set `TCP_NODELAY`, disabling Nagle's algorithm.
+    pub fn nodelay(&mut self, nodelay: bool) -> &mut Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Set `SO_KEEPALIVE`.
+    pub fn keepalive(&mut self, keepalive: bool) -> &mut Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Idle time before the first keepalive probe is sent (`TCP_KEEPIDLE`),
+    /// rounded up to seconds. Setting this implies `keepalive(true)`.
+    pub fn keepalive_idle(&mut self, idle: Duration) -> &mut Self {
+        self.keepalive_idle = Some(idle);
+        self
+    }
+
+    /// Time between keepalive probes (`TCP_KEEPINTVL`), rounded up to
+    /// seconds. Setting this implies `keepalive(true)`.
+    pub fn keepalive_interval(&mut self, interval: Duration) -> &mut Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Number of unanswered probes before the connection is dropped (`TCP_KEEPCNT`).
+    /// Setting this implies `keepalive(true)`.
+    pub fn keepalive_count(&mut self, count: u32) -> &mut Self {
+        self.keepalive_count = Some(count);
+        self
+    }
+
+    /// Set `SO_LINGER`, rounded up to seconds. `None` disables lingering.
+    pub fn linger(&mut self, linger: Option<Duration>) -> &mut Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    /// Set `SO_SNDBUF`.
+    pub fn send_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set `SO_RCVBUF`.
+    pub fn recv_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set `IPV6_V6ONLY`. Ignored for IPv4 addresses.
+    pub fn only_v6(&mut self, only_v6: bool) -> &mut Self {
+        self.only_v6 = Some(only_v6);
+        self
+    }
+
+    /// Enable `TCP_FASTOPEN` on a listener with the given queue length.
+    pub fn fastopen(&mut self, queue_len: u32) -> &mut Self {
+        self.fastopen = Some(queue_len);
+        self
+    }
+
+    /// Set `TCP_DEFER_ACCEPT` on a listener: only wake up once data has arrived,
+    /// waiting at most `timeout` (rounded up to seconds).
+    pub fn defer_accept(&mut self, timeout: Duration) -> &mut Self {
+        self.defer_accept = Some(timeout);
+        self
+    }
+
+    /// The maximum number of connections a listener accepts per readiness event.
+    /// See `TcpListener::set_accept_budget`.
+    pub fn accept_budget(&mut self, budget: usize) -> &mut Self {
+        self.accept_budget = Some(budget);
+        self
+    }
+
+    /// Limit the number of active connections per listener.
+    /// See `TcpListener::set_max_connections`.
+    pub fn max_connections(&mut self, max: usize) -> &mut Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Bind a `TcpListener` using the first resolved address.
+    pub fn bind<A: ToSocketAddrs>(&self, addr: A) -> Result<TcpListener> {
+        let addr = resolve(addr)?[0];
+        let socket = self.listener_socket(&addr)?;
+        let listener = unsafe { StdTcpListener::from_raw_fd(socket.into_raw_fd()) };
+        self.listener(listener)
+    }
+
+    /// Bind a `TcpListener` to every resolved address, e.g. both `127.0.0.1`
+    /// and `::1` for `localhost`.
+    ///
+    /// When both IPv4 and IPv6 addresses are bound, `IPV6_V6ONLY` is set on the
+    /// IPv6 sockets (unless configured otherwise) so they don't clash.
+    /// If the port is zero, the port picked for the first address is reused
+    /// for the rest.
+    pub fn bind_all<A: ToSocketAddrs>(&self, addr: A) -> Result<TcpListeners> {
+        let mut addrs = resolve(addr)?;
+        addrs.dedup();
+
+        let dual_stack = addrs.iter().any(SocketAddr::is_ipv4) && addrs.iter().any(SocketAddr::is_ipv6);
+        let mut builder = self.clone();
+        if dual_stack && builder.only_v6.is_none() {
+            builder.only_v6(true);
+        }
+
+        let mut listeners = Vec::with_capacity(addrs.len());
+        let mut port = None;
+
+        for mut addr in addrs {
+            if let (0, Some(port)) = (addr.port(), port) {
+                addr.set_port(port);
+            }
+
+            let socket = builder.listener_socket(&addr)?;
+            let listener = unsafe { StdTcpListener::from_raw_fd(socket.into_raw_fd()) };
+            port = Some(listener.local_addr()?.port());
+            listeners.push(builder.listener(listener)?);
+        }
+
+        Ok(TcpListeners::new(listeners))
+    }
+
+    /// Start a non-blocking connect to the first resolved address.
+    /// The stream is armed for writing, as that is how the connection is
+    /// signalled as established.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<TcpStream> {
+        let addr = resolve(addr)?[0];
+        let socket = self.stream_socket(&addr)?;
+        let stream = unsafe { StdTcpStream::from_raw_fd(socket.into_raw_fd()) };
+        TcpStream::new(stream, Interest::ReadWrite)
+    }
+
+    fn listener(&self, listener: StdTcpListener) -> Result<TcpListener> {
+        let mut listener = TcpListener::new(listener)?;
+        if let Some(budget) = self.accept_budget {
+            listener.set_accept_budget(budget);
+        }
+        if let Some(max) = self.max_connections {
+            listener.set_max_connections(max)?;
+        }
+        Ok(listener)
+    }
+
+    pub(super) fn listener_socket(&self, addr: &SocketAddr) -> Result<Socket> {
+        let socket = Socket::new(addr, libc::SOCK_STREAM)?;
+        self.apply(&socket, addr)?;
+
+        if self.reuse_addr {
+            setsockopt(&socket, libc::SOL_SOCKET, libc::SO_REUSEADDR, 1 as c_int)?;
+        }
+
+        if self.reuse_port {
+            setsockopt(&socket, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1 as c_int)?;
+        }
+
+        if let Some(queue_len) = self.fastopen {
+            setsockopt(&socket, libc::IPPROTO_TCP, libc::TCP_FASTOPEN, int(queue_len, "TCP_FASTOPEN")?)?;
+        }
+
+        if let Some(timeout) = self.defer_accept {
+            setsockopt(&socket, libc::IPPROTO_TCP, libc::TCP_DEFER_ACCEPT, secs(timeout))?;
+        }
+
+        socket.bind(addr)?;
+        socket.listen(self.backlog)?;
+        Ok(socket)
+    }
+
+    pub(super) fn stream_socket(&self, addr: &SocketAddr) -> Result<Socket> {
+        let socket = Socket::new(addr, libc::SOCK_STREAM)?;
+        self.apply(&socket, addr)?;
+        socket.connect(addr)?;
+        Ok(socket)
+    }
+
+    // Options for both listeners and outgoing streams
+    fn apply(&self, socket: &Socket, addr: &SocketAddr) -> Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            setsockopt(socket, libc::IPPROTO_TCP, libc::TCP_NODELAY, nodelay as c_int)?;
+        }
+
+        let keepalive_opts = self.keepalive_idle.is_some()
+            || self.keepalive_interval.is_some()
+            || self.keepalive_count.is_some();
+
+        let keepalive = match self.keepalive {
+            None if keepalive_opts => Some(true),
+            keepalive => keepalive,
+        };
+
+        if let Some(keepalive) = keepalive {
+            setsockopt(socket, libc::SOL_SOCKET, libc::SO_KEEPALIVE, keepalive as c_int)?;
+        }
+
+        if let Some(idle) = self.keepalive_idle {
+            setsockopt(socket, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, secs(idle))?;
+        }
+
+        if let Some(interval) = self.keepalive_interval {
+            setsockopt(socket, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, secs(interval))?;
+        }
+
+        if let Some(count) = self.keepalive_count {
+            setsockopt(socket, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, int(count, "TCP_KEEPCNT")?)?;
+        }
+
+        if let Some(linger) = self.linger {
+            let payload = libc::linger {
+                l_onoff: linger.is_some() as c_int,
+                l_linger: linger.map(secs).unwrap_or(0),
+            };
+            setsockopt(socket, libc::SOL_SOCKET, libc::SO_LINGER, payload)?;
+        }
+
+        if let Some(size) = self.send_buffer_size {
+            setsockopt(socket, libc::SOL_SOCKET, libc::SO_SNDBUF, int(size, "SO_SNDBUF")?)?;
+        }
+
+        if let Some(size) = self.recv_buffer_size {
+            setsockopt(socket, libc::SOL_SOCKET, libc::SO_RCVBUF, int(size, "SO_RCVBUF")?)?;
+        }
+
+        if let (Some(only_v6), SocketAddr::V6(..)) = (self.only_v6, addr) {
+            setsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, only_v6 as c_int)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for SocketBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::System;
+
+    #[test]
+    fn options_are_applied() {
+        System::builder().finish();
+        let listener = SocketBuilder::new()
+            .nodelay(true)
+            .keepalive_idle(Duration::from_secs(30))
+            .keepalive_interval(Duration::from_millis(500))
+            .linger(Some(Duration::from_millis(1500)))
+            .recv_buffer_size(64 * 1024)
+            .bind("127.0.0.1:0")
+            .unwrap();
+
+        let nodelay: c_int = getsockopt(&listener, libc::IPPROTO_TCP, libc::TCP_NODELAY).unwrap();
+        let keepalive: c_int = getsockopt(&listener, libc::SOL_SOCKET, libc::SO_KEEPALIVE).unwrap();
+        let idle: c_int = getsockopt(&listener, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE).unwrap();
+        let interval: c_int = getsockopt(&listener, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL).unwrap();
+        let linger: libc::linger = getsockopt(&listener, libc::SOL_SOCKET, libc::SO_LINGER).unwrap();
+        assert_eq!(nodelay, 1);
+        assert_eq!(keepalive, 1);
+        assert_eq!(idle, 30);
+        // Sub-second parts round up
+        assert_eq!(interval, 1);
+        assert_eq!((linger.l_onoff, linger.l_linger), (1, 2));
+    }
+
+    #[test]
+    fn reuse_only_on_listeners() {
+        System::builder().finish();
+        let listener = SocketBuilder::new().bind("127.0.0.1:0").unwrap();
+        let stream = SocketBuilder::new().connect(listener.local_addr().unwrap()).unwrap();
+
+        let reuse: c_int = getsockopt(&listener, libc::SOL_SOCKET, libc::SO_REUSEADDR).unwrap();
+        assert_eq!(reuse, 1);
+        let reuse: c_int = getsockopt(&stream, libc::SOL_SOCKET, libc::SO_REUSEADDR).unwrap();
+        assert_eq!(reuse, 0);
+        let reuse: c_int = getsockopt(&stream, libc::SOL_SOCKET, libc::SO_REUSEPORT).unwrap();
+        assert_eq!(reuse, 0);
+    }
+
+    #[test]
+    fn oversized_option() {
+        System::builder().finish();
+        let res = SocketBuilder::new().send_buffer_size(usize::MAX).bind("127.0.0.1:0");
+        match res {
+            Err(crate::Error::Io(e)) => assert_eq!(e.kind(), ErrorKind::InvalidInput),
+            _ => panic!("expected InvalidInput"),
+        }
+
+        // The backlog is capped instead
+        assert!(SocketBuilder::new().backlog(u32::MAX).bind("127.0.0.1:0").is_ok());
+    }
+
+    #[test]
+    fn bind_all_shares_port() {
+        System::builder().finish();
+        let addrs: [SocketAddr; 2] = ["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()];
+        let listeners = SocketBuilder::new().bind_all(&addrs[..]).unwrap();
+        let local = listeners.local_addrs().unwrap();
+        assert_eq!(local.len(), 2);
+        assert_eq!(local[0].port(), local[1].port());
+    }
+
+    #[test]
+    fn bind_without_address() {
+        System::builder().finish();
+        let addrs: [SocketAddr; 0] = [];
+        assert!(SocketBuilder::new().bind(&addrs[..]).is_err());
+    }
+
+    #[test]
+    fn sockaddr_round_trip() {
+        let addrs: [SocketAddr; 2] = ["10.0.0.1:9000".parse().unwrap(), "[::1]:443".parse().unwrap()];
+        for addr in &addrs {
+            let (storage, _) = into_inner(addr);
+            assert_eq!(from_inner(&storage), Some(*addr));
+        }
+    }
+}
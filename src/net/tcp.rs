@@ -1,47 +1,465 @@
 use std::convert::TryFrom;
+use std::io::ErrorKind::WouldBlock;
 use std::net::{
     Shutdown, SocketAddr, TcpListener as StdTcpListener, TcpStream as StdTcpStream, ToSocketAddrs,
 };
-use std::os::unix::io::FromRawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, ThreadId};
 
-use super::socket::Socket;
-use crate::{Interest, PollReactor, Reaction, Reactor, Result};
+use super::socket::{accept4, SocketBuilder};
+use crate::{Event, Evented, Interest, PollReactor, Reaction, Reactor, Result, System};
+
+// -----------------------------------------------------------------------------
+//     - Connection tracker -
+// -----------------------------------------------------------------------------
+/// Counts the live connections of a listener with a connection limit.
+///
+/// The listener acquires a slot for every accepted stream and yields it
+/// along with the stream. The slot is released when it is dropped, so it
+/// should live as long as the connection. This wakes the listener if it
+/// paused accepting, even from another thread.
+#[derive(Debug, Clone)]
+pub struct ConnectionTracker {
+    inner: Arc<Tracked>,
+}
+
+#[derive(Debug)]
+struct Tracked {
+    active: AtomicUsize,
+    paused: AtomicBool,
+    evented: Evented,
+    // The thread whose system the eventfd is registered with
+    thread: ThreadId,
+}
+
+impl ConnectionTracker {
+    fn new() -> Result<Self> {
+        let inner = Tracked {
+            active: AtomicUsize::new(0),
+            paused: AtomicBool::new(false),
+            evented: Evented::new()?,
+            thread: thread::current().id(),
+        };
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// The number of connections currently accepted and not yet released.
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::Acquire)
+    }
+
+    fn acquire(&self) -> ConnectionSlot {
+        self.inner.active.fetch_add(1, Ordering::AcqRel);
+        ConnectionSlot(self.clone())
+    }
+
+    fn release(&self) {
+        let _ = self.inner.active.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+        if self.inner.paused.load(Ordering::Acquire) {
+            let _ = self.evented().poke();
+        }
+    }
+
+    fn evented(&self) -> Evented {
+        self.inner.evented
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        // Closing the eventfd takes it out of epoll. The id belongs to the
+        // listener's system, so it can only be given back on that thread.
+        unsafe { libc::close(self.evented.fd) };
+        if thread::current().id() == self.thread {
+            System::free(self.evented.reactor_id);
+        }
+    }
+}
+
+/// A connection's slot in the listener's connection limit,
+/// released when dropped.
+#[derive(Debug)]
+pub struct ConnectionSlot(ConnectionTracker);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// An accepted stream, the peer's address, and the stream's slot if the
+/// listener has a connection limit.
+pub type Accepted = (StdTcpStream, SocketAddr, Option<ConnectionSlot>);
+
+// -----------------------------------------------------------------------------
+//     - Accept stats -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AcceptStats {
+    /// Connections handed out by the listener.
+    pub accepted: u64,
+    /// Connections closed straight away because the process ran out of
+    /// file descriptors.
+    pub rejected: u64,
+    /// The number of times the listener stopped accepting.
+    pub paused: u64,
+}
 
 // -----------------------------------------------------------------------------
 //     - TcpListener -
 // -----------------------------------------------------------------------------
-pub type TcpListener = PollReactor<StdTcpListener>;
+const DEFAULT_ACCEPT_BUDGET: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pause {
+    Running,
+    Limit,
+    Manual,
+}
+
+pub struct TcpListener {
+    inner: PollReactor<StdTcpListener>,
+    accept_budget: usize,
+    accepted: usize,
+    max_connections: Option<usize>,
+    tracker: Option<ConnectionTracker>,
+    pause: Pause,
+    spare_fd: Option<RawFd>,
+    stats: AcceptStats,
+}
 
 impl TcpListener {
+    /// Bind a listener with the default socket options.
+    /// Use `SocketBuilder` to configure the socket.
     pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self> {
-        let addr = addr.to_socket_addrs()?.next().expect("Invalid address");
-        let socket = Socket::new(Ok(&addr))?; // and this
+        SocketBuilder::new().bind(addr)
+    }
+
+    /// Bind a listener to every resolved address with the default socket options.
+    /// See `SocketBuilder::bind_all`.
+    pub fn bind_all<A: ToSocketAddrs>(addr: A) -> Result<TcpListeners> {
+        SocketBuilder::new().bind_all(addr)
+    }
 
-        let listener = unsafe { StdTcpListener::from_raw_fd(socket.0) };
+    /// Register an existing listener with the `System`.
+    /// The listener is set to non-blocking.
+    pub fn new(listener: StdTcpListener) -> Result<Self> {
         listener.set_nonblocking(true)?;
-        Self::new(listener, Interest::Read)
+        let inst = Self {
+            inner: PollReactor::new(listener, Interest::Read)?,
+            accept_budget: DEFAULT_ACCEPT_BUDGET,
+            accepted: 0,
+            max_connections: None,
+            tracker: None,
+            pause: Pause::Running,
+            spare_fd: open_spare_fd(),
+            stats: AcceptStats::default(),
+        };
+        Ok(inst)
+    }
+
+    /// Adopt a listening socket, e.g. one inherited from another process.
+    ///
+    /// # Safety
+    ///
+    /// `fd` has to be an open listening TCP socket owned by the caller.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> Result<Self> {
+        Self::new(StdTcpListener::from_raw_fd(fd))
+    }
+
+    /// The maximum number of connections accepted per readiness event
+    /// before the listener yields to other reactors.
+    /// The remaining backlog is picked up on the next poll.
+    pub fn set_accept_budget(&mut self, budget: usize) {
+        self.accept_budget = budget.max(1);
+    }
+
+    /// Stop accepting once `max` connections are active.
+    /// Pending connections stay in the kernel backlog until the
+    /// `ConnectionSlot` of a connection is dropped.
+    pub fn set_max_connections(&mut self, max: usize) -> Result<ConnectionTracker> {
+        self.max_connections = Some(max);
+        let tracker = match self.tracker.take() {
+            Some(tracker) => tracker,
+            None => ConnectionTracker::new()?,
+        };
+        self.tracker = Some(tracker.clone());
+        Ok(tracker)
+    }
+
+    /// The tracker for active connections, if a connection limit is set.
+    pub fn tracker(&self) -> Option<ConnectionTracker> {
+        self.tracker.clone()
+    }
+
+    pub fn stats(&self) -> AcceptStats {
+        self.stats
+    }
+
+    /// Stop accepting connections until `resume` is called.
+    pub fn pause(&mut self) {
+        self.set_paused(Pause::Manual);
+    }
+
+    /// Start accepting connections again.
+    pub fn resume(&mut self) -> Result<()> {
+        self.set_paused(Pause::Running);
+        self.rearm()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause != Pause::Running
+    }
+
+    pub fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    /// True if the event belongs to this listener
+    /// (either the socket or the connection tracker).
+    pub fn owns(&self, ev: &Event) -> bool {
+        ev.owner == self.inner.id
+            || self.tracker.as_ref().map(|t| t.evented().reactor_id) == Some(ev.owner)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.inner.as_ref().local_addr()?)
+    }
+
+    fn at_limit(&self) -> bool {
+        match (self.max_connections, &self.tracker) {
+            (Some(max), Some(tracker)) => tracker.active() >= max,
+            _ => false,
+        }
+    }
+
+    fn set_paused(&mut self, pause: Pause) {
+        if pause != Pause::Running && self.pause == Pause::Running {
+            self.stats.paused += 1;
+        }
+
+        self.pause = pause;
+        self.accepted = 0;
+        if let Some(tracker) = &self.tracker {
+            tracker.inner.paused.store(pause == Pause::Limit, Ordering::Release);
+        }
+    }
+
+    fn accept(&mut self, ev: Event) -> Reaction<Result<Accepted>> {
+        if self.is_paused() {
+            return Reaction::Continue;
+        }
+
+        if self.at_limit() {
+            return self.pause_at_limit();
+        }
+
+        match accept4(self.inner.as_ref()) {
+            Ok((stream, addr)) => {
+                self.stats.accepted += 1;
+                let slot = self.tracker.as_ref().map(ConnectionTracker::acquire);
+
+                match self.next(ev) {
+                    Ok(()) => Reaction::Value(Ok((stream, addr, slot))),
+                    Err(e) => Reaction::Value(Err(e)),
+                }
+            }
+            Err(ref e) if e.kind() == WouldBlock => match self.rearm() {
+                Err(e) => Reaction::Value(Err(e)),
+                Ok(()) => Reaction::Continue,
+            },
+            Err(e) if is_fd_exhaustion(&e) => {
+                self.shed();
+                let res = match self.tracker.as_ref().map(ConnectionTracker::active) {
+                    // Wait for a connection to be released
+                    Some(active) if active > 0 => self.pause_until_release(active),
+                    // Nothing to wait for
+                    _ => self.next(ev),
+                };
+
+                match res {
+                    Ok(()) => Reaction::Value(Err(e.into())),
+                    Err(e) => Reaction::Value(Err(e)),
+                }
+            }
+            Err(e) => match self.rearm() {
+                Err(e) => Reaction::Value(Err(e)),
+                Ok(()) => Reaction::Value(Err(e.into())),
+            },
+        }
+    }
+
+    fn pause_at_limit(&mut self) -> Reaction<Result<Accepted>> {
+        self.set_paused(Pause::Limit);
+
+        // A connection could have been released before
+        // the tracker knew the listener was paused.
+        if !self.at_limit() {
+            if let Err(e) = self.resume() {
+                return Reaction::Value(Err(e));
+            }
+        }
+
+        Reaction::Continue
+    }
+
+    fn pause_until_release(&mut self, active: usize) -> Result<()> {
+        self.set_paused(Pause::Limit);
+
+        // A connection could have been released before
+        // the tracker knew the listener was paused.
+        match self.tracker.as_ref().map_or(0, ConnectionTracker::active) < active {
+            true => self.resume(),
+            false => Ok(()),
+        }
+    }
+
+    fn wake(&mut self) -> Reaction<Result<Accepted>> {
+        if let Some(tracker) = &self.tracker {
+            if let Err(e) = tracker.evented().consume_event() {
+                return Reaction::Value(Err(e));
+            }
+        }
+
+        match self.pause == Pause::Limit && !self.at_limit() {
+            true => match self.resume() {
+                Err(e) => Reaction::Value(Err(e)),
+                Ok(()) => Reaction::Continue,
+            },
+            false => Reaction::Continue,
+        }
+    }
+
+    // Accept the next connection straight away if there is budget left,
+    // otherwise wait for epoll.
+    fn next(&mut self, ev: Event) -> Result<()> {
+        self.accepted += 1;
+        match self.accepted < self.accept_budget {
+            // Have the system call us again rather than
+            // waiting for the next epoll event.
+            true => {
+                System::defer(ev);
+                Ok(())
+            }
+            false => self.rearm(),
+        }
+    }
+
+    // Out of file descriptors: free up the spare one to accept
+    // and immediately close the pending connection, so it is
+    // not left firing events in the backlog.
+    fn shed(&mut self) {
+        if let Some(fd) = self.spare_fd.take() {
+            unsafe { libc::close(fd) };
+        }
+
+        if let Ok((stream, _)) = accept4(self.inner.as_ref()) {
+            drop(stream);
+            self.stats.rejected += 1;
+        }
+
+        self.spare_fd = open_spare_fd();
+    }
+
+    fn rearm(&mut self) -> Result<()> {
+        self.accepted = 0;
+        self.inner.rearm(Interest::Read)
+    }
+}
+
+fn open_spare_fd() -> Option<RawFd> {
+    let path = b"/dev/null\0";
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+    match unsafe { libc::open(path.as_ptr() as *const libc::c_char, flags) } {
+        -1 => None,
+        fd => Some(fd),
+    }
+}
+
+fn is_fd_exhaustion(e: &std::io::Error) -> bool {
+    match e.raw_os_error() {
+        Some(libc::EMFILE) | Some(libc::ENFILE) => true,
+        _ => false,
     }
 }
 
 impl Reactor for TcpListener {
     type Input = ();
-    type Output = Result<(StdTcpStream, SocketAddr)>;
+    type Output = Result<Accepted>;
 
     fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
         match reaction {
-            Reaction::Event(ev) if ev.owner != self.id => Reaction::Event(ev),
-            Reaction::Event(ev) if ev.read => {
-                match self.rearm(Interest::Read) {
-                    Err(e) => Reaction::Value(Err(e)),
-                    Ok(_) => {
-                        let val = match self.as_mut().accept() {
-                            Err(e) => Err(crate::Error::Io(e)),
-                            Ok(s) => Ok(s)
-                        };
-                        Reaction::Value(val)
-                    }
-                }
-            }
+            Reaction::Event(ev) if !self.owns(&ev) => Reaction::Event(ev),
+            Reaction::Event(ev) if ev.owner != self.inner.id => self.wake(),
+            Reaction::Event(ev) if ev.read => self.accept(ev),
+            _ => Reaction::Continue,
+        }
+    }
+}
+
+impl AsRawFd for TcpListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Drop -
+// -----------------------------------------------------------------------------
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        if let Some(fd) = self.spare_fd.take() {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+impl AsRef<StdTcpListener> for TcpListener {
+    fn as_ref(&self) -> &StdTcpListener {
+        self.inner.as_ref()
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - TcpListeners -
+// -----------------------------------------------------------------------------
+/// A set of listeners acting as one reactor,
+/// typically one per address family.
+pub struct TcpListeners {
+    inner: Vec<TcpListener>,
+}
+
+impl TcpListeners {
+    pub(super) fn new(inner: Vec<TcpListener>) -> Self {
+        Self { inner }
+    }
+
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        let addrs = self
+            .inner
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(addrs)
+    }
+
+    pub fn listeners(&self) -> &[TcpListener] {
+        &self.inner
+    }
+}
+
+impl Reactor for TcpListeners {
+    type Input = ();
+    type Output = Result<Accepted>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(ev) => match self.inner.iter_mut().find(|l| l.owns(&ev)) {
+                Some(listener) => listener.react(Reaction::Event(ev)),
+                None => Reaction::Event(ev),
+            },
             _ => Reaction::Continue,
         }
     }
@@ -53,6 +471,12 @@ impl Reactor for TcpListener {
 pub type TcpStream = PollReactor<StdTcpStream>;
 
 impl TcpStream {
+    /// Start a non-blocking connect with the default socket options.
+    /// Use `SocketBuilder` to configure the socket.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        SocketBuilder::new().connect(addr)
+    }
+
     pub fn close(&mut self) -> Result<()> {
         self.as_mut().shutdown(Shutdown::Both)?;
         Ok(())
@@ -66,3 +490,70 @@ impl TryFrom<StdTcpStream> for TcpStream {
         TcpStream::new(s, Interest::Read)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpStream as StdTcpStream;
+
+    #[test]
+    fn drain_backlog() {
+        System::builder().finish();
+        let mut listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _clients = (0..3).map(|_| StdTcpStream::connect(addr).unwrap()).collect::<Vec<_>>();
+
+        let ev = Event { read: true, write: false, owner: listener.id() };
+        for _ in 0..3 {
+            match listener.react(Reaction::Event(ev)) {
+                Reaction::Value(Ok((stream, _, None))) => {
+                    let flags = unsafe { libc::fcntl(stream.as_raw_fd(), libc::F_GETFL) };
+                    assert_ne!(flags & libc::O_NONBLOCK, 0);
+                }
+                r => panic!("expected a stream, got {:?}", r),
+            }
+        }
+
+        assert!(matches!(listener.react(Reaction::Event(ev)), Reaction::Continue));
+    }
+
+    #[test]
+    fn pause_at_connection_limit() {
+        System::builder().finish();
+        let mut listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tracker = listener.set_max_connections(1).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _clients = (0..2).map(|_| StdTcpStream::connect(addr).unwrap()).collect::<Vec<_>>();
+
+        let ev = Event { read: true, write: false, owner: listener.id() };
+        let slot = match listener.react(Reaction::Event(ev)) {
+            Reaction::Value(Ok((_, _, slot))) => slot.unwrap(),
+            r => panic!("expected a stream, got {:?}", r),
+        };
+        assert!(matches!(listener.react(Reaction::Event(ev)), Reaction::Continue));
+        assert!(listener.is_paused());
+        assert_eq!(tracker.active(), 1);
+
+        drop(slot);
+        let wake = Event { read: true, write: false, owner: tracker.evented().reactor_id };
+        assert!(matches!(listener.react(Reaction::Event(wake)), Reaction::Continue));
+        assert!(!listener.is_paused());
+
+        assert!(matches!(listener.react(Reaction::Event(ev)), Reaction::Value(Ok((_, _, Some(_))))));
+        assert_eq!(listener.stats().accepted, 2);
+        assert_eq!(listener.stats().paused, 1);
+    }
+
+    #[test]
+    fn tracker_is_freed() {
+        System::builder().finish();
+        let mut listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tracker = listener.set_max_connections(1).unwrap();
+        let id = tracker.evented().reactor_id;
+
+        // Still held by the listener
+        drop(tracker);
+        drop(listener);
+        assert_eq!(System::reserve(), id);
+    }
+}
@@ -0,0 +1,51 @@
+use std::io::{self, ErrorKind::WouldBlock};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket as StdUdpSocket};
+use std::os::unix::io::{FromRawFd, RawFd};
+
+use crate::{Interest, PollReactor, Result};
+
+// -----------------------------------------------------------------------------
+//     - UdpSocket -
+// -----------------------------------------------------------------------------
+pub type UdpSocket = PollReactor<StdUdpSocket>;
+
+impl UdpSocket {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let socket = StdUdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Self::new(socket, Interest::Read)
+    }
+
+    /// Adopt a datagram socket, e.g. one inherited from another process.
+    ///
+    /// # Safety
+    ///
+    /// `fd` has to be an open UDP socket owned by the caller.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> Result<Self> {
+        let socket = StdUdpSocket::from_raw_fd(fd);
+        socket.set_nonblocking(true)?;
+        Self::new(socket, Interest::Read)
+    }
+
+    /// Receive a datagram, rearming on `WouldBlock`.
+    pub fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let res = self.as_mut().recv_from(buf);
+        if let Err(ref e) = res {
+            if e.kind() == WouldBlock {
+                self.read_blocked();
+            }
+        }
+        res
+    }
+
+    /// Send a datagram, rearming on `WouldBlock`.
+    pub fn send_to<A: ToSocketAddrs>(&mut self, buf: &[u8], addr: A) -> io::Result<usize> {
+        let res = self.as_mut().send_to(buf, addr);
+        if let Err(ref e) = res {
+            if e.kind() == WouldBlock {
+                self.write_blocked();
+            }
+        }
+        res
+    }
+}
@@ -1,22 +1,195 @@
 use std::convert::TryFrom;
-use std::path::Path;
+use std::io;
+use std::mem::{size_of, zeroed};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::net::Shutdown;
-use std::os::unix::net::{UnixStream as StdUnixStream, UnixListener as StdUnixListener, SocketAddr};
-use std::os::unix::io::FromRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixStream as StdUnixStream, UnixListener as StdUnixListener, UnixDatagram as StdUnixDatagram, SocketAddr};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr;
 
-use super::socket::Socket;
-use crate::{Interest, PollReactor, Reaction, Reactor, Result};
+use libc::{c_int, c_void};
+
+use super::socket::getsockopt;
+use crate::{res, Interest, PollReactor, Reaction, Reactor, Result};
+
+/// The maximum number of file descriptors the kernel accepts in one message.
+pub const MAX_FDS: usize = 253;
+
+// -----------------------------------------------------------------------------
+//     - UnixListener builder -
+// -----------------------------------------------------------------------------
+/// Options for binding a `UnixListener`.
+///
+/// ```
+/// # use netlib::System;
+/// # use netlib::net::uds::UnixListenerBuilder;
+/// # let path = std::env::temp_dir().join(format!("netlib-doc-{}", std::process::id()));
+/// System::builder().finish();
+/// let listener = UnixListenerBuilder::new()
+///     .remove_stale(true)
+///     .mode(0o660)
+///     .unlink_on_drop(true)
+///     .bind(&path)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct UnixListenerBuilder {
+    remove_stale: bool,
+    unlink_on_drop: bool,
+    mode: Option<u32>,
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    backlog: Option<c_int>,
+}
+
+impl UnixListenerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// If a socket file already exists at the path, try to connect to it.
+    /// If nothing is listening, the file is left over from a previous run
+    /// and is removed. If something is listening, binding fails with `AddrInUse`.
+    /// Files that aren't sockets are never removed.
+    pub fn remove_stale(&mut self, remove: bool) -> &mut Self {
+        self.remove_stale = remove;
+        self
+    }
+
+    /// Remove the socket file when the listener is dropped,
+    /// unless it has been replaced by another socket in the meantime.
+    pub fn unlink_on_drop(&mut self, unlink: bool) -> &mut Self {
+        self.unlink_on_drop = unlink;
+        self
+    }
+
+    /// File permissions of the socket, e.g. `0o660`.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Owner and group of the socket file.
+    /// `None` leaves the respective id unchanged.
+    pub fn owner(&mut self, uid: Option<libc::uid_t>, gid: Option<libc::gid_t>) -> &mut Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// The maximum length of the queue of pending connections.
+    pub fn backlog(&mut self, backlog: u32) -> &mut Self {
+        self.backlog = Some(c_int::try_from(backlog).unwrap_or(c_int::MAX));
+        self
+    }
+
+    pub fn bind<P: AsRef<Path>>(&self, path: P) -> Result<UnixListener> {
+        let path = path.as_ref();
+        if self.remove_stale {
+            remove_stale(path)?;
+        }
+
+        let (addr, len) = sockaddr_un(path)?;
+        let flags = libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+        let fd = res!(unsafe { libc::socket(libc::AF_UNIX, flags, 0) });
+        let listener = unsafe { StdUnixListener::from_raw_fd(fd) };
+
+        let addr_ptr = &addr as *const _ as *const libc::sockaddr;
+        let _ = res!(unsafe { libc::bind(fd, addr_ptr, len) });
+
+        // The socket file is ours now: don't leave it behind on failure
+        self.listen(path, listener).map_err(|e| {
+            let _ = fs::remove_file(path);
+            e
+        })
+    }
+
+    fn listen(&self, path: &Path, listener: StdUnixListener) -> Result<UnixListener> {
+        // Permissions are set before `listen` so no one can connect
+        // while the defaults are still in place.
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+
+        if self.uid.is_some() || self.gid.is_some() {
+            std::os::unix::fs::chown(path, self.uid, self.gid)?;
+        }
+
+        let _ = res!(unsafe { libc::listen(listener.as_raw_fd(), self.backlog.unwrap_or(128)) });
+
+        let mut listener = UnixListener::from_std(listener)?;
+        if self.unlink_on_drop {
+            let meta = fs::metadata(path)?;
+            listener.unlink = Some((path.to_path_buf(), meta.dev(), meta.ino()));
+        }
+
+        Ok(listener)
+    }
+}
+
+fn remove_stale(path: &Path) -> Result<()> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    if !meta.file_type().is_socket() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "path exists and is not a socket").into());
+    }
+
+    match StdUnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AddrInUse, "socket is in use").into()),
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            fs::remove_file(path)?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
 
 // -----------------------------------------------------------------------------
 //     - UnixListener -
 // -----------------------------------------------------------------------------
-pub type UnixListener = PollReactor<StdUnixListener>;
+pub struct UnixListener {
+    inner: PollReactor<StdUnixListener>,
+    unlink: Option<(PathBuf, u64, u64)>,
+}
 
 impl UnixListener {
+    /// Bind a listener with the default options.
+    /// Use `UnixListenerBuilder` to configure it.
     pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let listener = StdUnixListener::bind(path)?;
+        Self::from_std(StdUnixListener::bind(path)?)
+    }
+
+    /// Adopt a listening socket, e.g. one inherited from another process.
+    ///
+    /// # Safety
+    ///
+    /// `fd` has to be an open listening Unix stream socket owned by the caller.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> Result<Self> {
+        Self::from_std(StdUnixListener::from_raw_fd(fd))
+    }
+
+    fn from_std(listener: StdUnixListener) -> Result<Self> {
         listener.set_nonblocking(true)?;
-        Self::new(listener, Interest::Read)
+        let inst = Self {
+            inner: PollReactor::new(listener, Interest::Read)?,
+            unlink: None,
+        };
+        Ok(inst)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.inner.as_ref().local_addr()?)
     }
 }
 
@@ -26,12 +199,12 @@ impl Reactor for UnixListener {
 
     fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
         match reaction {
-            Reaction::Event(ev) if ev.owner != self.id => Reaction::Event(ev),
+            Reaction::Event(ev) if ev.owner != self.inner.id => Reaction::Event(ev),
             Reaction::Event(ev) if ev.read => {
-                match self.rearm(Interest::Read) {
+                match self.inner.rearm(Interest::Read) {
                     Err(e) => Reaction::Value(Err(e)),
                     Ok(_) => {
-                        let val = match self.as_mut().accept() {
+                        let val = match self.inner.as_mut().accept() {
                             Err(e) => Err(crate::Error::Io(e)),
                             Ok(s) => Ok(s)
                         };
@@ -44,6 +217,34 @@ impl Reactor for UnixListener {
     }
 }
 
+impl AsRawFd for UnixListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl AsRef<StdUnixListener> for UnixListener {
+    fn as_ref(&self) -> &StdUnixListener {
+        self.inner.as_ref()
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Drop -
+// -----------------------------------------------------------------------------
+impl Drop for UnixListener {
+    fn drop(&mut self) {
+        if let Some((path, dev, ino)) = self.unlink.take() {
+            match fs::symlink_metadata(&path) {
+                Ok(meta) if meta.dev() == dev && meta.ino() == ino => {
+                    let _ = fs::remove_file(path);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
 // -----------------------------------------------------------------------------
 //     - UnixStream -
 // -----------------------------------------------------------------------------
@@ -54,6 +255,136 @@ impl UnixStream {
         self.as_mut().shutdown(Shutdown::Both)?;
         Ok(())
     }
+
+    /// Send `buf` along with the file descriptors in `fds` (`SCM_RIGHTS`).
+    /// At least one byte has to be sent for the descriptors to arrive.
+    ///
+    /// The descriptors are duplicated into the receiving process;
+    /// the caller still owns (and should close) its copies.
+    pub fn send_fds(&mut self, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+        let res = send_fds(self.as_raw_fd(), buf, fds);
+        self.write_result(&res);
+        res
+    }
+
+    /// Receive into `buf`, appending any file descriptors passed
+    /// along with the data to `fds`.
+    ///
+    /// The received descriptors are close-on-exec and owned by the caller.
+    pub fn recv_fds(&mut self, buf: &mut [u8], fds: &mut Vec<RawFd>) -> io::Result<usize> {
+        let res = recv_fds(self.as_raw_fd(), buf, fds);
+        self.read_result(&res);
+        res
+    }
+
+    /// The credentials of the process on the other end,
+    /// as they were when the connection was made (`SO_PEERCRED`).
+    pub fn peer_cred(&self) -> Result<PeerCred> {
+        let cred: libc::ucred = getsockopt(self, libc::SOL_SOCKET, libc::SO_PEERCRED)?;
+        let cred = PeerCred {
+            pid: cred.pid,
+            uid: cred.uid,
+            gid: cred.gid,
+        };
+        Ok(cred)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Peer credentials -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeerCred {
+    pub pid: libc::pid_t,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+// -----------------------------------------------------------------------------
+//     - Fd passing -
+// -----------------------------------------------------------------------------
+fn cmsg_space(fd_count: usize) -> usize {
+    unsafe { libc::CMSG_SPACE((fd_count * size_of::<c_int>()) as u32) as usize }
+}
+
+pub(crate) fn send_fds(fd: RawFd, buf: &[u8], fds: &[RawFd]) -> io::Result<usize> {
+    if fds.len() > MAX_FDS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many file descriptors"));
+    }
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+
+    // u64 to keep the control buffer aligned for `cmsghdr`
+    let mut control = vec![0u64; (cmsg_space(fds.len()) + 7) / 8];
+
+    let mut msg: libc::msghdr = unsafe { zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if !fds.is_empty() {
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = cmsg_space(fds.len()) as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN((fds.len() * size_of::<c_int>()) as u32) as _;
+            ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut c_int, fds.len());
+        }
+    }
+
+    match unsafe { libc::sendmsg(fd, &msg, libc::MSG_NOSIGNAL) } {
+        -1 => Err(crate::os_err()),
+        n => Ok(n as usize),
+    }
+}
+
+pub(crate) fn recv_fds(fd: RawFd, buf: &mut [u8], fds: &mut Vec<RawFd>) -> io::Result<usize> {
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut control = vec![0u64; (cmsg_space(MAX_FDS) + 7) / 8];
+
+    let mut msg: libc::msghdr = unsafe { zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = cmsg_space(MAX_FDS) as _;
+
+    let n = match unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_CMSG_CLOEXEC) } {
+        -1 => return Err(crate::os_err()),
+        n => n as usize,
+    };
+
+    let received = fds.len();
+
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const c_int;
+                let len = (*cmsg).cmsg_len as usize - (data as usize - cmsg as usize);
+                for i in 0..len / size_of::<c_int>() {
+                    fds.push(ptr::read_unaligned(data.add(i)));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        // Some were lost, don't hand out the rest
+        fds.drain(received..).for_each(|fd| unsafe { libc::close(fd); });
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "file descriptors truncated"));
+    }
+
+    Ok(n)
 }
 
 impl TryFrom<StdUnixStream> for UnixStream {
@@ -64,3 +395,439 @@ impl TryFrom<StdUnixStream> for UnixStream {
     }
 }
 
+
+// -----------------------------------------------------------------------------
+//     - UnixDatagram -
+// -----------------------------------------------------------------------------
+pub type UnixDatagram = PollReactor<StdUnixDatagram>;
+
+impl UnixDatagram {
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_std(StdUnixDatagram::bind(path)?)
+    }
+
+    /// A socket not bound to any address, used for sending.
+    pub fn unbound() -> Result<Self> {
+        Self::from_std(StdUnixDatagram::unbound()?)
+    }
+
+    /// A pair of connected sockets.
+    pub fn pair() -> Result<(Self, Self)> {
+        let (a, b) = StdUnixDatagram::pair()?;
+        Ok((Self::from_std(a)?, Self::from_std(b)?))
+    }
+
+    fn from_std(socket: StdUnixDatagram) -> Result<Self> {
+        socket.set_nonblocking(true)?;
+        Self::new(socket, Interest::Read)
+    }
+
+    /// Connect the socket so `send` and `recv` can be used.
+    pub fn connect<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.as_ref().connect(path)?;
+        Ok(())
+    }
+
+    /// Receive a datagram, rearming on `WouldBlock`.
+    pub fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.as_mut().recv(buf);
+        self.datagram_result(res.as_ref().err(), true);
+        res
+    }
+
+    /// Receive a datagram and the address it came from, rearming on `WouldBlock`.
+    pub fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let res = self.as_mut().recv_from(buf);
+        self.datagram_result(res.as_ref().err(), true);
+        res
+    }
+
+    /// Send a datagram to the connected peer, rearming on `WouldBlock`.
+    pub fn send(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.as_mut().send(buf);
+        self.datagram_result(res.as_ref().err(), false);
+        res
+    }
+
+    /// Send a datagram to `path`, rearming on `WouldBlock`.
+    pub fn send_to<P: AsRef<Path>>(&mut self, buf: &[u8], path: P) -> io::Result<usize> {
+        let res = self.as_mut().send_to(buf, path);
+        self.datagram_result(res.as_ref().err(), false);
+        res
+    }
+
+    // An empty datagram is not the end of the stream,
+    // so only `WouldBlock` changes the readiness.
+    fn datagram_result(&mut self, err: Option<&io::Error>, read: bool) {
+        match err {
+            Some(e) if e.kind() == io::ErrorKind::WouldBlock && read => self.read_blocked(),
+            Some(e) if e.kind() == io::ErrorKind::WouldBlock => self.write_blocked(),
+            _ => {}
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Sequenced packet sockets -
+// -----------------------------------------------------------------------------
+fn sockaddr_un(path: &Path) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+    let mut addr: libc::sockaddr_un = unsafe { zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "path too long for a unix socket"));
+    }
+
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+
+    let len = size_of::<libc::sa_family_t>() + bytes.len() + 1;
+    Ok((addr, len as libc::socklen_t))
+}
+
+fn seqpacket_socket() -> io::Result<RawFd> {
+    let flags = libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+    match unsafe { libc::socket(libc::AF_UNIX, flags, 0) } {
+        -1 => Err(crate::os_err()),
+        fd => Ok(fd),
+    }
+}
+
+/// A connected `SOCK_SEQPACKET` socket.
+/// Every `write` sends one message and every `read` receives one;
+/// a message larger than the read buffer is truncated.
+/// `read` skips empty messages, as it returns 0 only once the peer has
+/// shut down; use `recv` to receive them.
+#[derive(Debug)]
+pub struct SeqPacketSocket(RawFd);
+
+impl SeqPacketSocket {
+    /// Receive one message, or `None` once the peer has shut down.
+    ///
+    /// `recv` returns 0 both for an empty message and at the end of the
+    /// stream, so the socket is polled to tell them apart: an empty
+    /// message sent right before the peer shuts down reads as the end.
+    pub fn recv(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let p = buf.as_mut_ptr() as *mut c_void;
+        match unsafe { libc::recv(self.0, p, buf.len(), 0) } {
+            -1 => Err(crate::os_err()),
+            0 if !self.peer_shut_down()? => Ok(Some(0)),
+            0 => Ok(None),
+            n => Ok(Some(n as usize)),
+        }
+    }
+
+    fn peer_shut_down(&self) -> io::Result<bool> {
+        let mut fd = libc::pollfd { fd: self.0, events: libc::POLLRDHUP, revents: 0 };
+        match unsafe { libc::poll(&mut fd, 1, 0) } {
+            -1 => Err(crate::os_err()),
+            _ => Ok(fd.revents & (libc::POLLRDHUP | libc::POLLHUP) != 0),
+        }
+    }
+}
+
+/// A listening `SOCK_SEQPACKET` socket.
+#[derive(Debug)]
+pub struct SeqPacketListenerSocket(RawFd);
+
+impl io::Read for SeqPacketSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            match self.recv(buf)? {
+                Some(0) => continue,
+                Some(n) => break Ok(n),
+                None => break Ok(0),
+            }
+        }
+    }
+}
+
+impl io::Write for SeqPacketSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let p = buf.as_ptr() as *const c_void;
+        match unsafe { libc::send(self.0, p, buf.len(), libc::MSG_NOSIGNAL) } {
+            -1 => Err(crate::os_err()),
+            n => Ok(n as usize),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for SeqPacketSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl AsRawFd for SeqPacketListenerSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for SeqPacketSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+impl Drop for SeqPacketListenerSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - SeqPacketListener -
+// -----------------------------------------------------------------------------
+pub type SeqPacketListener = PollReactor<SeqPacketListenerSocket>;
+
+impl SeqPacketListener {
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let (addr, len) = sockaddr_un(path.as_ref())?;
+        let socket = SeqPacketListenerSocket(seqpacket_socket()?);
+        let addr_ptr = &addr as *const _ as *const libc::sockaddr;
+        let _ = res!(unsafe { libc::bind(socket.0, addr_ptr, len) });
+        let _ = res!(unsafe { libc::listen(socket.0, 128) });
+        Self::new(socket, Interest::Read)
+    }
+
+    fn accept(&mut self) -> io::Result<SeqPacketSocket> {
+        let flags = libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+        let fd = unsafe { libc::accept4(self.as_raw_fd(), ptr::null_mut(), ptr::null_mut(), flags) };
+        match fd {
+            -1 => Err(crate::os_err()),
+            fd => Ok(SeqPacketSocket(fd)),
+        }
+    }
+}
+
+impl Reactor for SeqPacketListener {
+    type Input = ();
+    type Output = Result<SeqPacketSocket>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(ev) if ev.owner != self.id => Reaction::Event(ev),
+            Reaction::Event(ev) if ev.read => {
+                match self.rearm(Interest::Read) {
+                    Err(e) => Reaction::Value(Err(e)),
+                    Ok(_) => {
+                        let val = match self.accept() {
+                            Err(e) => Err(crate::Error::Io(e)),
+                            Ok(s) => Ok(s)
+                        };
+                        Reaction::Value(val)
+                    }
+                }
+            }
+            _ => Reaction::Continue,
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - SeqPacketStream -
+// -----------------------------------------------------------------------------
+pub type SeqPacketStream = PollReactor<SeqPacketSocket>;
+
+impl SeqPacketStream {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let (addr, len) = sockaddr_un(path.as_ref())?;
+        let socket = SeqPacketSocket(seqpacket_socket()?);
+        let addr_ptr = &addr as *const _ as *const libc::sockaddr;
+        let _ = res!(unsafe { libc::connect(socket.0, addr_ptr, len) });
+        Self::new(socket, Interest::ReadWrite)
+    }
+
+    /// A pair of connected sockets.
+    pub fn pair() -> Result<(Self, Self)> {
+        let mut fds = [0; 2];
+        let flags = libc::SOCK_SEQPACKET | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+        let _ = res!(unsafe { libc::socketpair(libc::AF_UNIX, flags, 0, fds.as_mut_ptr()) });
+        let (a, b) = (SeqPacketSocket(fds[0]), SeqPacketSocket(fds[1]));
+        Ok((Self::try_from(a)?, Self::try_from(b)?))
+    }
+
+    /// Receive one message, or `None` once the peer has shut down.
+    /// See `SeqPacketSocket::recv`.
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let res = self.as_mut().recv(buf);
+        match &res {
+            Ok(None) => self.read_result(&Ok(0)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.read_blocked(),
+            _ => {}
+        }
+        Ok(res?)
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        let _ = res!(unsafe { libc::shutdown(self.as_raw_fd(), libc::SHUT_RDWR) });
+        Ok(())
+    }
+}
+
+impl TryFrom<SeqPacketSocket> for SeqPacketStream {
+    type Error = crate::Error;
+
+    fn try_from(s: SeqPacketSocket) -> Result<Self> {
+        SeqPacketStream::new(s, Interest::Read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use crate::System;
+
+    #[test]
+    fn pass_fd() {
+        System::builder().finish();
+        let (a, b) = StdUnixStream::pair().unwrap();
+        let mut a = UnixStream::try_from(a).unwrap();
+        let mut b = UnixStream::try_from(b).unwrap();
+
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+        let mut reader = unsafe { File::from_raw_fd(pipe[0]) };
+        let writer = unsafe { File::from_raw_fd(pipe[1]) };
+
+        assert_eq!(a.send_fds(b"x", &[writer.as_raw_fd()]).unwrap(), 1);
+        drop(writer);
+
+        let mut buf = [0u8; 4];
+        let mut fds = Vec::new();
+        assert_eq!(b.recv_fds(&mut buf, &mut fds).unwrap(), 1);
+        assert_eq!(fds.len(), 1);
+
+        let mut received = unsafe { File::from_raw_fd(fds[0]) };
+        received.write_all(b"hi").unwrap();
+        drop(received);
+
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hi");
+
+        let cred = a.peer_cred().unwrap();
+        assert_eq!(cred.pid, std::process::id() as libc::pid_t);
+    }
+
+    #[test]
+    fn too_many_fds() {
+        System::builder().finish();
+        let (a, b) = StdUnixStream::pair().unwrap();
+        let mut a = UnixStream::try_from(a).unwrap();
+        let mut b = UnixStream::try_from(b).unwrap();
+        let file = File::open("/dev/null").unwrap();
+
+        let err = a.send_fds(b"x", &vec![file.as_raw_fd(); MAX_FDS + 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Credentials take up part of the control buffer,
+        // leaving no room for all of the file descriptors
+        let on: c_int = 1;
+        let on_ptr = &on as *const c_int as *const c_void;
+        let len = size_of::<c_int>() as libc::socklen_t;
+        assert_eq!(unsafe { libc::setsockopt(b.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PASSCRED, on_ptr, len) }, 0);
+        assert_eq!(a.send_fds(b"x", &vec![file.as_raw_fd(); MAX_FDS]).unwrap(), 1);
+
+        let mut fds = vec![file.as_raw_fd()];
+        let err = b.recv_fds(&mut [0u8; 4], &mut fds).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fds, [file.as_raw_fd()]);
+    }
+
+    #[test]
+    fn seqpacket_message_boundaries() {
+        System::builder().finish();
+        let path = std::env::temp_dir().join(format!("netlib-seqpacket-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut listener = SeqPacketListener::bind(&path).unwrap();
+        let mut client = SeqPacketStream::connect(&path).unwrap();
+        let ev = crate::Event { read: true, write: false, owner: listener.id };
+        let mut server = match listener.react(Reaction::Event(ev)) {
+            Reaction::Value(Ok(s)) => SeqPacketStream::try_from(s).unwrap(),
+            r => panic!("{:?}", r),
+        };
+
+        client.write_all(b"hello").unwrap();
+        client.write_all(b"world").unwrap();
+
+        let mut buf = [0u8; 64];
+        assert_eq!(server.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(server.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"world");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn seqpacket_empty_message() {
+        System::builder().finish();
+        let (mut a, mut b) = SeqPacketStream::pair().unwrap();
+        let mut buf = [0u8; 64];
+
+        assert_eq!(a.write(b"").unwrap(), 0);
+        assert_eq!(b.recv(&mut buf).unwrap(), Some(0));
+
+        // Skipped by `read`
+        assert_eq!(a.write(b"").unwrap(), 0);
+        a.write_all(b"x").unwrap();
+        assert_eq!(b.read(&mut buf).unwrap(), 1);
+
+        drop(a);
+        assert_eq!(b.recv(&mut buf).unwrap(), None);
+        assert_eq!(b.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn stale_socket_and_unlink() {
+        System::builder().finish();
+        let path = std::env::temp_dir().join(format!("netlib-stale-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // Leave a socket file behind with nothing listening
+        drop(StdUnixListener::bind(&path).unwrap());
+        assert!(UnixListener::bind(&path).is_err());
+
+        let listener = UnixListenerBuilder::new()
+            .remove_stale(true)
+            .mode(0o600)
+            .unlink_on_drop(true)
+            .bind(&path)
+            .unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // In use: not stale
+        let err = UnixListenerBuilder::new().remove_stale(true).bind(&path);
+        assert!(err.is_err());
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn datagram_pair() {
+        System::builder().finish();
+        let (mut a, mut b) = UnixDatagram::pair().unwrap();
+        a.send(b"ping").unwrap();
+        a.send(b"").unwrap();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(b.recv(&mut buf).unwrap(), 4);
+        assert_eq!(b.recv(&mut buf).unwrap(), 0);
+        assert_eq!(b.recv(&mut buf).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::{self, Read, Write};
+use std::io::{self, IoSlice, Read, Write};
 use std::io::ErrorKind::WouldBlock;
 use std::os::unix::io::AsRawFd;
 use std::fmt;
@@ -101,6 +101,44 @@ impl<T: AsRawFd> PollReactor<T> {
     pub fn writable(&self) -> bool {
         self.writable
     }
+
+    /// Update the readiness after reading from the inner value,
+    /// rearming on `WouldBlock`.
+    pub(crate) fn read_result(&mut self, res: &io::Result<usize>) {
+        match res {
+            Ok(0) => self.readable = false,
+            Ok(_) => {}
+            Err(ref e) if e.kind() == WouldBlock => self.read_blocked(),
+            Err(_) => self.readable = false,
+
+        }
+    }
+
+    /// Reading would block: wait for the next read event.
+    pub(crate) fn read_blocked(&mut self) {
+        self.readable = false;
+        if self.writable {
+            self.rearm(Interest::ReadWrite);
+        } else {
+            self.rearm(Interest::Read);
+        }
+    }
+
+    /// Update the readiness after writing to the inner value,
+    /// rearming on `WouldBlock`.
+    pub(crate) fn write_result(&mut self, res: &io::Result<usize>) {
+        match res {
+            Err(ref e) if e.kind() == WouldBlock => self.write_blocked(),
+            Err(_) => self.writable = false,
+            Ok(_) => {}
+        }
+    }
+
+    /// Writing would block: wait for the next write event.
+    pub(crate) fn write_blocked(&mut self) {
+        self.writable = false;
+        self.rearm(Interest::Write);
+    }
 }
 
 impl<T: AsRawFd> AsRawFd for PollReactor<T> {
@@ -143,22 +181,7 @@ impl<T: AsRawFd> AsMut<T> for PollReactor<T> {
 impl<T: AsRawFd + Read> Read for PollReactor<T> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let res = self.as_mut().read(buf);
-
-        match res {
-            Ok(0) => self.readable = false,
-            Ok(_) => {}
-            Err(ref e) if e.kind() == WouldBlock => {
-                self.readable = false;
-                if self.writable {
-                    self.rearm(Interest::ReadWrite);
-                } else {
-                    self.rearm(Interest::Read);
-                }
-            }
-            Err(_) => self.readable = false,
-
-        }
-
+        self.read_result(&res);
         res
     }
 }
@@ -169,18 +192,18 @@ impl<T: AsRawFd + Read> Read for PollReactor<T> {
 impl<T: AsRawFd + Write> Write for PollReactor<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let res = self.as_mut().write(buf);
-        match res {
-            Err(ref e) if e.kind() == WouldBlock => {
-                self.writable = false;
-                self.rearm(Interest::Write);
-            }
-            Err(_) => self.writable = false,
-            Ok(_) => {}
-        }
-
+        self.write_result(&res);
         Ok(res?)
     }
 
+    /// Write from several buffers with one call (`writev`),
+    /// rearming on `WouldBlock`.
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let res = self.as_mut().write_vectored(bufs);
+        self.write_result(&res);
+        res
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         Ok(self.as_mut().flush()?)
     }
@@ -194,3 +217,35 @@ impl<T: fmt::Debug + AsRawFd> fmt::Debug for PollReactor<T> {
         write!(f, "<PollReactor {:?} read: {}, write: {}>", self.inner, self.readable, self.writable)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn write_vectored() {
+        System::builder().finish();
+        let (a, mut b) = UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let mut a = PollReactor::new(a, Interest::Read).unwrap();
+
+        let bufs = [IoSlice::new(b"hello"), IoSlice::new(b" "), IoSlice::new(b"world")];
+        assert_eq!(a.write_vectored(&bufs).unwrap(), 11);
+        let mut buf = [0u8; 11];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello world");
+
+        // Blocking clears the write readiness
+        a.update(&Event { read: false, write: true, owner: a.id });
+        let chunk = vec![0u8; 64 * 1024];
+        loop {
+            match a.write_vectored(&[IoSlice::new(&chunk)]) {
+                Ok(_) => assert!(a.writable()),
+                Err(ref e) if e.kind() == WouldBlock => break,
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert!(!a.writable());
+    }
+}
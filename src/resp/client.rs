@@ -0,0 +1,328 @@
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpStream as StdTcpStream, ToSocketAddrs};
+
+use super::{Command, RespCodec, Value};
+use crate::codecs::Framed;
+use crate::net::tcp::TcpStream;
+use crate::{Error, Event, Reaction, Reactor, Result, System};
+
+pub type RequestId = u64;
+
+// -----------------------------------------------------------------------------
+//     - Reply -
+// -----------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum Reply {
+    /// The reply to the command with this id. An error reply from the
+    /// server is `Ok(Value::Error(_))`; `Err` means the command failed to
+    /// get a reply at all.
+    Response(RequestId, Result<Value>),
+    /// Out-of-band data (RESP3 only), e.g. a pub/sub message.
+    Push(Vec<Value>),
+}
+
+// -----------------------------------------------------------------------------
+//     - Resp client -
+// -----------------------------------------------------------------------------
+/// Sends commands to a single server and yields the replies, tagged with
+/// the id returned by `send`.
+///
+/// Commands are written as soon as they are queued, without waiting for
+/// the replies to earlier ones (pipelining); replies come back in the
+/// same order. A command is queued with `send`, or by passing it in as a
+/// `Reaction::Value`.
+///
+/// The connection is opened on the first command. If it fails, every
+/// command waiting for a reply fails with it, and the next command opens
+/// a new connection. State set by commands such as `SELECT` or `HELLO`
+/// is lost with the connection.
+pub struct RespClient {
+    id: u64,
+    addr: SocketAddr,
+    next_id: RequestId,
+    inflight: VecDeque<RequestId>,
+    connection: Option<Framed<StdTcpStream, RespCodec>>,
+    results: VecDeque<Reply>,
+}
+
+impl RespClient {
+    /// A client for the server at `addr`.
+    pub fn new(addr: impl ToSocketAddrs) -> Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "could not resolve address"))?;
+
+        let inst = Self {
+            id: System::reserve(),
+            addr,
+            next_id: 0,
+            inflight: VecDeque::new(),
+            connection: None,
+            results: VecDeque::new(),
+        };
+
+        Ok(inst)
+    }
+
+    /// Queue a command, returning the id its reply is tagged with.
+    pub fn send(&mut self, command: Command) -> RequestId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        if self.connection.is_none() {
+            match TcpStream::connect(self.addr) {
+                Ok(stream) => self.connection = Some(Framed::new(stream, RespCodec::new())),
+                Err(e) => {
+                    self.push_result(Reply::Response(id, Err(e)));
+                    return id;
+                }
+            }
+        }
+
+        self.inflight.push_back(id);
+        let res = self.connection.as_mut().expect("connected above").send(command);
+        if let Err(e) = res {
+            self.fail(e);
+        }
+
+        id
+    }
+
+    /// The number of commands waiting for a reply.
+    pub fn pending(&self) -> usize {
+        self.inflight.len()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    // The connection is gone, and with it every reply still to come.
+    // The first command waiting gets the error, the others are aborted.
+    fn fail(&mut self, e: Error) {
+        self.connection = None;
+        let mut e = Some(e);
+        while let Some(id) = self.inflight.pop_front() {
+            let e = e
+                .take()
+                .unwrap_or_else(|| io::Error::new(ErrorKind::ConnectionAborted, "connection lost").into());
+            self.push_result(Reply::Response(id, Err(e)));
+        }
+    }
+
+    fn value(&mut self, value: Value) {
+        let reply = match value {
+            Value::Push(values) => Reply::Push(values),
+            value => match self.inflight.pop_front() {
+                Some(id) => Reply::Response(id, Ok(value)),
+                None => return self.fail(Error::protocol("reply without a command")),
+            },
+        };
+        self.push_result(reply);
+    }
+
+    // Results are yielded one per reaction; the deferred event
+    // makes sure the next one follows.
+    fn push_result(&mut self, reply: Reply) {
+        self.results.push_back(reply);
+        System::defer(Event { read: false, write: false, owner: self.id });
+    }
+
+    fn next_result(&mut self) -> Reaction<Reply> {
+        match self.results.pop_front() {
+            Some(reply) => Reaction::Value(reply),
+            None => Reaction::Continue,
+        }
+    }
+}
+
+impl Drop for RespClient {
+    fn drop(&mut self) {
+        System::free(self.id);
+    }
+}
+
+impl Reactor for RespClient {
+    type Input = Command;
+    type Output = Reply;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Value(command) => {
+                self.send(command);
+                self.next_result()
+            }
+            Reaction::Event(ev) if ev.owner == self.id => self.next_result(),
+            Reaction::Event(ev) => {
+                let connection = match &mut self.connection {
+                    Some(c) if c.id() == ev.owner => c,
+                    _ => return Reaction::Event(ev),
+                };
+
+                match connection.react(Reaction::Event(ev)) {
+                    Reaction::Value(Ok(value)) => self.value(value),
+                    Reaction::Value(Err(e)) => self.fail(e),
+                    _ => {}
+                }
+
+                self.next_result()
+            }
+            Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use std::thread;
+    use crate::net::tcp::{Accepted, TcpListener};
+
+    // Passes events on to the client
+    struct Idle;
+
+    impl Reactor for Idle {
+        type Input = ();
+        type Output = Command;
+
+        fn react(&mut self, reaction: Reaction<()>) -> Reaction<Command> {
+            match reaction {
+                Reaction::Event(ev) => Reaction::Event(ev),
+                _ => Reaction::Continue,
+            }
+        }
+    }
+
+    // A key value store understanding GET, SET and PUBLISH (which pushes
+    // the message back), serving one connection
+    struct FakeServer {
+        connection: Option<Framed<StdTcpStream, RespCodec>>,
+        store: HashMap<Vec<u8>, Vec<u8>>,
+    }
+
+    impl FakeServer {
+        fn execute(&mut self, args: Vec<Vec<u8>>) -> Vec<Value> {
+            match (args[0].as_slice(), &args[1..]) {
+                (b"GET", [key]) => match self.store.get(key) {
+                    Some(value) => vec![Value::Bulk(value.clone())],
+                    None => vec![Value::Null],
+                },
+                (b"SET", [key, value]) => {
+                    self.store.insert(key.clone(), value.clone());
+                    vec![Value::Simple("OK".into())]
+                }
+                (b"PUBLISH", [channel, message]) => vec![
+                    Value::Push(vec![Value::Bulk(channel.clone()), Value::Bulk(message.clone())]),
+                    Value::Integer(1),
+                ],
+                _ => vec![Value::Error("ERR unknown command".into())],
+            }
+        }
+    }
+
+    impl Reactor for FakeServer {
+        type Input = Result<Accepted>;
+        type Output = ();
+
+        fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<()> {
+            match reaction {
+                Reaction::Value(Ok((stream, _, _))) => {
+                    let stream = TcpStream::new(stream, crate::Interest::Read).unwrap();
+                    self.connection = Some(Framed::new(stream, RespCodec::new()));
+                }
+                Reaction::Event(ev) => {
+                    let request = match self.connection.as_mut().map(|c| c.react(Reaction::Event(ev))) {
+                        Some(Reaction::Value(request)) => request,
+                        _ => return Reaction::Continue,
+                    };
+
+                    let args = match request {
+                        Ok(Value::Array(args)) => args,
+                        _ => {
+                            System::stop();
+                            return Reaction::Continue;
+                        }
+                    };
+
+                    let args = args.iter().map(|a| a.as_bytes().unwrap().to_vec()).collect();
+                    for value in self.execute(args) {
+                        self.connection.as_mut().unwrap().send(value).unwrap();
+                    }
+                }
+                _ => {}
+            }
+            Reaction::Continue
+        }
+    }
+
+    #[test]
+    fn pipelined_commands() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server = thread::spawn(move || {
+            System::builder().finish();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+            let server = FakeServer { connection: None, store: HashMap::new() };
+            System::start(listener.chain(server)).unwrap();
+        });
+
+        System::builder().finish();
+        let mut client = RespClient::new(rx.recv().unwrap()).unwrap();
+        let ids = vec![
+            client.send(Command::new("SET").arg("key").arg("value")),
+            client.send(Command::new("GET").arg("key")),
+            client.send(Command::new("PUBLISH").arg("news").arg("hi")),
+            client.send(Command::new("GET").arg("missing")),
+            client.send(Command::new("NOPE")),
+        ];
+        assert_eq!(client.pending(), 5);
+
+        let replies = Rc::new(RefCell::new(Vec::new()));
+        let r = replies.clone();
+        let client = Idle.chain(client).map(move |reply| {
+            let done = matches!(reply, Reply::Response(4, _));
+            r.borrow_mut().push(reply);
+            if done {
+                System::stop();
+            }
+        });
+        System::start(client).unwrap();
+
+        let replies = replies
+            .borrow_mut()
+            .drain(..)
+            .map(|reply| match reply {
+                Reply::Response(id, value) => (Some(id), value.unwrap()),
+                Reply::Push(values) => (None, Value::Push(values)),
+            })
+            .collect::<Vec<_>>();
+
+        let expected = vec![
+            (Some(ids[0]), Value::Simple("OK".into())),
+            (Some(ids[1]), Value::Bulk(b"value".to_vec())),
+            (None, Value::Push(vec![Value::Bulk(b"news".to_vec()), Value::Bulk(b"hi".to_vec())])),
+            (Some(ids[2]), Value::Integer(1)),
+            (Some(ids[3]), Value::Null),
+            (Some(ids[4]), Value::Error("ERR unknown command".into())),
+        ];
+        assert_eq!(replies, expected);
+
+        // The client is gone with its system, which stops the server
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn ids_are_freed() {
+        System::builder().finish();
+        let id = System::reserve();
+        System::free(id);
+
+        drop(RespClient::new("127.0.0.1:1").unwrap());
+        assert_eq!(System::reserve(), id);
+    }
+}
@@ -0,0 +1,347 @@
+use super::{Command, Value};
+use crate::codecs::{Decoder, Encoder};
+use crate::memchr::memchr;
+use crate::{Error, Result};
+
+const DEFAULT_MAX_BULK: usize = 512 * 1024 * 1024;
+const MAX_LINE: usize = 64 * 1024;
+const MAX_DEPTH: usize = 64;
+
+// `?` for parsing steps that may need more data
+macro_rules! ready {
+    ($e:expr) => {
+        match $e? {
+            Some(val) => val,
+            None => return Ok(None),
+        }
+    };
+}
+
+// -----------------------------------------------------------------------------
+//     - Resp codec -
+// -----------------------------------------------------------------------------
+/// Decodes RESP2 and RESP3 values, encodes commands and values.
+///
+/// Values are encoded in their RESP3 form: a RESP2 peer only understands
+/// simple strings, errors, integers, bulk strings and arrays.
+/// Attributes are decoded and dropped.
+#[derive(Debug)]
+pub struct RespCodec {
+    max_bulk: usize,
+}
+
+impl RespCodec {
+    pub fn new() -> Self {
+        Self { max_bulk: DEFAULT_MAX_BULK }
+    }
+
+    /// The largest bulk string accepted, in bytes.
+    pub fn max_bulk_size(mut self, max: usize) -> Self {
+        self.max_bulk = max;
+        self
+    }
+}
+
+impl Default for RespCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Parses one value from the front of the buffer without consuming it,
+// so an incomplete value is parsed again from the start once more
+// data has arrived.
+struct Parser<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    max_bulk: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn line(&mut self) -> Result<Option<&'a [u8]>> {
+        let rest = &self.buf[self.pos..];
+        match memchr(rest, b'\n') {
+            Some(i) if i > 0 && rest[i - 1] == b'\r' => {
+                self.pos += i + 1;
+                Ok(Some(&rest[..i - 1]))
+            }
+            Some(_) => Err(Error::protocol("line not terminated by CRLF")),
+            None if rest.len() > MAX_LINE => Err(Error::protocol("line too long")),
+            None => Ok(None),
+        }
+    }
+
+    fn blob(&mut self, len: i64) -> Result<Option<Vec<u8>>> {
+        if len < 0 {
+            return Err(Error::protocol("invalid length"));
+        }
+
+        let len = len as usize;
+        if len > self.max_bulk {
+            return Err(Error::protocol("bulk string too large"));
+        }
+
+        let rest = &self.buf[self.pos..];
+        if rest.len() < len + 2 {
+            return Ok(None);
+        }
+
+        if &rest[len..len + 2] != b"\r\n" {
+            return Err(Error::protocol("bulk string not terminated by CRLF"));
+        }
+
+        self.pos += len + 2;
+        Ok(Some(rest[..len].to_vec()))
+    }
+
+    fn values(&mut self, count: i64, depth: usize) -> Result<Option<Vec<Value>>> {
+        if count < 0 {
+            return Err(Error::protocol("invalid length"));
+        }
+
+        // Don't trust the count with the allocation
+        let mut values = Vec::with_capacity((count as usize).min(1024));
+        for _ in 0..count {
+            values.push(ready!(self.value(depth + 1)));
+        }
+        Ok(Some(values))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Option<Value>> {
+        if depth > MAX_DEPTH {
+            return Err(Error::protocol("values nested too deep"));
+        }
+
+        let line = ready!(self.line());
+        let (kind, rest) = match line.split_first() {
+            Some((kind, rest)) => (*kind, rest),
+            None => return Err(Error::protocol("empty line")),
+        };
+
+        let value = match kind {
+            b'+' => Value::Simple(text(rest)?),
+            b'-' => Value::Error(text(rest)?),
+            b':' => Value::Integer(number(rest)?),
+            b'$' => match number(rest)? {
+                -1 => Value::Null,
+                len => Value::Bulk(ready!(self.blob(len))),
+            },
+            b'*' => match number(rest)? {
+                -1 => Value::Null,
+                count => Value::Array(ready!(self.values(count, depth))),
+            },
+            b'_' => match rest.is_empty() {
+                true => Value::Null,
+                false => return Err(Error::protocol("invalid null")),
+            },
+            b'#' => match rest {
+                b"t" => Value::Boolean(true),
+                b"f" => Value::Boolean(false),
+                _ => return Err(Error::protocol("invalid boolean")),
+            },
+            b',' => match text(rest)?.parse() {
+                Ok(d) => Value::Double(d),
+                Err(_) => return Err(Error::protocol("invalid double")),
+            },
+            b'(' => Value::BigNumber(text(rest)?),
+            b'!' => Value::Error(text(&ready!(self.blob(number(rest)?)))?),
+            b'=' => {
+                // Three characters of format, then a colon
+                let mut blob = ready!(self.blob(number(rest)?));
+                if blob.len() < 4 || blob[3] != b':' {
+                    return Err(Error::protocol("invalid verbatim string"));
+                }
+                blob.drain(..4);
+                Value::Bulk(blob)
+            }
+            b'~' => Value::Set(ready!(self.values(number(rest)?, depth))),
+            b'>' => Value::Push(ready!(self.values(number(rest)?, depth))),
+            b'%' | b'|' => {
+                let count = number(rest)?;
+                let pairs = ready!(self.values(count.saturating_mul(2), depth));
+                let mut pairs = pairs.into_iter();
+                let mut map = Vec::with_capacity(pairs.len() / 2);
+                while let (Some(key), Some(value)) = (pairs.next(), pairs.next()) {
+                    map.push((key, value));
+                }
+
+                match kind {
+                    b'%' => Value::Map(map),
+                    // Attributes describe the value that follows
+                    _ => ready!(self.value(depth)),
+                }
+            }
+            _ => return Err(Error::protocol(format!("unknown type: {:?}", kind as char))),
+        };
+
+        Ok(Some(value))
+    }
+}
+
+fn text(bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| Error::protocol("invalid utf-8"))
+}
+
+fn number(bytes: &[u8]) -> Result<i64> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| Error::protocol("invalid integer"))
+}
+
+impl Decoder for RespCodec {
+    type Item = Value;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Value>> {
+        let mut parser = Parser { buf, pos: 0, max_bulk: self.max_bulk };
+        match parser.value(0)? {
+            Some(value) => {
+                let pos = parser.pos;
+                buf.drain(..pos);
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+fn write_header(kind: u8, len: impl std::fmt::Display, buf: &mut Vec<u8>) {
+    buf.push(kind);
+    buf.extend_from_slice(len.to_string().as_bytes());
+    buf.extend_from_slice(b"\r\n");
+}
+
+fn write_blob(kind: u8, blob: &[u8], buf: &mut Vec<u8>) {
+    write_header(kind, blob.len(), buf);
+    buf.extend_from_slice(blob);
+    buf.extend_from_slice(b"\r\n");
+}
+
+// Simple strings and errors can't contain a line break
+fn write_line(kind: u8, line: &str, buf: &mut Vec<u8>) -> Result<()> {
+    if line.contains(|c| c == '\r' || c == '\n') {
+        return Err(Error::protocol("line break in a simple string"));
+    }
+    write_header(kind, line, buf);
+    Ok(())
+}
+
+fn write_values(kind: u8, values: &[Value], buf: &mut Vec<u8>) -> Result<()> {
+    write_header(kind, values.len(), buf);
+    values.iter().try_for_each(|value| write_value(value, buf))
+}
+
+fn write_value(value: &Value, buf: &mut Vec<u8>) -> Result<()> {
+    match value {
+        Value::Simple(s) => write_line(b'+', s, buf)?,
+        Value::Error(e) => write_line(b'-', e, buf)?,
+        Value::Integer(i) => write_header(b':', i, buf),
+        Value::Bulk(b) => write_blob(b'$', b, buf),
+        Value::Array(values) => write_values(b'*', values, buf)?,
+        Value::Null => buf.extend_from_slice(b"_\r\n"),
+        Value::Boolean(true) => buf.extend_from_slice(b"#t\r\n"),
+        Value::Boolean(false) => buf.extend_from_slice(b"#f\r\n"),
+        Value::Double(d) if d.is_nan() => buf.extend_from_slice(b",nan\r\n"),
+        Value::Double(d) => write_header(b',', d, buf),
+        Value::BigNumber(n) => write_line(b'(', n, buf)?,
+        Value::Map(map) => {
+            write_header(b'%', map.len(), buf);
+            for (key, value) in map {
+                write_value(key, buf)?;
+                write_value(value, buf)?;
+            }
+        }
+        Value::Set(values) => write_values(b'~', values, buf)?,
+        Value::Push(values) => write_values(b'>', values, buf)?,
+    }
+    Ok(())
+}
+
+impl Encoder<Value> for RespCodec {
+    fn encode(&mut self, value: Value, buf: &mut Vec<u8>) -> Result<()> {
+        let start = buf.len();
+        let res = write_value(&value, buf);
+        if res.is_err() {
+            // Nothing of a value that failed halfway is sent
+            buf.truncate(start);
+        }
+        res
+    }
+}
+
+impl Encoder<Command> for RespCodec {
+    fn encode(&mut self, command: Command, buf: &mut Vec<u8>) -> Result<()> {
+        write_header(b'*', command.args.len(), buf);
+        command.args.iter().for_each(|arg| write_blob(b'$', arg, buf));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_resp2() {
+        let mut codec = RespCodec::new();
+        let mut buf = b"+OK\r\n-ERR wrong type\r\n:-42\r\n$5\r\nhel\r\n\r\n$-1\r\n*2\r\n$1\r\na\r\n*-1\r\n".to_vec();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Value::Simple("OK".into())));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Value::Error("ERR wrong type".into())));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Value::Integer(-42)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Value::Bulk(b"hel\r\n".to_vec())));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Value::Null));
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Value::Array(vec![Value::Bulk(b"a".to_vec()), Value::Null]))
+        );
+        assert!(buf.is_empty());
+
+        // Incomplete values are left in the buffer
+        let mut buf = b"*2\r\n:1\r\n$3\r\nab".to_vec();
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"c\r\n");
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Value::Array(vec![Value::Integer(1), Value::Bulk(b"abc".to_vec())]))
+        );
+
+        assert!(codec.decode(&mut b"$3\r\nabcd\r\n".to_vec()).is_err());
+        assert!(codec.decode(&mut b"?\r\n".to_vec()).is_err());
+        assert!(RespCodec::new().max_bulk_size(2).decode(&mut b"$3\r\n".to_vec()).is_err());
+    }
+
+    #[test]
+    fn resp3_round_trip() {
+        let value = Value::Map(vec![
+            (Value::Simple("set".into()), Value::Set(vec![Value::Boolean(true), Value::Double(1.5)])),
+            (Value::BigNumber("12345678901234567890".into()), Value::Null),
+            (Value::Simple("push".into()), Value::Push(vec![Value::Bulk(b"message".to_vec())])),
+        ]);
+
+        let mut codec = RespCodec::new();
+        let mut buf = Vec::new();
+        codec.encode(value.clone(), &mut buf).unwrap();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(value));
+
+        let mut buf = b"|1\r\n+ttl\r\n:3\r\n=7\r\ntxt:abc\r\n!4\r\nNOPE\r\n,-inf\r\n".to_vec();
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Value::Bulk(b"abc".to_vec())));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Value::Error("NOPE".into())));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(Value::Double(f64::NEG_INFINITY)));
+    }
+
+    #[test]
+    fn encode_error() {
+        let value = Value::Array(vec![Value::Integer(1), Value::Simple("a\r\nb".into())]);
+        let mut buf = b"+OK\r\n".to_vec();
+        assert!(RespCodec::new().encode(value, &mut buf).is_err());
+        assert_eq!(buf, b"+OK\r\n");
+    }
+
+    #[test]
+    fn encode_command() {
+        let mut buf = Vec::new();
+        RespCodec::new().encode(Command::new("GET").arg("key"), &mut buf).unwrap();
+        assert_eq!(buf, b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n");
+    }
+}
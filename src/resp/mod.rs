@@ -0,0 +1,120 @@
+//! RESP, the Redis serialization protocol (RESP2 and RESP3).
+//!
+//! `RespCodec` decodes replies into `Value`s and encodes `Command`s
+//! (and `Value`s, for the server side).
+//!
+//! `RespClient` is a reactor pipelining commands to a single server,
+//! yielding each reply with the id of its command:
+//!
+//! ```no_run
+//! # use netlib::{Reactor, System};
+//! # use netlib::resp::{Command, Reply, RespClient};
+//! fn run(commands: impl Reactor<Input = (), Output = Command>) {
+//!     System::builder().finish();
+//!     let mut client = RespClient::new("127.0.0.1:6379").unwrap();
+//!     client.send(Command::new("SET").arg("greeting").arg("hello"));
+//!     client.send(Command::new("GET").arg("greeting"));
+//!
+//!     let client = commands.chain(client).map(|reply| {
+//!         if let Reply::Response(id, value) = reply {
+//!             println!("{}: {:?}", id, value);
+//!         }
+//!     });
+//!     System::start(client);
+//! }
+//! ```
+mod client;
+mod codec;
+
+pub use client::{Reply, RequestId, RespClient};
+pub use codec::RespCodec;
+
+// -----------------------------------------------------------------------------
+//     - Value -
+// -----------------------------------------------------------------------------
+/// A RESP value.
+///
+/// RESP2 only has simple strings, errors, integers, bulk strings and
+/// arrays; its null bulk string and null array both decode as `Null`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Simple(String),
+    /// An error reply, simple or bulk.
+    Error(String),
+    Integer(i64),
+    /// A bulk string. Verbatim strings decode as bulk strings
+    /// without their format prefix.
+    Bulk(Vec<u8>),
+    Array(Vec<Value>),
+    Null,
+    Boolean(bool),
+    Double(f64),
+    BigNumber(String),
+    Map(Vec<(Value, Value)>),
+    Set(Vec<Value>),
+    /// Out-of-band data, e.g. a pub/sub message. Not a reply to a command.
+    Push(Vec<Value>),
+}
+
+impl Value {
+    /// The bytes of a simple or bulk string.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Simple(s) => Some(s.as_bytes()),
+            Value::Bulk(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// A simple string, or a bulk string that is valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Value::Error(_))
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Command -
+// -----------------------------------------------------------------------------
+/// A command: its name followed by its arguments, sent as an array of
+/// bulk strings.
+///
+/// ```
+/// # use netlib::resp::Command;
+/// let command = Command::new("SET").arg("key").arg(b"\x00binary");
+/// assert_eq!(command.args().len(), 3);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    args: Vec<Vec<u8>>,
+}
+
+impl Command {
+    pub fn new(name: impl AsRef<[u8]>) -> Self {
+        Self { args: vec![name.as_ref().to_vec()] }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<[u8]>) -> Self {
+        self.args.push(arg.as_ref().to_vec());
+        self
+    }
+
+    /// The name followed by the arguments.
+    pub fn args(&self) -> &[Vec<u8>] {
+        &self.args
+    }
+}
@@ -0,0 +1,417 @@
+//! Zero-downtime restarts by handing listening sockets to a successor process.
+//!
+//! The running process binds a `Handoff` reactor to a control socket and
+//! registers its listeners with it. A successor process calls `receive`
+//! with the same path, adopts the listeners and calls `Successor::complete`.
+//! At that point the `Handoff` reactor yields a value, and the old process
+//! should stop accepting (`TcpListener::pause`), let its connections finish and
+//! call `System::stop`.
+//!
+//! ```no_run
+//! # use netlib::restart;
+//! # use netlib::System;
+//! System::builder().finish();
+//! let mut successor = restart::receive("/tmp/game.handoff").unwrap();
+//! let listeners = successor.tcp_listeners().unwrap();
+//! successor.complete().unwrap();
+//! ```
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::net::tcp::TcpListener;
+use crate::net::uds::{recv_fds, UnixListener, UnixListenerBuilder, UnixStream, MAX_FDS};
+use crate::{Reaction, Reactor, Result};
+
+const HEADER: &[u8] = b"netlib-handoff 1\n";
+const ACK: u8 = b'k';
+const MAX_MESSAGE: usize = 64 * 1024;
+
+fn invalid(msg: &str) -> crate::Error {
+    io::Error::new(ErrorKind::InvalidData, msg).into()
+}
+
+// -----------------------------------------------------------------------------
+//     - Inherited listener -
+// -----------------------------------------------------------------------------
+/// A listening socket received from the previous process.
+#[derive(Debug, PartialEq)]
+pub enum Inherited {
+    Tcp(SocketAddr, RawFd),
+    Unix(PathBuf, RawFd),
+}
+
+impl Inherited {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Inherited::Tcp(addr, _) => buf.extend_from_slice(format!("tcp {}\n", addr).as_bytes()),
+            Inherited::Unix(path, _) => {
+                buf.extend_from_slice(b"unix ");
+                buf.extend_from_slice(path.as_os_str().as_bytes());
+                buf.push(b'\n');
+            }
+        }
+    }
+
+    fn decode(line: &[u8], fd: RawFd) -> Result<Self> {
+        let space = line.iter().position(|b| *b == b' ').ok_or_else(|| invalid("malformed handoff entry"))?;
+        let (kind, value) = (&line[..space], &line[space + 1..]);
+
+        match kind {
+            b"tcp" => {
+                let addr = std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(|| invalid("invalid address in handoff"))?;
+                Ok(Inherited::Tcp(addr, fd))
+            }
+            b"unix" => Ok(Inherited::Unix(OsStr::from_bytes(value).into(), fd)),
+            _ => Err(invalid("unknown listener kind in handoff")),
+        }
+    }
+
+    fn fd(&self) -> RawFd {
+        match self {
+            Inherited::Tcp(_, fd) | Inherited::Unix(_, fd) => *fd,
+        }
+    }
+}
+
+fn encode(listeners: &[Inherited]) -> Vec<u8> {
+    let mut buf = HEADER.to_vec();
+    listeners.iter().for_each(|l| l.encode(&mut buf));
+    buf
+}
+
+fn decode(buf: &[u8], fds: &[RawFd]) -> Result<Vec<Inherited>> {
+    if !buf.starts_with(HEADER) {
+        return Err(invalid("not a handoff message"));
+    }
+
+    let lines = buf[HEADER.len()..]
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+
+    if lines.len() != fds.len() {
+        return Err(invalid("listener and file descriptor count differ"));
+    }
+
+    lines
+        .into_iter()
+        .zip(fds)
+        .map(|(line, fd)| Inherited::decode(line, *fd))
+        .collect()
+}
+
+// -----------------------------------------------------------------------------
+//     - Handoff -
+// -----------------------------------------------------------------------------
+/// Runs in the old process and sends the registered listeners
+/// to the first successor that connects to the control socket.
+///
+/// Yields `Ok(())` once the successor has adopted the listeners.
+pub struct Handoff {
+    control: UnixListener,
+    listeners: Vec<Inherited>,
+    successor: Option<UnixStream>,
+}
+
+impl Handoff {
+    /// Bind the control socket.
+    /// A socket file left at `path` by a process that is gone is removed,
+    /// but if the previous owner is still listening this fails with
+    /// `AddrInUse`. The file is removed again when the `Handoff` is
+    /// dropped, so the old process should drop it once it has handed off.
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let control = UnixListenerBuilder::new()
+            .remove_stale(true)
+            .unlink_on_drop(true)
+            .bind(path)?;
+
+        let inst = Self {
+            control,
+            listeners: Vec::new(),
+            successor: None,
+        };
+
+        Ok(inst)
+    }
+
+    /// Hand off this listener on restart.
+    pub fn add_tcp(&mut self, listener: &TcpListener) -> Result<()> {
+        let addr = listener.local_addr()?;
+        self.add(Inherited::Tcp(addr, listener.as_raw_fd()))
+    }
+
+    /// Hand off this listener on restart.
+    pub fn add_unix(&mut self, listener: &UnixListener) -> Result<()> {
+        let path = listener
+            .local_addr()?
+            .as_pathname()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "unnamed unix listener"))?;
+
+        if path.as_os_str().as_bytes().contains(&b'\n') {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "newline in socket path").into());
+        }
+
+        self.add(Inherited::Unix(path, listener.as_raw_fd()))
+    }
+
+    fn add(&mut self, listener: Inherited) -> Result<()> {
+        if self.listeners.len() == MAX_FDS {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "too many listeners").into());
+        }
+
+        self.listeners.push(listener);
+        Ok(())
+    }
+
+    fn accept(&mut self, stream: StdUnixStream) -> Result<()> {
+        // Only one successor at a time
+        if self.successor.is_some() {
+            return Ok(());
+        }
+
+        stream.set_nonblocking(true)?;
+        let mut stream = UnixStream::try_from(stream)?;
+        let fds = self.listeners.iter().map(Inherited::fd).collect::<Vec<_>>();
+        let msg = encode(&self.listeners);
+
+        let n = stream.send_fds(&msg, &fds)?;
+        if n != msg.len() {
+            return Err(io::Error::new(ErrorKind::WriteZero, "partial handoff message").into());
+        }
+
+        self.successor = Some(stream);
+        Ok(())
+    }
+
+    fn acknowledged(&mut self) -> Result<bool> {
+        let mut successor = match self.successor.take() {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+
+        let mut buf = [0u8; 1];
+        match successor.read(&mut buf) {
+            Ok(1) if buf[0] == ACK => Ok(true),
+            Ok(_) => Err(io::Error::new(ErrorKind::ConnectionAborted, "successor aborted the handoff").into()),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                self.successor = Some(successor);
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Reactor for Handoff {
+    type Input = ();
+    type Output = Result<()>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Event(ev) if ev.owner == self.control.id() => {
+                let res = match self.control.react(Reaction::Event(ev)) {
+                    Reaction::Value(Ok((stream, _))) => self.accept(stream),
+                    Reaction::Value(Err(e)) => Err(e),
+                    _ => Ok(()),
+                };
+
+                match res {
+                    Ok(()) => Reaction::Continue,
+                    Err(e) => Reaction::Value(Err(e)),
+                }
+            }
+            Reaction::Event(ev) if Some(ev.owner) == self.successor.as_ref().map(|s| s.id) => {
+                match self.acknowledged() {
+                    Ok(true) => Reaction::Value(Ok(())),
+                    Ok(false) => Reaction::Continue,
+                    Err(e) => Reaction::Value(Err(e)),
+                }
+            }
+            Reaction::Event(ev) => Reaction::Event(ev),
+            _ => Reaction::Continue,
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Successor -
+// -----------------------------------------------------------------------------
+/// The receiving end of a handoff, in the new process.
+///
+/// Any listener not adopted is closed when this is dropped.
+pub struct Successor {
+    stream: StdUnixStream,
+    listeners: Vec<Inherited>,
+}
+
+/// Connect to the control socket of the running process and receive its listeners.
+/// This blocks for at most five seconds and is meant to run at startup.
+pub fn receive<P: AsRef<Path>>(path: P) -> Result<Successor> {
+    receive_timeout(path, Duration::from_secs(5))
+}
+
+/// Same as `receive` with a custom timeout.
+pub fn receive_timeout<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<Successor> {
+    let stream = StdUnixStream::connect(path)?;
+    stream.set_read_timeout(Some(timeout))?;
+
+    let mut buf = vec![0u8; MAX_MESSAGE];
+    let mut fds = Vec::new();
+    let n = recv_fds(stream.as_raw_fd(), &mut buf, &mut fds)?;
+
+    let listeners = match decode(&buf[..n], &fds) {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            fds.into_iter().for_each(|fd| unsafe { libc::close(fd); });
+            return Err(e);
+        }
+    };
+
+    let inst = Successor { stream, listeners };
+    Ok(inst)
+}
+
+impl Successor {
+    /// The listeners that have not been adopted yet.
+    pub fn listeners(&self) -> &[Inherited] {
+        &self.listeners
+    }
+
+    /// Adopt all inherited TCP listeners.
+    pub fn tcp_listeners(&mut self) -> Result<Vec<TcpListener>> {
+        let adopted = self
+            .take(|l| matches!(l, Inherited::Tcp(..)))
+            .into_iter()
+            .map(|l| unsafe { TcpListener::from_raw_fd(l.fd()) })
+            .collect::<Vec<_>>();
+        // Every fd is owned before bailing out, so none of them leak
+        adopted.into_iter().collect()
+    }
+
+    /// Adopt the inherited TCP listener bound to `addr`.
+    pub fn tcp_listener(&mut self, addr: SocketAddr) -> Option<Result<TcpListener>> {
+        let pos = self.listeners.iter().position(|l| matches!(l, Inherited::Tcp(a, _) if *a == addr))?;
+        let l = self.listeners.remove(pos);
+        Some(unsafe { TcpListener::from_raw_fd(l.fd()) })
+    }
+
+    /// Adopt all inherited Unix listeners.
+    pub fn unix_listeners(&mut self) -> Result<Vec<UnixListener>> {
+        let adopted = self
+            .take(|l| matches!(l, Inherited::Unix(..)))
+            .into_iter()
+            .map(|l| unsafe { UnixListener::from_raw_fd(l.fd()) })
+            .collect::<Vec<_>>();
+        adopted.into_iter().collect()
+    }
+
+    /// Tell the old process the listeners have been taken over,
+    /// so it can start draining.
+    pub fn complete(mut self) -> Result<()> {
+        self.stream.write_all(&[ACK])?;
+        Ok(())
+    }
+
+    fn take(&mut self, f: impl Fn(&Inherited) -> bool) -> Vec<Inherited> {
+        let (taken, rest) = self.listeners.drain(..).partition(f);
+        self.listeners = rest;
+        taken
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Drop -
+// -----------------------------------------------------------------------------
+impl Drop for Successor {
+    fn drop(&mut self) {
+        self.listeners.iter().for_each(|l| unsafe { libc::close(l.fd()); });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Event, System};
+    use std::os::unix::io::IntoRawFd;
+    use std::thread;
+
+    #[test]
+    fn encode_decode() {
+        let listeners = vec![
+            Inherited::Tcp("127.0.0.1:9000".parse().unwrap(), 3),
+            Inherited::Unix("/tmp/game.sock".into(), 4),
+        ];
+        let msg = encode(&listeners);
+        assert_eq!(decode(&msg, &[3, 4]).unwrap(), listeners);
+        assert!(decode(&msg, &[3]).is_err());
+    }
+
+    #[test]
+    fn adopt_one_of_several() {
+        System::builder().finish();
+        let addr = "127.0.0.1:9000".parse().unwrap();
+        let listeners = (0..2)
+            .map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap().into_raw_fd())
+            .map(|fd| Inherited::Tcp(addr, fd))
+            .collect();
+        let (stream, _peer) = StdUnixStream::pair().unwrap();
+        let mut successor = Successor { stream, listeners };
+
+        assert!(successor.tcp_listener(addr).unwrap().is_ok());
+        assert_eq!(successor.listeners().len(), 1);
+        assert!(successor.tcp_listener(addr).unwrap().is_ok());
+        assert!(successor.tcp_listener(addr).is_none());
+    }
+
+    #[test]
+    fn hand_off_listener() {
+        System::builder().finish();
+        let path = std::env::temp_dir().join(format!("netlib-handoff-{}", std::process::id()));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Left behind by a previous run
+        let _ = std::fs::remove_file(&path);
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let mut handoff = Handoff::bind(&path).unwrap();
+        handoff.add_tcp(&listener).unwrap();
+
+        let successor_path = path.clone();
+        let successor = thread::spawn(move || {
+            System::builder().finish();
+            let mut successor = receive(successor_path).unwrap();
+            let adopted = successor.tcp_listener(addr).unwrap().unwrap();
+            assert_eq!(adopted.local_addr().unwrap(), addr);
+            successor.complete().unwrap();
+        });
+
+        let ev = Event { read: true, write: false, owner: handoff.control.id() };
+        while handoff.successor.is_none() {
+            if let Reaction::Value(Err(e)) = handoff.react(Reaction::Event(ev)) {
+                match e {
+                    crate::Error::Io(ref e) if e.kind() == ErrorKind::WouldBlock => thread::yield_now(),
+                    e => panic!("{:?}", e),
+                }
+            }
+        }
+
+        successor.join().unwrap();
+        let owner = handoff.successor.as_ref().unwrap().id;
+        let ev = Event { read: true, write: false, owner };
+        assert!(matches!(handoff.react(Reaction::Event(ev)), Reaction::Value(Ok(()))));
+        drop(handoff);
+        assert!(!path.exists());
+    }
+}
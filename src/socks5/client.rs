@@ -0,0 +1,448 @@
+use std::io::{self, ErrorKind, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+
+use super::proto;
+use super::{Address, ReplyCode};
+use crate::net::tcp::TcpStream;
+use crate::net::udp::UdpSocket;
+use crate::{Error, Event, Interest, Reaction, Reactor, Result, System};
+
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+// -----------------------------------------------------------------------------
+//     - Handshake -
+// -----------------------------------------------------------------------------
+enum State {
+    Connecting,
+    Greeting,
+    Auth,
+    Request,
+}
+
+struct Handshake {
+    stream: TcpStream,
+    credentials: Option<(String, String)>,
+    command: u8,
+    target: Address,
+    state: State,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl Handshake {
+    fn new(proxy: impl ToSocketAddrs, command: u8, target: Address) -> Result<Self> {
+        let handshake = Self {
+            stream: TcpStream::connect(proxy)?,
+            credentials: None,
+            command,
+            target,
+            state: State::Connecting,
+            buf: Vec::new(),
+            out: Vec::new(),
+        };
+        Ok(handshake)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        while !self.out.is_empty() {
+            match self.stream.write(&self.out) {
+                Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(n) => {
+                    self.out.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    // The bound address from the reply, once the proxy granted the request
+    fn react(&mut self, ev: Event) -> Result<Option<Address>> {
+        if let State::Connecting = self.state {
+            if let Some(e) = self.stream.as_ref().take_error()? {
+                return Err(e.into());
+            }
+
+            if !ev.write {
+                self.stream.rearm(Interest::ReadWrite)?;
+                return Ok(None);
+            }
+
+            let methods: &[u8] = match self.credentials {
+                Some(_) => &[proto::NO_AUTH, proto::USER_PASS],
+                None => &[proto::NO_AUTH],
+            };
+            self.out.extend_from_slice(&[proto::VERSION, methods.len() as u8]);
+            self.out.extend_from_slice(methods);
+            self.state = State::Greeting;
+        }
+
+        loop {
+            self.flush()?;
+            let stream = &mut self.stream;
+            let buf = &mut self.buf;
+
+            self.state = match self.state {
+                State::Connecting => unreachable!(),
+                State::Greeting => {
+                    let method = match proto::read_message(stream, buf, |b| proto::answer(b, proto::VERSION))? {
+                        Some(method) => method,
+                        None => break,
+                    };
+
+                    match (method, &self.credentials) {
+                        (proto::NO_AUTH, _) => {
+                            proto::write_request(self.command, &self.target, &mut self.out)?;
+                            State::Request
+                        }
+                        (proto::USER_PASS, Some((user, password))) => {
+                            proto::write_credentials(user, password, &mut self.out)?;
+                            State::Auth
+                        }
+                        _ => return Err(Error::protocol("socks5: no acceptable authentication method")),
+                    }
+                }
+                State::Auth => match proto::read_message(stream, buf, |b| proto::answer(b, proto::AUTH_VERSION))? {
+                    Some(0) => {
+                        proto::write_request(self.command, &self.target, &mut self.out)?;
+                        State::Request
+                    }
+                    Some(_) => return Err(io::Error::new(ErrorKind::PermissionDenied, "socks5: authentication failed").into()),
+                    None => break,
+                },
+                State::Request => match proto::read_message(stream, buf, proto::request)? {
+                    Some((0, bound)) => {
+                        self.stream.rearm(Interest::Read)?;
+                        return Ok(Some(bound));
+                    }
+                    Some((code, _)) => return Err(request_failed(ReplyCode(code))),
+                    None => break,
+                },
+            };
+        }
+
+        match self.out.is_empty() {
+            true => self.stream.rearm(Interest::Read)?,
+            false => self.stream.rearm(Interest::ReadWrite)?,
+        }
+
+        Ok(None)
+    }
+}
+
+fn request_failed(code: ReplyCode) -> Error {
+    let (kind, msg) = match code {
+        ReplyCode::NOT_ALLOWED => (ErrorKind::PermissionDenied, "not allowed by ruleset"),
+        ReplyCode::NETWORK_UNREACHABLE => (ErrorKind::Other, "network unreachable"),
+        ReplyCode::HOST_UNREACHABLE => (ErrorKind::Other, "host unreachable"),
+        ReplyCode::CONNECTION_REFUSED => (ErrorKind::ConnectionRefused, "connection refused"),
+        ReplyCode::TTL_EXPIRED => (ErrorKind::TimedOut, "ttl expired"),
+        ReplyCode::COMMAND_NOT_SUPPORTED => (ErrorKind::Unsupported, "command not supported"),
+        ReplyCode::ADDRESS_NOT_SUPPORTED => (ErrorKind::Unsupported, "address type not supported"),
+        _ => (ErrorKind::Other, "general failure"),
+    };
+
+    io::Error::new(kind, format!("socks5: {}", msg)).into()
+}
+
+// Drive a handshake owned by a reactor, passing on other reactions
+fn react_handshake<T>(
+    handshake: &mut Option<Handshake>,
+    reaction: Reaction<()>,
+    done: impl FnOnce(Handshake, Address) -> Result<T>,
+) -> Reaction<Result<T>> {
+    let ev = match reaction {
+        Reaction::Event(ev) if handshake.as_ref().map(|h| h.stream.id) == Some(ev.owner) => ev,
+        Reaction::Event(ev) => return Reaction::Event(ev),
+        _ => return Reaction::Continue,
+    };
+
+    let res = match handshake.as_mut().map(|h| h.react(ev)) {
+        Some(Ok(None)) | None => return Reaction::Continue,
+        Some(Ok(Some(bound))) => Ok(bound),
+        Some(Err(e)) => Err(e),
+    };
+
+    let handshake = handshake.take().expect("handshake");
+    Reaction::Value(res.and_then(|bound| done(handshake, bound)))
+}
+
+// -----------------------------------------------------------------------------
+//     - Connect -
+// -----------------------------------------------------------------------------
+/// Connect to a target through a SOCKS5 proxy.
+///
+/// Yields the stream once the proxy has connected it, or the error if the
+/// proxy couldn't. The stream keeps its id and stays armed for reading;
+/// its events are passed on, as are all other events.
+pub struct Socks5Connect {
+    handshake: Option<Handshake>,
+}
+
+impl Socks5Connect {
+    /// Start connecting to the proxy. Host names in `target` are resolved
+    /// by the proxy.
+    pub fn new(proxy: impl ToSocketAddrs, target: impl Into<Address>) -> Result<Self> {
+        let handshake = Handshake::new(proxy, proto::CONNECT, target.into())?;
+        Ok(Self { handshake: Some(handshake) })
+    }
+
+    /// Authenticate with a username and password if the proxy asks for it.
+    pub fn auth(mut self, user: &str, password: &str) -> Self {
+        if let Some(handshake) = self.handshake.as_mut() {
+            handshake.credentials = Some((user.to_string(), password.to_string()));
+        }
+        self
+    }
+
+    /// The id of the stream, until it's yielded.
+    pub fn id(&self) -> Option<u64> {
+        self.handshake.as_ref().map(|h| h.stream.id)
+    }
+}
+
+impl Reactor for Socks5Connect {
+    type Input = ();
+    type Output = Result<TcpStream>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        react_handshake(&mut self.handshake, reaction, |handshake, _| Ok(handshake.stream))
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Associate -
+// -----------------------------------------------------------------------------
+/// Set up a UDP relay through a SOCKS5 proxy, yielding a `Socks5Udp`.
+pub struct Socks5Associate {
+    handshake: Option<Handshake>,
+    socket: Option<UdpSocket>,
+}
+
+impl Socks5Associate {
+    pub fn new(proxy: impl ToSocketAddrs) -> Result<Self> {
+        let proxy = proxy
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::from(io::Error::new(ErrorKind::InvalidInput, "could not resolve address")))?;
+
+        // The proxy is told where the datagrams will come from
+        let any = match proxy {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(any, 0))?;
+        let from = SocketAddr::new(any, socket.as_ref().local_addr()?.port());
+        let handshake = Handshake::new(proxy, proto::UDP_ASSOCIATE, Address::Ip(from))?;
+
+        Ok(Self { handshake: Some(handshake), socket: Some(socket) })
+    }
+
+    /// Authenticate with a username and password if the proxy asks for it.
+    pub fn auth(mut self, user: &str, password: &str) -> Self {
+        if let Some(handshake) = self.handshake.as_mut() {
+            handshake.credentials = Some((user.to_string(), password.to_string()));
+        }
+        self
+    }
+}
+
+impl Reactor for Socks5Associate {
+    type Input = ();
+    type Output = Result<Socks5Udp>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        let socket = &mut self.socket;
+        react_handshake(&mut self.handshake, reaction, |handshake, bound| {
+            let control = handshake.stream;
+            // An unspecified address means the relay is on the proxy's host
+            let relay = match bound.resolve()? {
+                relay if relay.ip().is_unspecified() => SocketAddr::new(control.as_ref().peer_addr()?.ip(), relay.port()),
+                relay => relay,
+            };
+
+            let udp = Socks5Udp {
+                control,
+                socket: socket.take().expect("socket"),
+                relay,
+                buf: Vec::new(),
+            };
+            Ok(udp)
+        })
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Udp -
+// -----------------------------------------------------------------------------
+/// Datagrams relayed by a SOCKS5 proxy.
+///
+/// A reactor yielding the datagrams received, with the address they came
+/// from. The relay lasts as long as the control connection: it ends with
+/// an `UnexpectedEof` error if the proxy closes it, or when this is
+/// dropped.
+pub struct Socks5Udp {
+    control: TcpStream,
+    socket: UdpSocket,
+    relay: SocketAddr,
+    buf: Vec<u8>,
+}
+
+impl Socks5Udp {
+    /// The id of the UDP socket.
+    pub fn id(&self) -> u64 {
+        self.socket.id
+    }
+
+    /// The address of the relay on the proxy.
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay
+    }
+
+    /// Send a datagram to `target` through the relay.
+    pub fn send_to(&mut self, payload: &[u8], target: impl Into<Address>) -> Result<()> {
+        let mut datagram = Vec::with_capacity(payload.len() + 22);
+        proto::write_udp_datagram(&target.into(), payload, &mut datagram)?;
+        self.socket.send_to(&datagram, self.relay)?;
+        Ok(())
+    }
+
+    /// Receive a datagram from the relay into `buf`, returning its length
+    /// and where it came from. Datagrams from anywhere else are dropped.
+    pub fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, Address)> {
+        self.buf.resize(MAX_DATAGRAM, 0);
+        loop {
+            let (n, from) = self.socket.recv_from(&mut self.buf)?;
+            if from != self.relay {
+                continue;
+            }
+
+            let (source, payload) = match proto::udp_datagram(&self.buf[..n]) {
+                Ok(datagram) => datagram,
+                Err(_) => continue,
+            };
+
+            let len = payload.len().min(buf.len());
+            buf[..len].copy_from_slice(&payload[..len]);
+            return Ok((len, source));
+        }
+    }
+
+    // Nothing is expected on the control connection but its end
+    fn watch_control(&mut self) -> Result<()> {
+        let mut discard = [0u8; 64];
+        loop {
+            match io::Read::read(&mut self.control, &mut discard) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "socks5: relay closed").into()),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Reactor for Socks5Udp {
+    type Input = ();
+    type Output = Result<(Address, Vec<u8>)>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        let ev = match reaction {
+            Reaction::Event(ev) if ev.owner == self.control.id => {
+                return match self.watch_control() {
+                    Ok(()) => Reaction::Continue,
+                    Err(e) => Reaction::Value(Err(e)),
+                };
+            }
+            Reaction::Event(ev) if ev.owner == self.socket.id => ev,
+            Reaction::Event(ev) => return Reaction::Event(ev),
+            _ => return Reaction::Continue,
+        };
+
+        let mut buf = vec![0u8; MAX_DATAGRAM];
+        match self.recv_from(&mut buf) {
+            Ok((n, source)) => {
+                buf.truncate(n);
+                // One datagram at a time: come back for the next one
+                System::defer(ev);
+                Reaction::Value(Ok((source, buf)))
+            }
+            Err(Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => Reaction::Continue,
+            Err(e) => Reaction::Value(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket as StdUdpSocket;
+    use std::thread;
+    use crate::net::tcp::TcpListener;
+    use crate::socks5::Socks5Server;
+
+    struct Roundtrip {
+        associate: Socks5Associate,
+        udp: Option<Socks5Udp>,
+        echo: SocketAddr,
+    }
+
+    impl Reactor for Roundtrip {
+        type Input = ();
+        type Output = ();
+
+        fn react(&mut self, reaction: Reaction<()>) -> Reaction<()> {
+            let reaction = match self.udp.as_mut() {
+                Some(udp) => udp.react(reaction),
+                None => match self.associate.react(reaction) {
+                    Reaction::Value(udp) => {
+                        let mut udp = udp.unwrap();
+                        udp.send_to(b"hello", self.echo).unwrap();
+                        self.udp = Some(udp);
+                        return Reaction::Continue;
+                    }
+                    Reaction::Event(ev) => Reaction::Event(ev),
+                    Reaction::Continue => Reaction::Continue,
+                },
+            };
+
+            if let Reaction::Value(datagram) = reaction {
+                assert_eq!(datagram.unwrap(), (Address::Ip(self.echo), b"hello".to_vec()));
+                System::stop();
+            }
+
+            Reaction::Continue
+        }
+    }
+
+    #[test]
+    fn udp_associate() {
+        let echo = StdUdpSocket::bind("127.0.0.1:0").unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            loop {
+                let (n, from) = echo.recv_from(&mut buf).unwrap();
+                echo.send_to(&buf[..n], from).unwrap();
+            }
+        });
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            System::builder().finish();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+            System::start(listener.chain(Socks5Server::new())).unwrap();
+        });
+        let proxy = rx.recv().unwrap();
+
+        System::builder().finish();
+        let roundtrip = Roundtrip {
+            associate: Socks5Associate::new(proxy).unwrap(),
+            udp: None,
+            echo: echo_addr,
+        };
+        System::start(roundtrip).unwrap();
+    }
+}
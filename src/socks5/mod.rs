@@ -0,0 +1,99 @@
+//! SOCKS5 (RFC 1928), with username/password authentication (RFC 1929).
+//!
+//! `Socks5Server` is a gateway serving the connections of a `TcpListener`:
+//! it relays `CONNECT` streams and `UDP ASSOCIATE` datagrams.
+//!
+//! `Socks5Connect` opens a stream through a proxy and yields it once the
+//! proxy has connected it, ready to be used like any other stream;
+//! `Socks5Associate` sets up a UDP relay and yields a `Socks5Udp`:
+//!
+//! ```no_run
+//! # use std::io::Write;
+//! # use netlib::{Reactor, System};
+//! # use netlib::socks5::Socks5Connect;
+//! System::builder().finish();
+//! let connect = Socks5Connect::new("127.0.0.1:1080", ("example.com", 80))
+//!     .unwrap()
+//!     .auth("user", "password")
+//!     .map(|stream| {
+//!         let _ = stream.unwrap().write_all(b"GET / HTTP/1.0\r\n\r\n");
+//!     });
+//! System::start(connect);
+//! ```
+mod client;
+mod proto;
+mod server;
+
+pub use client::{Socks5Associate, Socks5Connect, Socks5Udp};
+pub use server::Socks5Server;
+
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use crate::Result;
+
+// -----------------------------------------------------------------------------
+//     - Address -
+// -----------------------------------------------------------------------------
+/// A destination: an address, or a host name for the proxy to resolve.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl Address {
+    /// Resolve to a socket address. Host names are resolved
+    /// with a blocking lookup.
+    pub fn resolve(&self) -> Result<SocketAddr> {
+        let addr = match self {
+            Address::Ip(addr) => Some(*addr),
+            Address::Domain(host, port) => (host.as_str(), *port).to_socket_addrs()?.next(),
+        };
+
+        addr.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "could not resolve address").into())
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(addr: SocketAddr) -> Self {
+        Address::Ip(addr)
+    }
+}
+
+impl From<(&str, u16)> for Address {
+    fn from((host, port): (&str, u16)) -> Self {
+        match host.parse() {
+            Ok(ip) => Address::Ip(SocketAddr::new(ip, port)),
+            Err(_) => Address::Domain(host.to_string(), port),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Address::Ip(addr) => write!(f, "{}", addr),
+            Address::Domain(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Reply code -
+// -----------------------------------------------------------------------------
+/// The outcome of a request, as sent by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplyCode(pub u8);
+
+impl ReplyCode {
+    pub const SUCCEEDED: ReplyCode = ReplyCode(0);
+    pub const GENERAL_FAILURE: ReplyCode = ReplyCode(1);
+    pub const NOT_ALLOWED: ReplyCode = ReplyCode(2);
+    pub const NETWORK_UNREACHABLE: ReplyCode = ReplyCode(3);
+    pub const HOST_UNREACHABLE: ReplyCode = ReplyCode(4);
+    pub const CONNECTION_REFUSED: ReplyCode = ReplyCode(5);
+    pub const TTL_EXPIRED: ReplyCode = ReplyCode(6);
+    pub const COMMAND_NOT_SUPPORTED: ReplyCode = ReplyCode(7);
+    pub const ADDRESS_NOT_SUPPORTED: ReplyCode = ReplyCode(8);
+}
@@ -0,0 +1,286 @@
+use std::io::{self, ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::{Address, ReplyCode};
+use crate::net::tcp::TcpStream;
+use crate::{Error, Result};
+
+pub(crate) const VERSION: u8 = 5;
+pub(crate) const AUTH_VERSION: u8 = 1;
+
+pub(crate) const NO_AUTH: u8 = 0x00;
+pub(crate) const USER_PASS: u8 = 0x02;
+pub(crate) const NO_ACCEPTABLE: u8 = 0xff;
+
+pub(crate) const CONNECT: u8 = 1;
+pub(crate) const UDP_ASSOCIATE: u8 = 3;
+
+const IPV4: u8 = 1;
+const DOMAIN: u8 = 3;
+const IPV6: u8 = 4;
+
+// -----------------------------------------------------------------------------
+//     - Parsing -
+// -----------------------------------------------------------------------------
+/// A message, or how many bytes it takes at least.
+pub(crate) enum Parsed<T> {
+    Done(T),
+    More(usize),
+}
+
+/// Read the message at the front of the stream, never reading past it
+/// so what follows is left in the socket. Returns `Ok(None)` if the
+/// stream would block; `buf` holds the partial message until then.
+pub(crate) fn read_message<T, F>(stream: &mut TcpStream, buf: &mut Vec<u8>, parse: F) -> Result<Option<T>>
+where
+    F: Fn(&[u8]) -> Result<Parsed<T>>,
+{
+    loop {
+        let need = match parse(buf)? {
+            Parsed::Done(message) => {
+                buf.clear();
+                return Ok(Some(message));
+            }
+            Parsed::More(need) => need,
+        };
+
+        let len = buf.len();
+        buf.resize(need, 0);
+        let res = stream.read(&mut buf[len..]);
+        buf.truncate(len + *res.as_ref().unwrap_or(&0));
+
+        match res {
+            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::protocol(format!("socks5: {}", msg))
+}
+
+fn version(buf: &[u8], expected: u8) -> Result<()> {
+    match buf.first() {
+        Some(&v) if v != expected => Err(invalid("unsupported version")),
+        _ => Ok(()),
+    }
+}
+
+// The address at the front of `buf`, and its length
+fn address(buf: &[u8]) -> Result<Parsed<(Address, usize)>> {
+    let len = match buf.first() {
+        None => return Ok(Parsed::More(1)),
+        Some(&IPV4) => 1 + 4 + 2,
+        Some(&IPV6) => 1 + 16 + 2,
+        Some(&DOMAIN) if buf.len() < 2 => return Ok(Parsed::More(2)),
+        Some(&DOMAIN) => 2 + buf[1] as usize + 2,
+        Some(_) => return Err(invalid("unknown address type")),
+    };
+
+    if buf.len() < len {
+        return Ok(Parsed::More(len));
+    }
+
+    let port = u16::from_be_bytes([buf[len - 2], buf[len - 1]]);
+    let address = match buf[0] {
+        IPV4 => Address::Ip(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(buf[1], buf[2], buf[3], buf[4])), port)),
+        IPV6 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&buf[1..17]);
+            Address::Ip(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        _ => match std::str::from_utf8(&buf[2..len - 2]) {
+            Ok(host) => Address::Domain(host.to_string(), port),
+            Err(_) => return Err(invalid("host name is not utf-8")),
+        },
+    };
+
+    Ok(Parsed::Done((address, len)))
+}
+
+pub(crate) fn write_address(address: &Address, buf: &mut Vec<u8>) -> Result<()> {
+    let port = match address {
+        Address::Ip(SocketAddr::V4(addr)) => {
+            buf.push(IPV4);
+            buf.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Address::Ip(SocketAddr::V6(addr)) => {
+            buf.push(IPV6);
+            buf.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Address::Domain(host, port) => {
+            if host.len() > 255 {
+                return Err(invalid("host name too long"));
+            }
+            buf.extend_from_slice(&[DOMAIN, host.len() as u8]);
+            buf.extend_from_slice(host.as_bytes());
+            *port
+        }
+    };
+
+    buf.extend_from_slice(&port.to_be_bytes());
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+//     - Handshake messages -
+// -----------------------------------------------------------------------------
+/// The client's greeting: the authentication methods it supports.
+pub(crate) fn greeting(buf: &[u8]) -> Result<Parsed<Vec<u8>>> {
+    version(buf, VERSION)?;
+    match buf.len() {
+        0 | 1 => Ok(Parsed::More(2)),
+        len if len < 2 + buf[1] as usize => Ok(Parsed::More(2 + buf[1] as usize)),
+        _ => Ok(Parsed::Done(buf[2..].to_vec())),
+    }
+}
+
+/// The client's username and password.
+pub(crate) fn credentials(buf: &[u8]) -> Result<Parsed<(String, String)>> {
+    version(buf, AUTH_VERSION)?;
+    if buf.len() < 2 {
+        return Ok(Parsed::More(2));
+    }
+
+    let user_end = 2 + buf[1] as usize;
+    if buf.len() < user_end + 1 {
+        return Ok(Parsed::More(user_end + 1));
+    }
+
+    let end = user_end + 1 + buf[user_end] as usize;
+    if buf.len() < end {
+        return Ok(Parsed::More(end));
+    }
+
+    let text = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).map_err(|_| invalid("credentials are not utf-8"));
+    Ok(Parsed::Done((text(&buf[2..user_end])?, text(&buf[user_end + 1..end])?)))
+}
+
+/// A two byte answer: the chosen method, or the authentication status.
+pub(crate) fn answer(buf: &[u8], expected_version: u8) -> Result<Parsed<u8>> {
+    version(buf, expected_version)?;
+    match buf.len() {
+        0 | 1 => Ok(Parsed::More(2)),
+        _ => Ok(Parsed::Done(buf[1])),
+    }
+}
+
+/// A request (command, address) or a reply (code, address),
+/// which share a layout.
+pub(crate) fn request(buf: &[u8]) -> Result<Parsed<(u8, Address)>> {
+    version(buf, VERSION)?;
+    if buf.len() < 3 {
+        return Ok(Parsed::More(3));
+    }
+
+    match address(&buf[3..])? {
+        Parsed::Done((address, _)) => Ok(Parsed::Done((buf[1], address))),
+        Parsed::More(len) => Ok(Parsed::More(3 + len)),
+    }
+}
+
+pub(crate) fn write_request(command: u8, address: &Address, buf: &mut Vec<u8>) -> Result<()> {
+    buf.extend_from_slice(&[VERSION, command, 0]);
+    write_address(address, buf)
+}
+
+pub(crate) fn write_reply(code: ReplyCode, address: &Address, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&[VERSION, code.0, 0]);
+    // Only a host name can be too long, and replies carry addresses
+    let _ = write_address(address, buf);
+}
+
+pub(crate) fn write_credentials(user: &str, password: &str, buf: &mut Vec<u8>) -> Result<()> {
+    if user.len() > 255 || password.len() > 255 {
+        return Err(invalid("username or password too long"));
+    }
+
+    buf.extend_from_slice(&[AUTH_VERSION, user.len() as u8]);
+    buf.extend_from_slice(user.as_bytes());
+    buf.push(password.len() as u8);
+    buf.extend_from_slice(password.as_bytes());
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+//     - UDP -
+// -----------------------------------------------------------------------------
+/// The address and payload of a relayed datagram.
+pub(crate) fn udp_datagram(buf: &[u8]) -> Result<(Address, &[u8])> {
+    if buf.len() < 3 {
+        return Err(invalid("datagram too short"));
+    }
+
+    if buf[2] != 0 {
+        return Err(invalid("fragmented datagrams are not supported"));
+    }
+
+    match address(&buf[3..])? {
+        Parsed::Done((address, len)) => Ok((address, &buf[3 + len..])),
+        Parsed::More(_) => Err(invalid("datagram too short")),
+    }
+}
+
+pub(crate) fn write_udp_datagram(address: &Address, payload: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+    buf.extend_from_slice(&[0, 0, 0]);
+    write_address(address, buf)?;
+    buf.extend_from_slice(payload);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let addresses = vec![
+            Address::Ip("10.0.0.1:80".parse().unwrap()),
+            Address::Ip("[::1]:443".parse().unwrap()),
+            Address::Domain("example.com".into(), 8080),
+        ];
+
+        for address in addresses {
+            let mut buf = Vec::new();
+            write_request(CONNECT, &address, &mut buf).unwrap();
+
+            for len in 0..buf.len() {
+                match request(&buf[..len]).unwrap() {
+                    Parsed::More(need) => assert!(need > len && need <= buf.len()),
+                    Parsed::Done(_) => panic!("done after {} bytes", len),
+                }
+            }
+
+            match request(&buf).unwrap() {
+                Parsed::Done((command, parsed)) => assert_eq!((command, parsed), (CONNECT, address)),
+                Parsed::More(_) => panic!("incomplete"),
+            }
+        }
+
+        assert!(request(&[4, 1, 0, 1]).is_err());
+        assert!(request(&[5, 1, 0, 9]).is_err());
+    }
+
+    #[test]
+    fn credentials_and_datagrams() {
+        let mut buf = Vec::new();
+        write_credentials("user", "secret", &mut buf).unwrap();
+        match credentials(&buf).unwrap() {
+            Parsed::Done(creds) => assert_eq!(creds, ("user".to_string(), "secret".to_string())),
+            Parsed::More(_) => panic!("incomplete"),
+        }
+
+        let mut buf = Vec::new();
+        let address = Address::Domain("localhost".into(), 53);
+        write_udp_datagram(&address, b"query", &mut buf).unwrap();
+        assert_eq!(udp_datagram(&buf).unwrap(), (address, &b"query"[..]));
+
+        buf[2] = 1;
+        assert!(udp_datagram(&buf).is_err());
+    }
+}
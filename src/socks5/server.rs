@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream as StdTcpStream};
+
+use super::proto;
+use super::{Address, ReplyCode};
+use crate::net::forward::{self, Half};
+use crate::net::tcp::{Accepted, ConnectionSlot, TcpStream};
+use crate::net::udp::UdpSocket;
+use crate::{Event, Interest, Reaction, Reactor, Result};
+
+const RELAY_BUFFER_SIZE: usize = 16 * 1024;
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+// -----------------------------------------------------------------------------
+//     - Relay -
+// -----------------------------------------------------------------------------
+struct Relay {
+    target: TcpStream,
+    // Client to target
+    up: Half,
+    // Target to client
+    down: Half,
+}
+
+// Datagrams from the client are sent on to the address in their header,
+// everything else goes back to the client with the sender's address.
+struct UdpRelay {
+    socket: UdpSocket,
+    client_ip: IpAddr,
+    // The port the client said it would send from, 0 if it didn't know
+    client_port: u16,
+    client: Option<SocketAddr>,
+    buf: Vec<u8>,
+}
+
+impl UdpRelay {
+    fn is_client(&self, from: SocketAddr) -> bool {
+        match self.client {
+            Some(client) => client == from,
+            None => from.ip() == self.client_ip && (self.client_port == 0 || from.port() == self.client_port),
+        }
+    }
+
+    // Datagrams that can't be relayed are dropped
+    fn pump(&mut self) -> Result<()> {
+        let mut out = Vec::new();
+        self.buf.resize(MAX_DATAGRAM, 0);
+
+        loop {
+            let (n, from) = match self.socket.recv_from(&mut self.buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+
+            if self.is_client(from) {
+                self.client = Some(from);
+                let target = match proto::udp_datagram(&self.buf[..n]) {
+                    Ok((target, payload)) => target.resolve().map(|target| (target, payload)),
+                    Err(e) => Err(e),
+                };
+
+                if let Ok((target, payload)) = target {
+                    let _ = self.socket.as_ref().send_to(payload, target);
+                }
+            } else if let Some(client) = self.client {
+                out.clear();
+                proto::write_udp_datagram(&Address::Ip(from), &self.buf[..n], &mut out)?;
+                let _ = self.socket.as_ref().send_to(&out, client);
+            }
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Connection -
+// -----------------------------------------------------------------------------
+enum Stage {
+    Greeting,
+    Auth,
+    Request,
+    Connecting(TcpStream),
+    Relay(Relay),
+    Udp(UdpRelay),
+    // The reply is written, then the connection closed
+    Failed,
+}
+
+struct Connection {
+    stream: TcpStream,
+    peer: SocketAddr,
+    stage: Stage,
+    // A partial handshake message
+    buf: Vec<u8>,
+    // Handshake answers not yet written
+    out: Vec<u8>,
+    _slot: Option<ConnectionSlot>,
+}
+
+impl Connection {
+    // Write the handshake answers, true once they're all written
+    fn flush(&mut self) -> Result<bool> {
+        while !self.out.is_empty() {
+            match self.stream.write(&self.out) {
+                Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(n) => {
+                    self.out.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(true)
+    }
+
+    fn reply(&mut self, code: ReplyCode, bound: SocketAddr) {
+        proto::write_reply(code, &Address::Ip(bound), &mut self.out);
+    }
+
+    fn fail(&mut self, code: ReplyCode) {
+        self.reply(code, SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0));
+        self.stage = Stage::Failed;
+    }
+}
+
+fn reply_code(e: &crate::Error) -> ReplyCode {
+    let e = match e {
+        crate::Error::Io(e) => e,
+        _ => return ReplyCode::GENERAL_FAILURE,
+    };
+
+    match e.raw_os_error() {
+        Some(libc::ECONNREFUSED) => ReplyCode::CONNECTION_REFUSED,
+        Some(libc::ENETUNREACH) => ReplyCode::NETWORK_UNREACHABLE,
+        Some(libc::EHOSTUNREACH) | Some(libc::ETIMEDOUT) => ReplyCode::HOST_UNREACHABLE,
+        _ if e.kind() == ErrorKind::InvalidInput => ReplyCode::HOST_UNREACHABLE,
+        _ => ReplyCode::GENERAL_FAILURE,
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Socks5 server -
+// -----------------------------------------------------------------------------
+/// A SOCKS5 gateway for the connections of a `TcpListener`.
+///
+/// `CONNECT` requests are relayed in both directions until both sides
+/// have closed. For `UDP ASSOCIATE` a UDP socket is bound on the address
+/// the client connected to, and datagrams are relayed for as long as the
+/// client keeps its TCP connection open. Host names are resolved with a
+/// blocking lookup. `BIND` is not supported.
+///
+/// Without credentials no authentication is required; with them every
+/// client has to log in with a username and password.
+///
+/// Yields `Ok(())` for every connection that is done, and the error of
+/// every connection that can't be accepted or fails, whether in the
+/// handshake, connecting to the target or relaying.
+///
+/// ```no_run
+/// # use netlib::{Reactor, System};
+/// # use netlib::net::tcp::TcpListener;
+/// # use netlib::socks5::Socks5Server;
+/// System::builder().finish();
+/// let gateway = Socks5Server::new().credentials(|user, password| user == "admin" && password == "hunter2");
+/// System::start(TcpListener::bind("127.0.0.1:1080").unwrap().chain(gateway));
+/// ```
+pub struct Socks5Server {
+    credentials: Option<Box<dyn FnMut(&str, &str) -> bool>>,
+    udp: bool,
+    connections: HashMap<u64, Connection>,
+    // Target streams and UDP sockets to their connection
+    owners: HashMap<u64, u64>,
+}
+
+impl Socks5Server {
+    pub fn new() -> Self {
+        Self {
+            credentials: None,
+            udp: true,
+            connections: HashMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+
+    /// Require a username and password, accepted if `check` returns true.
+    pub fn credentials<F>(mut self, check: F) -> Self
+    where
+        F: FnMut(&str, &str) -> bool + 'static,
+    {
+        self.credentials = Some(Box::new(check));
+        self
+    }
+
+    /// Allow `UDP ASSOCIATE`. Enabled by default.
+    pub fn udp(mut self, enabled: bool) -> Self {
+        self.udp = enabled;
+        self
+    }
+
+    /// The number of client connections, whatever their stage.
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    fn add(&mut self, stream: StdTcpStream, peer: SocketAddr, slot: Option<ConnectionSlot>) -> Result<()> {
+        let stream = TcpStream::new(stream, Interest::Read)?;
+        let connection = Connection {
+            stream,
+            peer,
+            stage: Stage::Greeting,
+            buf: Vec::new(),
+            out: Vec::new(),
+            _slot: slot,
+        };
+        self.connections.insert(connection.stream.id, connection);
+        Ok(())
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some(connection) = self.connections.remove(&id) {
+            match connection.stage {
+                Stage::Connecting(target) | Stage::Relay(Relay { target, .. }) => self.owners.remove(&target.id),
+                Stage::Udp(udp) => self.owners.remove(&udp.socket.id),
+                _ => None,
+            };
+        }
+    }
+
+    // Move the connection along as far as it goes, true once it's done
+    fn advance(&mut self, id: u64, ev: Event) -> Result<bool> {
+        let connection = self.connections.get_mut(&id).expect("connection");
+
+        loop {
+            if !connection.flush()? {
+                connection.stream.rearm(Interest::Write)?;
+                return Ok(false);
+            }
+
+            let stream = &mut connection.stream;
+            let buf = &mut connection.buf;
+            match &mut connection.stage {
+                Stage::Greeting => {
+                    let methods = match proto::read_message(stream, buf, proto::greeting)? {
+                        Some(methods) => methods,
+                        None => break,
+                    };
+
+                    let (method, next) = match self.credentials {
+                        Some(_) if methods.contains(&proto::USER_PASS) => (proto::USER_PASS, Stage::Auth),
+                        None if methods.contains(&proto::NO_AUTH) => (proto::NO_AUTH, Stage::Request),
+                        _ => (proto::NO_ACCEPTABLE, Stage::Failed),
+                    };
+
+                    connection.out.extend_from_slice(&[proto::VERSION, method]);
+                    connection.stage = next;
+                }
+                Stage::Auth => {
+                    let (user, password) = match proto::read_message(stream, buf, proto::credentials)? {
+                        Some(credentials) => credentials,
+                        None => break,
+                    };
+
+                    let valid = self.credentials.as_mut().map(|check| check(&user, &password)).unwrap_or(false);
+                    connection.out.extend_from_slice(&[proto::AUTH_VERSION, !valid as u8]);
+                    connection.stage = match valid {
+                        true => Stage::Request,
+                        false => Stage::Failed,
+                    };
+                }
+                Stage::Request => {
+                    let (command, address) = match proto::read_message(stream, buf, proto::request)? {
+                        Some(request) => request,
+                        None => break,
+                    };
+
+                    match command {
+                        proto::CONNECT => match address.resolve().and_then(TcpStream::connect) {
+                            Ok(target) => {
+                                self.owners.insert(target.id, id);
+                                connection.stage = Stage::Connecting(target);
+                            }
+                            Err(e) => connection.fail(reply_code(&e)),
+                        },
+                        proto::UDP_ASSOCIATE if self.udp => {
+                            let bind = SocketAddr::new(stream.as_ref().local_addr()?.ip(), 0);
+                            let socket = UdpSocket::bind(bind)?;
+                            let bound = socket.as_ref().local_addr()?;
+                            let client_port = match address {
+                                Address::Ip(addr) => addr.port(),
+                                Address::Domain(_, port) => port,
+                            };
+
+                            self.owners.insert(socket.id, id);
+                            connection.reply(ReplyCode::SUCCEEDED, bound);
+                            connection.stage = Stage::Udp(UdpRelay {
+                                socket,
+                                client_ip: connection.peer.ip(),
+                                client_port,
+                                client: None,
+                                buf: Vec::new(),
+                            });
+                        }
+                        _ => connection.fail(ReplyCode::COMMAND_NOT_SUPPORTED),
+                    }
+                }
+                Stage::Connecting(target) => {
+                    if ev.owner != target.id {
+                        // The client waits for the reply: leave its data for the relay
+                        return Ok(false);
+                    }
+
+                    if let Some(e) = target.as_ref().take_error()? {
+                        connection.fail(reply_code(&e.into()));
+                        continue;
+                    }
+
+                    if !ev.write {
+                        target.rearm(Interest::ReadWrite)?;
+                        return Ok(false);
+                    }
+
+                    let bound = target.as_ref().local_addr()?;
+                    let target = match std::mem::replace(&mut connection.stage, Stage::Failed) {
+                        Stage::Connecting(target) => target,
+                        _ => unreachable!(),
+                    };
+                    connection.reply(ReplyCode::SUCCEEDED, bound);
+                    connection.stage = Stage::Relay(Relay {
+                        target,
+                        up: Half::new(RELAY_BUFFER_SIZE, true),
+                        down: Half::new(RELAY_BUFFER_SIZE, true),
+                    });
+                }
+                Stage::Relay(relay) => return forward::relay(stream, &mut relay.target, &mut relay.up, &mut relay.down),
+                Stage::Udp(udp) => {
+                    if ev.owner == udp.socket.id {
+                        udp.pump()?;
+                        return Ok(false);
+                    }
+
+                    // The association lasts as long as the connection
+                    let mut discard = [0u8; 512];
+                    loop {
+                        match stream.read(&mut discard) {
+                            Ok(0) => return Ok(true),
+                            Ok(_) => {}
+                            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                }
+                Stage::Failed => return Ok(true),
+            }
+        }
+
+        // Waiting for more of the handshake
+        connection.stream.rearm(Interest::Read)?;
+        Ok(false)
+    }
+}
+
+impl Default for Socks5Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reactor for Socks5Server {
+    type Input = Result<Accepted>;
+    type Output = Result<()>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        let ev = match reaction {
+            Reaction::Value(Ok((stream, peer, slot))) => {
+                return match self.add(stream, peer, slot) {
+                    Ok(()) => Reaction::Continue,
+                    Err(e) => Reaction::Value(Err(e)),
+                };
+            }
+            Reaction::Value(Err(e)) => return Reaction::Value(Err(e)),
+            Reaction::Event(ev) => ev,
+            Reaction::Continue => return Reaction::Continue,
+        };
+
+        let id = match self.owners.get(&ev.owner) {
+            Some(&id) => id,
+            None if self.connections.contains_key(&ev.owner) => ev.owner,
+            None => return Reaction::Event(ev),
+        };
+
+        match self.advance(id, ev) {
+            Ok(false) => Reaction::Continue,
+            res => {
+                self.remove(id);
+                Reaction::Value(res.map(|_| ()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener as StdTcpListener;
+    use std::thread;
+    use crate::net::tcp::TcpListener;
+    use crate::socks5::Socks5Connect;
+    use crate::System;
+
+    // Echoes every connection until it's closed
+    fn echo_server() -> SocketAddr {
+        let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut reader = stream.try_clone().unwrap();
+                    let _ = io::copy(&mut reader, &mut stream);
+                });
+            }
+        });
+        addr
+    }
+
+    fn gateway() -> SocketAddr {
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            System::builder().finish();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+            let server = Socks5Server::new().credentials(|user, password| user == "user" && password == "secret");
+            System::start(listener.chain(server)).unwrap();
+        });
+        rx.recv().unwrap()
+    }
+
+    #[test]
+    fn connect_with_auth() {
+        let echo = echo_server();
+        let proxy = gateway();
+
+        System::builder().finish();
+        let both = Both {
+            refused: Socks5Connect::new(proxy, echo).unwrap().auth("user", "wrong"),
+            connect: Socks5Connect::new(proxy, echo).unwrap().auth("user", "secret"),
+            done: 0,
+        };
+        System::start(both).unwrap();
+    }
+
+    struct Both {
+        refused: Socks5Connect,
+        connect: Socks5Connect,
+        done: usize,
+    }
+
+    impl Reactor for Both {
+        type Input = ();
+        type Output = ();
+
+        fn react(&mut self, reaction: Reaction<()>) -> Reaction<()> {
+            let ev = match reaction {
+                Reaction::Event(ev) => ev,
+                _ => return Reaction::Continue,
+            };
+
+            if let Reaction::Value(res) = self.refused.react(Reaction::Event(ev)) {
+                assert!(res.is_err());
+                self.done += 1;
+            }
+
+            if let Reaction::Value(res) = self.connect.react(Reaction::Event(ev)) {
+                let mut stream = res.unwrap();
+                stream.as_mut().set_nonblocking(false).unwrap();
+                stream.as_mut().write_all(b"ping").unwrap();
+                let mut buf = [0u8; 4];
+                stream.as_mut().read_exact(&mut buf).unwrap();
+                assert_eq!(&buf, b"ping");
+                self.done += 1;
+            }
+
+            if self.done == 2 {
+                System::stop();
+            }
+
+            Reaction::Continue
+        }
+    }
+}
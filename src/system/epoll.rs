@@ -55,6 +55,14 @@ pub(crate) fn rearm(epoll_fd: i32, fd: i32, interest: Interest, user_data: u64)
     Ok(())
 }
 
+pub(crate) fn disarm(epoll_fd: i32, fd: i32) -> Result<()> {
+    // Kernels before 2.6.9 want an event even though it's ignored
+    let mut event = libc::epoll_event { events: 0, u64: 0 };
+    let status = unsafe { libc::epoll_ctl(epoll_fd, libc::EPOLL_CTL_DEL, fd, &mut event as *mut libc::epoll_event) };
+    let _ = res!(status);
+    Ok(())
+}
+
 fn epoll_control(epoll_fd: i32, fd: i32, interest: Interest, user_data: u64, op: i32) -> Result<()> {
     let events = Flags::EdgeTriggered as u32 | Flags::OneShot as u32 | interest.to_u32();
 
@@ -1,15 +1,18 @@
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::os::unix::io::AsRawFd;
 
-use crate::{Reaction, Reactor, Result};
+use crate::{Event, Reaction, Reactor, Result};
 // use crate::signals::{Sender, Receiver, signal};
 
 mod identities;
 mod epoll;
 pub(crate) mod evented;
 pub(crate) mod timer;
+pub(crate) mod pool;
 
 use identities::Identities;
+use pool::Pool;
 use epoll::Flags;
 pub use epoll::Interest;
 
@@ -39,6 +42,7 @@ pub enum SystemState {
 pub struct SystemBuilder {
     event_cap: Option<usize>,
     id_capacity: Option<usize>,
+    buffer_pool: Option<(usize, usize)>,
 }
 
 impl SystemBuilder {
@@ -56,12 +60,22 @@ impl SystemBuilder {
         self
     }
 
+    /// Set the size of the buffers in the `BufferPool`, and how many are
+    /// kept while not in use. Defaults to 16KiB and 256.
+    pub fn buffer_pool(&mut self, buffer_size: usize, max_idle: usize) -> &mut Self {
+        self.buffer_pool = Some((buffer_size, max_idle));
+        self
+    }
+
     /// Finish the `System` and set it up for the local thread.
-    pub fn finish(self) {
+    pub fn finish(&mut self) {
         let reactor_ids = self.id_capacity.unwrap_or(1024);
         let event_cap = self.event_cap.unwrap_or(10);
 
-        let sys = System::init(event_cap, reactor_ids);
+        let (buffer_size, max_idle) = self.buffer_pool.unwrap_or((pool::DEFAULT_BUFFER_SIZE, pool::DEFAULT_MAX_IDLE));
+
+        let mut sys = System::init(event_cap, reactor_ids);
+        sys.pool = Pool::new(buffer_size, max_idle);
 
         SYSTEM.with(|existing| *existing.borrow_mut() = SystemState::Running(sys));
 
@@ -82,6 +96,9 @@ pub struct System {
     epoll_fd: i32,
     identities: Identities,
     event_cap: usize,
+    deferred: VecDeque<Event>,
+    stopping: bool,
+    pool: Pool,
     // rx: Option<Receiver<SysEvent>>,
 }
 
@@ -93,6 +110,9 @@ impl System {
             epoll_fd,
             event_cap,
             identities: Identities::with_capacity(id_cap),
+            deferred: VecDeque::new(),
+            stopping: false,
+            pool: Pool::new(pool::DEFAULT_BUFFER_SIZE, pool::DEFAULT_MAX_IDLE),
             // rx: None,
         }
     }
@@ -108,6 +128,7 @@ impl System {
         SystemBuilder {
             event_cap: None,
             id_capacity: None,
+            buffer_pool: None,
         }
     }
 
@@ -166,6 +187,64 @@ impl System {
         Ok(())
     }
 
+    /// Remove the file descriptor from epoll, e.g. to hand it to
+    /// another reactor, which arms it again under its own id.
+    pub fn disarm(as_fd: &impl AsRawFd) -> Result<()> {
+        SYSTEM.with(|sys| match *sys.borrow() {
+            SystemState::Empty => panic!("System is uninitialized"),
+            SystemState::Running(ref sys) => epoll::disarm(sys.epoll_fd, as_fd.as_raw_fd()),
+            SystemState::Stopped(_) => panic!("System stopped"),
+        })
+    }
+
+    /// Queue an event to be delivered to the reactors once the current
+    /// batch of epoll events has been processed.
+    /// A reactor can use this to be called again without rearming,
+    /// e.g. to drain a listen backlog. Events deferred while the queue is
+    /// being delivered wait for the next pass, after polling again, so a
+    /// reactor deferring over and over doesn't starve the others.
+    pub fn defer(event: Event) {
+        SYSTEM.with(|sys| match *sys.borrow_mut() {
+            SystemState::Empty => panic!("System is uninitialized"),
+            SystemState::Running(ref mut s) => s.deferred.push_back(event),
+            SystemState::Stopped(_) => panic!("System stopped"),
+        });
+    }
+
+    fn take_deferred() -> VecDeque<Event> {
+        SYSTEM.with(|sys| match *sys.borrow_mut() {
+            SystemState::Empty => panic!("System is uninitialized"),
+            SystemState::Running(ref mut s) => std::mem::take(&mut s.deferred),
+            SystemState::Stopped(_) => panic!("System stopped"),
+        })
+    }
+
+    fn has_deferred() -> bool {
+        SYSTEM.with(|sys| match *sys.borrow() {
+            SystemState::Empty => panic!("System is uninitialized"),
+            SystemState::Running(ref s) => !s.deferred.is_empty(),
+            SystemState::Stopped(_) => panic!("System stopped"),
+        })
+    }
+
+    /// Stop the system once the current batch of events has been processed.
+    /// `System::start` then returns.
+    pub fn stop() {
+        SYSTEM.with(|sys| match *sys.borrow_mut() {
+            SystemState::Empty => panic!("System is uninitialized"),
+            SystemState::Running(ref mut s) => s.stopping = true,
+            SystemState::Stopped(_) => panic!("System stopped"),
+        });
+    }
+
+    fn is_stopping() -> bool {
+        SYSTEM.with(|sys| match *sys.borrow() {
+            SystemState::Empty => panic!("System is uninitialized"),
+            SystemState::Running(ref s) => s.stopping,
+            SystemState::Stopped(_) => panic!("System stopped"),
+        })
+    }
+
     /// Start polling for events.
     pub fn start<T>(mut reactor: T) -> Result<()>
     where
@@ -178,8 +257,7 @@ impl System {
         });
 
         // let mut rx = SYSTEM.with(|sys| sys.borrow_mut().rx.take());//.expect("this should never be None");
-        let mut events: Vec<libc::epoll_event> = Vec::with_capacity(event_cap);
-        unsafe { events.set_len(event_cap) };
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; event_cap];
 
         let timeout = 200;
         // Have zero ms timeout for epoll.
@@ -189,13 +267,19 @@ impl System {
         // 2. Check user defined events
         // 3. ??? <-- don't cook the fish
         'system: loop {
+            // Don't wait for epoll while there are deferred events
+            let timeout = match System::has_deferred() {
+                true => 0,
+                false => timeout,
+            };
+
             let count = SYSTEM.with(|sys| match *sys.borrow() {
                 SystemState::Empty => panic!("System is uninitialized"),
                 SystemState::Stopped(_) => panic!("System stopped"),
                 SystemState::Running(ref sys) => epoll::wait(sys.epoll_fd, &mut events, event_cap as i32, timeout)
             })?;
 
-            for epoll_event in events.drain(..count) {
+            for epoll_event in &events[..count] {
                 let event = crate::Event {
                     read: Flags::contains(epoll_event.events, Flags::Read),
                     write: Flags::contains(epoll_event.events, Flags::Write),
@@ -219,7 +303,15 @@ impl System {
                 // }
             }
 
-            unsafe { events.set_len(event_cap) };
+            // Only what was deferred before this pass
+            for event in System::take_deferred() {
+                reactor.react(Reaction::Event(event));
+            }
+
+            if System::is_stopping() {
+                break 'system;
+            }
+
             // Run game loop
         }
 
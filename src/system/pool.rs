@@ -0,0 +1,162 @@
+use super::{SystemState, SYSTEM};
+
+pub(super) const DEFAULT_BUFFER_SIZE: usize = 16 * 1024;
+pub(super) const DEFAULT_MAX_IDLE: usize = 256;
+
+// -----------------------------------------------------------------------------
+//     - Pool stats -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PoolStats {
+    /// Buffers lent out of the pool.
+    pub hits: u64,
+    /// Buffers allocated because the pool was empty.
+    pub misses: u64,
+    /// Buffers given back and kept.
+    pub returned: u64,
+    /// Buffers given back but dropped: the pool was full, or the buffer
+    /// had grown.
+    pub discarded: u64,
+    /// Buffers in the pool right now.
+    pub idle: usize,
+}
+
+// -----------------------------------------------------------------------------
+//     - Pool -
+// -----------------------------------------------------------------------------
+pub(super) struct Pool {
+    buffers: Vec<Vec<u8>>,
+    buffer_size: usize,
+    max_idle: usize,
+    stats: PoolStats,
+}
+
+impl Pool {
+    pub(super) fn new(buffer_size: usize, max_idle: usize) -> Self {
+        Self {
+            buffers: Vec::new(),
+            buffer_size: buffer_size.max(1),
+            max_idle,
+            stats: PoolStats::default(),
+        }
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        match self.buffers.pop() {
+            Some(buf) => {
+                self.stats.hits += 1;
+                buf
+            }
+            None => {
+                self.stats.misses += 1;
+                Vec::with_capacity(self.buffer_size)
+            }
+        }
+    }
+
+    fn give(&mut self, mut buf: Vec<u8>) {
+        if buf.capacity() != self.buffer_size || self.buffers.len() >= self.max_idle {
+            self.stats.discarded += 1;
+            return;
+        }
+
+        buf.clear();
+        self.buffers.push(buf);
+        self.stats.returned += 1;
+    }
+
+    fn stats(&self) -> PoolStats {
+        PoolStats { idle: self.buffers.len(), ..self.stats }
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Buffer pool -
+// -----------------------------------------------------------------------------
+/// Fixed-size buffers shared by everything running on the thread's `System`.
+///
+/// A buffer is taken when there is something to read or write and given
+/// back once it's empty again, so idle connections hold no buffers.
+/// The size of the buffers and how many are kept while not in use are set
+/// with `SystemBuilder::buffer_pool`.
+///
+/// ```
+/// # use netlib::{BufferPool, System};
+/// System::builder().buffer_pool(4096, 16).finish();
+/// let mut buf = BufferPool::take();
+/// buf.extend_from_slice(b"hello");
+/// BufferPool::give(buf);
+///
+/// let buf = BufferPool::take();
+/// assert!(buf.is_empty());
+/// assert_eq!(buf.capacity(), 4096);
+/// assert_eq!(BufferPool::stats().hits, 1);
+/// ```
+pub struct BufferPool;
+
+impl BufferPool {
+    /// An empty buffer with the capacity of the pool's buffers.
+    pub fn take() -> Vec<u8> {
+        SYSTEM.with(|sys| match *sys.borrow_mut() {
+            SystemState::Empty => panic!("System is uninitialized"),
+            SystemState::Running(ref mut s) => s.pool.take(),
+            SystemState::Stopped(_) => panic!("System stopped"),
+        })
+    }
+
+    /// Give a buffer back to the pool. It's dropped instead if the pool
+    /// is full or its capacity is no longer that of the pool's buffers.
+    /// This is safe to call when the system is gone, e.g. in `Drop`.
+    pub fn give(buf: Vec<u8>) {
+        let _ = SYSTEM.try_with(|sys| {
+            if let Ok(mut sys) = sys.try_borrow_mut() {
+                if let SystemState::Running(ref mut s) = *sys {
+                    s.pool.give(buf);
+                }
+            }
+        });
+    }
+
+    /// The capacity of the pool's buffers.
+    pub fn buffer_size() -> usize {
+        SYSTEM.with(|sys| match *sys.borrow() {
+            SystemState::Empty => panic!("System is uninitialized"),
+            SystemState::Running(ref s) => s.pool.buffer_size,
+            SystemState::Stopped(_) => panic!("System stopped"),
+        })
+    }
+
+    pub fn stats() -> PoolStats {
+        SYSTEM.with(|sys| match *sys.borrow() {
+            SystemState::Empty => panic!("System is uninitialized"),
+            SystemState::Running(ref s) => s.pool.stats(),
+            SystemState::Stopped(_) => panic!("System stopped"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::System;
+
+    #[test]
+    fn hits_misses_and_discards() {
+        System::builder().buffer_pool(1024, 1).finish();
+
+        let a = BufferPool::take();
+        let b = BufferPool::take();
+        BufferPool::give(a);
+        // The pool only keeps one
+        BufferPool::give(b);
+
+        let mut c = BufferPool::take();
+        c.extend_from_slice(&[0; 2048]);
+        // Grown past the buffer size
+        BufferPool::give(c);
+
+        let stats = BufferPool::stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!((stats.returned, stats.discarded, stats.idle), (1, 2, 0));
+    }
+}
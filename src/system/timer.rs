@@ -12,10 +12,12 @@ pub struct Timer {
 impl Timer {
     pub fn new(expiration: Duration, interval: Option<Duration>) -> Result<Self> {
         let flags = libc::EFD_CLOEXEC | libc::EFD_NONBLOCK;
-        let reactor_id = System::reserve();
         let clock_id = libc::CLOCK_MONOTONIC;
         let fd = res!(unsafe { libc::timerfd_create(clock_id, flags) });
 
+        // From here on dropping `inst` closes the fd and frees the id
+        let inst = Self { fd, reactor_id: System::reserve() };
+
         let interval = match interval {
             Some(d) => libc::timespec {
                 tv_sec: d.as_secs() as i64,
@@ -38,16 +40,15 @@ impl Timer {
         let flags = 0;
         let _ = res!(unsafe {
             libc::timerfd_settime(
-                fd,
+                inst.fd,
                 flags,
                 &new_value as *const libc::itimerspec,
                 std::ptr::null_mut(), // old_value
             )
         });
 
-        System::arm(&fd, Interest::Read, reactor_id)?;
+        System::arm(&inst.fd, Interest::Read, inst.reactor_id)?;
 
-        let inst = Self { fd, reactor_id };
         Ok(inst)
     }
 
@@ -63,6 +64,13 @@ impl Timer {
     }
 }
 
+impl Drop for Timer {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+        System::free(self.reactor_id);
+    }
+}
+
 // -----------------------------------------------------------------------------
 //     - Write -
 // -----------------------------------------------------------------------------
@@ -0,0 +1,425 @@
+use super::{TelnetEvent, TelnetFrame, TelnetOption};
+use crate::codecs::{Decoder, Encoder};
+use crate::{Error, Result};
+
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+// Subnegotiation commands
+const IS: u8 = 0;
+const SEND: u8 = 1;
+const MODE: u8 = 1;
+
+const MAX_SUBNEGOTIATION: usize = 4096;
+
+// -----------------------------------------------------------------------------
+//     - Option state -
+// -----------------------------------------------------------------------------
+// The state of an option on one side, after RFC 1143 (without the queue)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Q {
+    No,
+    Yes,
+    WantNo,
+    WantYes,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Side {
+    state: Q,
+    supported: bool,
+}
+
+// -----------------------------------------------------------------------------
+//     - Telnet codec -
+// -----------------------------------------------------------------------------
+/// Decodes data, commands and negotiation; encodes `TelnetFrame`s.
+///
+/// The peer's requests to enable an option are accepted for supported
+/// options and refused for the others; requests to disable one are always
+/// accepted. By default we support `ECHO` and `SGA` on our side and `SGA`,
+/// `NAWS`, `TTYPE` and `LINEMODE` on the peer's side. When the peer enables
+/// `TTYPE` its terminal type is asked for.
+///
+/// Replies are queued in the codec and written ahead of the next encoded
+/// frame (an empty `TelnetFrame::Data` writes just the replies), so
+/// whoever decodes should check `has_replies` afterwards.
+#[derive(Debug)]
+pub struct TelnetCodec {
+    local: [Side; 256],
+    remote: [Side; 256],
+    replies: Vec<u8>,
+    window_size: Option<(u16, u16)>,
+    terminal_type: Option<String>,
+}
+
+impl TelnetCodec {
+    pub fn new() -> Self {
+        let side = Side { state: Q::No, supported: false };
+        let mut inst = Self {
+            local: [side; 256],
+            remote: [side; 256],
+            replies: Vec::new(),
+            window_size: None,
+            terminal_type: None,
+        };
+
+        for option in &[TelnetOption::ECHO, TelnetOption::SGA] {
+            inst.local[option.0 as usize].supported = true;
+        }
+
+        for option in &[TelnetOption::SGA, TelnetOption::NAWS, TelnetOption::TTYPE, TelnetOption::LINEMODE] {
+            inst.remote[option.0 as usize].supported = true;
+        }
+
+        inst
+    }
+
+    /// Accept (or refuse) requests from the peer for us to perform `option`.
+    pub fn support_local(mut self, option: TelnetOption, supported: bool) -> Self {
+        self.local[option.0 as usize].supported = supported;
+        self
+    }
+
+    /// Accept (or refuse) offers from the peer to perform `option`.
+    pub fn support_remote(mut self, option: TelnetOption, supported: bool) -> Self {
+        self.remote[option.0 as usize].supported = supported;
+        self
+    }
+
+    pub fn local_enabled(&self, option: TelnetOption) -> bool {
+        self.local[option.0 as usize].state == Q::Yes
+    }
+
+    pub fn remote_enabled(&self, option: TelnetOption) -> bool {
+        self.remote[option.0 as usize].state == Q::Yes
+    }
+
+    /// The last window size sent by the peer, in columns and rows.
+    pub fn window_size(&self) -> Option<(u16, u16)> {
+        self.window_size
+    }
+
+    pub fn terminal_type(&self) -> Option<&str> {
+        self.terminal_type.as_deref()
+    }
+
+    /// Offer to perform `option` (`WILL`).
+    pub fn enable_local(&mut self, option: TelnetOption) {
+        self.request(option, true, true);
+    }
+
+    /// Stop performing `option` (`WONT`).
+    pub fn disable_local(&mut self, option: TelnetOption) {
+        self.request(option, true, false);
+    }
+
+    /// Ask the peer to perform `option` (`DO`).
+    pub fn enable_remote(&mut self, option: TelnetOption) {
+        self.request(option, false, true);
+    }
+
+    /// Ask the peer to stop performing `option` (`DONT`).
+    pub fn disable_remote(&mut self, option: TelnetOption) {
+        self.request(option, false, false);
+    }
+
+    /// True if there are negotiation replies to write.
+    pub fn has_replies(&self) -> bool {
+        !self.replies.is_empty()
+    }
+
+    fn reply(&mut self, command: u8, option: TelnetOption) {
+        self.replies.extend_from_slice(&[IAC, command, option.0]);
+    }
+
+    fn request(&mut self, option: TelnetOption, local: bool, enable: bool) {
+        let (side, yes, no) = match local {
+            true => (&mut self.local[option.0 as usize], WILL, WONT),
+            false => (&mut self.remote[option.0 as usize], DO, DONT),
+        };
+
+        let command = match (side.state, enable) {
+            (Q::No, true) => {
+                side.state = Q::WantYes;
+                yes
+            }
+            (Q::Yes, false) => {
+                side.state = Q::WantNo;
+                no
+            }
+            _ => return,
+        };
+
+        self.reply(command, option);
+    }
+
+    // The peer's `WILL`/`WONT` (remote side) or `DO`/`DONT` (local side)
+    fn negotiate(&mut self, command: u8, option: TelnetOption) -> Option<TelnetEvent> {
+        let local = command == DO || command == DONT;
+        let enable = command == DO || command == WILL;
+        let (side, yes, no) = match local {
+            true => (&mut self.local[option.0 as usize], WILL, WONT),
+            false => (&mut self.remote[option.0 as usize], DO, DONT),
+        };
+
+        let (state, reply, changed) = match (side.state, enable) {
+            (Q::No, true) if side.supported => (Q::Yes, Some(yes), true),
+            (Q::No, true) => (Q::No, Some(no), false),
+            (Q::Yes, false) => (Q::No, Some(no), true),
+            (Q::WantYes, true) => (Q::Yes, None, true),
+            (Q::WantNo, false) => (Q::No, None, true),
+            // The peer answered the opposite of what we asked for
+            (Q::WantYes, false) | (Q::WantNo, true) => (Q::No, None, false),
+            (state, _) => (state, None, false),
+        };
+
+        side.state = state;
+        if let Some(reply) = reply {
+            self.reply(reply, option);
+        }
+
+        if !changed {
+            return None;
+        }
+
+        if !local && enable && option == TelnetOption::TTYPE {
+            self.replies.extend_from_slice(&[IAC, SB, option.0, SEND, IAC, SE]);
+        }
+
+        let event = match (local, enable) {
+            (true, true) => TelnetEvent::LocalEnabled(option),
+            (true, false) => TelnetEvent::LocalDisabled(option),
+            (false, true) => TelnetEvent::RemoteEnabled(option),
+            (false, false) => TelnetEvent::RemoteDisabled(option),
+        };
+
+        Some(event)
+    }
+
+    fn subnegotiation(&mut self, option: TelnetOption, data: Vec<u8>) -> TelnetEvent {
+        match (option, data.as_slice()) {
+            (TelnetOption::NAWS, &[w1, w2, h1, h2]) => {
+                let size = (u16::from_be_bytes([w1, w2]), u16::from_be_bytes([h1, h2]));
+                self.window_size = Some(size);
+                TelnetEvent::WindowSize(size.0, size.1)
+            }
+            (TelnetOption::TTYPE, [IS, name @ ..]) => {
+                let name = String::from_utf8_lossy(name).into_owned();
+                self.terminal_type = Some(name.clone());
+                TelnetEvent::TerminalType(name)
+            }
+            (TelnetOption::LINEMODE, &[MODE, mask]) => TelnetEvent::Linemode(mask),
+            _ => TelnetEvent::Subnegotiation(option, data),
+        }
+    }
+}
+
+impl Default for TelnetCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The unescaped option and data of the subnegotiation at the front of
+// `buf` (starting with `IAC SB`) and its length, once complete.
+fn take_subnegotiation(buf: &[u8]) -> Result<Option<(Vec<u8>, usize)>> {
+    let mut data = Vec::new();
+    let mut i = 2;
+    while i < buf.len() {
+        match (buf[i], buf.get(i + 1)) {
+            (IAC, Some(&IAC)) => data.push(IAC),
+            (IAC, Some(&SE)) => return Ok(Some((data, i + 2))),
+            (IAC, Some(_)) => return Err(Error::protocol("invalid command in subnegotiation")),
+            (IAC, None) => break,
+            (b, _) => {
+                data.push(b);
+                i += 1;
+                continue;
+            }
+        }
+        i += 2;
+    }
+
+    match buf.len() > MAX_SUBNEGOTIATION {
+        true => Err(Error::protocol("subnegotiation too long")),
+        false => Ok(None),
+    }
+}
+
+impl Decoder for TelnetCodec {
+    type Item = TelnetEvent;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<TelnetEvent>> {
+        loop {
+            let mut data = Vec::new();
+            let mut i = 0;
+            while i < buf.len() {
+                match (buf[i], buf.get(i + 1)) {
+                    (IAC, Some(&IAC)) => data.push(IAC),
+                    (IAC, _) => break,
+                    (b'\r', Some(&0)) => data.push(b'\r'),
+                    (b'\r', Some(_)) => {
+                        data.push(b'\r');
+                        i += 1;
+                        continue;
+                    }
+                    // Wait for what follows the CR
+                    (b'\r', None) => break,
+                    (b, _) => {
+                        data.push(b);
+                        i += 1;
+                        continue;
+                    }
+                }
+                i += 2;
+            }
+
+            if !data.is_empty() {
+                buf.drain(..i);
+                return Ok(Some(TelnetEvent::Data(data)));
+            }
+
+            // Left: nothing, a lone CR or IAC, or a command
+            if buf.len() < 2 {
+                return Ok(None);
+            }
+
+            let (event, len) = match buf[1] {
+                WILL | WONT | DO | DONT if buf.len() < 3 => return Ok(None),
+                WILL | WONT | DO | DONT => (self.negotiate(buf[1], TelnetOption(buf[2])), 3),
+                SB => match take_subnegotiation(buf)? {
+                    Some((mut data, len)) if !data.is_empty() => {
+                        let option = TelnetOption(data.remove(0));
+                        (Some(self.subnegotiation(option, data)), len)
+                    }
+                    Some(_) => return Err(Error::protocol("empty subnegotiation")),
+                    None => return Ok(None),
+                },
+                command => (Some(TelnetEvent::Command(command)), 2),
+            };
+
+            buf.drain(..len);
+            if event.is_some() {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+fn escape(data: &[u8], buf: &mut Vec<u8>) {
+    for (i, &b) in data.iter().enumerate() {
+        match b {
+            IAC => buf.extend_from_slice(&[IAC, IAC]),
+            b'\r' if data.get(i + 1) != Some(&b'\n') => buf.extend_from_slice(b"\r\0"),
+            b => buf.push(b),
+        }
+    }
+}
+
+impl Encoder<TelnetFrame> for TelnetCodec {
+    fn encode(&mut self, frame: TelnetFrame, buf: &mut Vec<u8>) -> Result<()> {
+        buf.append(&mut self.replies);
+
+        match frame {
+            TelnetFrame::Data(data) => escape(&data, buf),
+            TelnetFrame::Command(command) => buf.extend_from_slice(&[IAC, command]),
+            TelnetFrame::Subnegotiation(option, data) => {
+                buf.extend_from_slice(&[IAC, SB]);
+                // Only IAC is escaped within a subnegotiation
+                for &b in [option.0].iter().chain(&data) {
+                    match b {
+                        IAC => buf.extend_from_slice(&[IAC, IAC]),
+                        b => buf.push(b),
+                    }
+                }
+                buf.extend_from_slice(&[IAC, SE]);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::telnet::IP;
+
+    fn replies(codec: &mut TelnetCodec) -> Vec<u8> {
+        let mut buf = Vec::new();
+        codec.encode(TelnetFrame::Data(Vec::new()), &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn data_and_commands() {
+        let mut codec = TelnetCodec::new();
+        let mut buf = b"a\xff\xffb\r\0c\r\nd\xff\xf4e\r".to_vec();
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::Data(b"a\xffb\rc\r\nd".to_vec())));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::Command(IP)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::Data(b"e".to_vec())));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert_eq!(buf, b"\r");
+
+        let mut out = Vec::new();
+        codec.encode(TelnetFrame::Data(b"\xff\r\n\r".to_vec()), &mut out).unwrap();
+        assert_eq!(out, b"\xff\xff\r\n\r\0");
+    }
+
+    #[test]
+    fn negotiation() {
+        let mut codec = TelnetCodec::new();
+        codec.enable_local(TelnetOption::ECHO);
+        assert_eq!(replies(&mut codec), [IAC, WILL, 1]);
+
+        // Agreed to, offered, refused and asked for a supported option
+        let mut buf = vec![IAC, DO, 1, IAC, WILL, 31, IAC, WILL, 5, IAC, DO, 3];
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::LocalEnabled(TelnetOption::ECHO)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::RemoteEnabled(TelnetOption::NAWS)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::LocalEnabled(TelnetOption::SGA)));
+        assert!(buf.is_empty());
+        assert_eq!(replies(&mut codec), [IAC, DO, 31, IAC, DONT, 5, IAC, WILL, 3]);
+        assert!(codec.local_enabled(TelnetOption::ECHO) && codec.remote_enabled(TelnetOption::NAWS));
+
+        // Repeated requests are not answered
+        let mut buf = vec![IAC, DO, 1];
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert!(!codec.has_replies());
+
+        // Terminal type is asked for once enabled
+        let mut buf = vec![IAC, WILL, 24];
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::RemoteEnabled(TelnetOption::TTYPE)));
+        assert_eq!(replies(&mut codec), [IAC, DO, 24, IAC, SB, 24, SEND, IAC, SE]);
+    }
+
+    #[test]
+    fn subnegotiation() {
+        let mut codec = TelnetCodec::new();
+        let mut buf = vec![IAC, SB, 31, 0, 80, 0, IAC, IAC];
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+        buf.extend_from_slice(&[IAC, SE, IAC, SB, 24, IS]);
+        buf.extend_from_slice(b"XTERM");
+        buf.extend_from_slice(&[IAC, SE, IAC, SB, 34, MODE, 3, IAC, SE, IAC, SB, 42, 1, IAC, SE]);
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::WindowSize(80, 255)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::TerminalType("XTERM".into())));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::Linemode(3)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(TelnetEvent::Subnegotiation(TelnetOption(42), vec![1])));
+        assert_eq!(codec.window_size(), Some((80, 255)));
+        assert_eq!(codec.terminal_type(), Some("XTERM"));
+
+        let mut out = Vec::new();
+        codec.encode(TelnetFrame::Subnegotiation(TelnetOption::LINEMODE, vec![MODE, IAC]), &mut out).unwrap();
+        assert_eq!(out, [IAC, SB, 34, MODE, IAC, IAC, IAC, SE]);
+
+        assert!(codec.decode(&mut vec![IAC, SB, 31, IAC, 241]).is_err());
+    }
+}
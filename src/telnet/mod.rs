@@ -0,0 +1,108 @@
+//! Telnet (RFC 854), for terminal clients.
+//!
+//! `TelnetCodec` separates data from commands, answers option negotiation
+//! and parses subnegotiations: the window size (NAWS), the terminal type
+//! (TTYPE) and the line mode (LINEMODE).
+//!
+//! `Telnet` is a reactor over a single connection yielding `TelnetEvent`s.
+//! With character mode the server echoes and every key press is sent as
+//! it is typed, instead of line by line:
+//!
+//! ```no_run
+//! # use netlib::{Interest, Reactor, System};
+//! # use netlib::net::tcp::TcpStream;
+//! # use netlib::telnet::{Telnet, TelnetEvent};
+//! System::builder().finish();
+//! let (stream, _) = std::net::TcpListener::bind("127.0.0.1:2323").unwrap().accept().unwrap();
+//! stream.set_nonblocking(true).unwrap();
+//! let mut telnet = Telnet::new(TcpStream::new(stream, Interest::Read).unwrap()).unwrap();
+//! telnet.set_character_mode(true).unwrap();
+//!
+//! let game = telnet.map(|event| match event {
+//!     Ok(TelnetEvent::WindowSize(width, height)) => println!("{}x{}", width, height),
+//!     Ok(TelnetEvent::Data(keys)) => println!("{:?}", keys),
+//!     _ => {}
+//! });
+//! System::start(game);
+//! ```
+mod codec;
+mod stream;
+
+pub use codec::TelnetCodec;
+pub use stream::Telnet;
+
+/// No operation.
+pub const NOP: u8 = 241;
+/// Data mark, the end of urgent data.
+pub const DM: u8 = 242;
+/// Break.
+pub const BRK: u8 = 243;
+/// Interrupt process (e.g. ctrl-c).
+pub const IP: u8 = 244;
+/// Abort output.
+pub const AO: u8 = 245;
+/// Are you there.
+pub const AYT: u8 = 246;
+/// Erase character.
+pub const EC: u8 = 247;
+/// Erase line.
+pub const EL: u8 = 248;
+/// Go ahead.
+pub const GA: u8 = 249;
+
+// -----------------------------------------------------------------------------
+//     - Option -
+// -----------------------------------------------------------------------------
+/// A negotiable option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TelnetOption(pub u8);
+
+impl TelnetOption {
+    pub const ECHO: TelnetOption = TelnetOption(1);
+    /// Suppress go ahead.
+    pub const SGA: TelnetOption = TelnetOption(3);
+    /// Terminal type.
+    pub const TTYPE: TelnetOption = TelnetOption(24);
+    /// Negotiate about window size.
+    pub const NAWS: TelnetOption = TelnetOption(31);
+    pub const LINEMODE: TelnetOption = TelnetOption(34);
+}
+
+// -----------------------------------------------------------------------------
+//     - Event -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelnetEvent {
+    /// Data, with escaped bytes restored and `CR NUL` turned into `CR`.
+    Data(Vec<u8>),
+    /// A command, e.g. `IP` or `AYT`.
+    Command(u8),
+    /// The peer now performs the option.
+    RemoteEnabled(TelnetOption),
+    /// The peer no longer performs the option.
+    RemoteDisabled(TelnetOption),
+    /// We now perform the option.
+    LocalEnabled(TelnetOption),
+    /// We no longer perform the option.
+    LocalDisabled(TelnetOption),
+    /// The window size of the client, in columns and rows.
+    WindowSize(u16, u16),
+    TerminalType(String),
+    /// The line mode the client acknowledged (`MODE`, RFC 1184).
+    Linemode(u8),
+    /// Any other subnegotiation, with its data unescaped.
+    Subnegotiation(TelnetOption, Vec<u8>),
+}
+
+// -----------------------------------------------------------------------------
+//     - Frame -
+// -----------------------------------------------------------------------------
+/// What can be encoded. Negotiation is done through the codec instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelnetFrame {
+    /// Data, escaped on the way out. A `CR` not followed by `LF` is sent
+    /// as `CR NUL`.
+    Data(Vec<u8>),
+    Command(u8),
+    Subnegotiation(TelnetOption, Vec<u8>),
+}
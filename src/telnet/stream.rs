@@ -0,0 +1,256 @@
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+
+use super::{TelnetCodec, TelnetEvent, TelnetFrame, TelnetOption};
+use crate::codecs::Framed;
+use crate::{PollReactor, Reaction, Reactor, Result};
+
+// LINEMODE MODE and its EDIT bit (RFC 1184)
+const MODE: u8 = 1;
+const EDIT: u8 = 1;
+
+// -----------------------------------------------------------------------------
+//     - Telnet -
+// -----------------------------------------------------------------------------
+/// The server side of a telnet connection.
+///
+/// The client is asked for its window size and terminal type when the
+/// connection is created, and negotiation is answered as it arrives.
+/// Every event is yielded, negotiation included.
+pub struct Telnet<S: AsRawFd> {
+    framed: Framed<S, TelnetCodec>,
+    character_mode: bool,
+}
+
+impl<S> Telnet<S>
+where
+    S: AsRawFd + Read + Write,
+{
+    pub fn new(stream: PollReactor<S>) -> Result<Self> {
+        Self::with_codec(stream, TelnetCodec::new())
+    }
+
+    /// Use a codec with other supported options.
+    pub fn with_codec(stream: PollReactor<S>, mut codec: TelnetCodec) -> Result<Self> {
+        codec.enable_remote(TelnetOption::NAWS);
+        codec.enable_remote(TelnetOption::TTYPE);
+
+        let mut inst = Self { framed: Framed::new(stream, codec), character_mode: false };
+        inst.flush_replies()?;
+        Ok(inst)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.framed.id()
+    }
+
+    pub fn codec(&self) -> &TelnetCodec {
+        self.framed.codec()
+    }
+
+    /// The client's window size, in columns and rows, once it has sent it.
+    pub fn window_size(&self) -> Option<(u16, u16)> {
+        self.codec().window_size()
+    }
+
+    pub fn terminal_type(&self) -> Option<&str> {
+        self.codec().terminal_type()
+    }
+
+    /// True once the client has closed the connection.
+    pub fn is_closed(&self) -> bool {
+        self.framed.is_closed()
+    }
+
+    pub fn send(&mut self, data: impl Into<Vec<u8>>) -> Result<()> {
+        self.framed.send(TelnetFrame::Data(data.into()))
+    }
+
+    pub fn send_frame(&mut self, frame: TelnetFrame) -> Result<()> {
+        self.framed.send(frame)
+    }
+
+    /// In character mode the server echoes (`ECHO`), go aheads are
+    /// suppressed (`SGA`) and a client with `LINEMODE` stops editing
+    /// locally, so every key press is sent as it is typed.
+    /// Otherwise the client edits and sends a line at a time.
+    pub fn set_character_mode(&mut self, on: bool) -> Result<()> {
+        self.character_mode = on;
+        let codec = self.framed.codec_mut();
+        match on {
+            true => {
+                codec.enable_local(TelnetOption::ECHO);
+                codec.enable_local(TelnetOption::SGA);
+                codec.enable_remote(TelnetOption::SGA);
+            }
+            false => {
+                codec.disable_local(TelnetOption::ECHO);
+                codec.disable_local(TelnetOption::SGA);
+                codec.disable_remote(TelnetOption::SGA);
+            }
+        }
+
+        self.send_linemode()?;
+        self.flush_replies()
+    }
+
+    pub fn enable_local(&mut self, option: TelnetOption) -> Result<()> {
+        self.framed.codec_mut().enable_local(option);
+        self.flush_replies()
+    }
+
+    pub fn disable_local(&mut self, option: TelnetOption) -> Result<()> {
+        self.framed.codec_mut().disable_local(option);
+        self.flush_replies()
+    }
+
+    pub fn enable_remote(&mut self, option: TelnetOption) -> Result<()> {
+        self.framed.codec_mut().enable_remote(option);
+        self.flush_replies()
+    }
+
+    pub fn disable_remote(&mut self, option: TelnetOption) -> Result<()> {
+        self.framed.codec_mut().disable_remote(option);
+        self.flush_replies()
+    }
+
+    fn flush_replies(&mut self) -> Result<()> {
+        match self.codec().has_replies() {
+            true => self.framed.send(TelnetFrame::Data(Vec::new())),
+            false => Ok(()),
+        }
+    }
+
+    fn send_linemode(&mut self) -> Result<()> {
+        if !self.codec().remote_enabled(TelnetOption::LINEMODE) {
+            return Ok(());
+        }
+
+        let mask = match self.character_mode {
+            true => 0,
+            false => EDIT,
+        };
+        self.framed.send(TelnetFrame::Subnegotiation(TelnetOption::LINEMODE, vec![MODE, mask]))
+    }
+}
+
+impl<S> Reactor for Telnet<S>
+where
+    S: AsRawFd + Read + Write,
+{
+    type Input = ();
+    type Output = Result<TelnetEvent>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        let ours = matches!(reaction, Reaction::Event(ev) if ev.owner == self.id());
+
+        let event = match self.framed.react(reaction) {
+            Reaction::Value(Ok(event)) => event,
+            reaction if !ours => return reaction,
+            // Negotiation the codec answered without yielding an event,
+            // e.g. an option that isn't supported
+            reaction => {
+                return match self.flush_replies() {
+                    Ok(()) => reaction,
+                    Err(e) => Reaction::Value(Err(e)),
+                }
+            }
+        };
+
+        let res = match event {
+            TelnetEvent::RemoteEnabled(TelnetOption::LINEMODE) => self.send_linemode(),
+            _ => Ok(()),
+        };
+
+        match res.and_then(|_| self.flush_replies()) {
+            Ok(()) => Reaction::Value(Ok(event)),
+            Err(e) => Reaction::Value(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream as StdTcpStream};
+    use std::os::unix::net::UnixStream as StdUnixStream;
+    use std::thread;
+    use crate::net::tcp::TcpStream;
+    use crate::net::uds::UnixStream;
+    use crate::{Event, Interest, System};
+
+    #[test]
+    fn character_mode_and_window_size() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = StdTcpStream::connect(addr).unwrap();
+            let mut buf = [0u8; 6];
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [255, 253, 31, 255, 253, 24]);
+
+            // Window size, and agree to echo
+            stream.write_all(&[255, 251, 31, 255, 250, 31, 0, 120, 0, 40, 255, 240]).unwrap();
+            stream.write_all(&[255, 253, 1]).unwrap();
+
+            let mut buf = [0u8; 9];
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, [255, 251, 1, 255, 251, 3, 255, 253, 3]);
+            stream.write_all(b"x").unwrap();
+            let _ = stream.read(&mut [0u8; 1]);
+        });
+
+        System::builder().finish();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut telnet = Telnet::new(TcpStream::new(stream, Interest::Read).unwrap()).unwrap();
+        telnet.set_character_mode(true).unwrap();
+
+        let mut events = Vec::new();
+        let telnet = telnet.map(move |event| {
+            match event.unwrap() {
+                TelnetEvent::Data(data) => {
+                    assert_eq!(data, b"x");
+                    assert_eq!(
+                        events,
+                        vec![
+                            TelnetEvent::RemoteEnabled(TelnetOption::NAWS),
+                            TelnetEvent::WindowSize(120, 40),
+                            TelnetEvent::LocalEnabled(TelnetOption::ECHO),
+                        ]
+                    );
+                    System::stop();
+                }
+                event => events.push(event),
+            }
+        });
+
+        System::start(telnet).unwrap();
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn refuse_unsupported_option() {
+        System::builder().finish();
+        let (a, mut b) = StdUnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let a = UnixStream::try_from(a).unwrap();
+        let mut telnet = Telnet::new(a).unwrap();
+
+        let mut buf = [0u8; 6];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [255, 253, 31, 255, 253, 24]);
+
+        // NEW-ENVIRON, on its own
+        b.write_all(&[255, 251, 39]).unwrap();
+        let ev = Event { read: true, write: false, owner: telnet.id() };
+        assert!(matches!(telnet.react(Reaction::Event(ev)), Reaction::Continue));
+
+        let mut buf = [0u8; 3];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [255, 254, 39]);
+    }
+}
@@ -0,0 +1,313 @@
+use std::convert::TryInto;
+
+use super::{CloseCode, Message};
+use crate::codecs::{Decoder, Encoder};
+use crate::{Error, Result};
+
+pub(crate) const DEFAULT_MAX_MESSAGE: usize = 16 * 1024 * 1024;
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+// -----------------------------------------------------------------------------
+//     - Role -
+// -----------------------------------------------------------------------------
+/// Which end of the connection the codec is on.
+/// Clients mask the frames they send, servers must not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+// -----------------------------------------------------------------------------
+//     - WebSocket codec -
+// -----------------------------------------------------------------------------
+/// Turns frames into messages and back.
+///
+/// Fragmented messages are reassembled; control frames may arrive between
+/// the fragments and are yielded as they come. A message larger than the
+/// maximum message size is an error.
+///
+/// After a decoding error `close_code` is the code to close the
+/// connection with.
+#[derive(Debug)]
+pub struct WebSocketCodec {
+    role: Role,
+    max_message: usize,
+    // Opcode and payload of a fragmented message
+    fragments: Option<(u8, Vec<u8>)>,
+    close_code: Option<CloseCode>,
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+impl WebSocketCodec {
+    pub fn new(role: Role) -> Self {
+        Self {
+            role,
+            max_message: DEFAULT_MAX_MESSAGE,
+            fragments: None,
+            close_code: None,
+        }
+    }
+
+    /// The largest message accepted, in bytes, after reassembly.
+    pub fn max_message_size(mut self, max: usize) -> Self {
+        self.max_message = max;
+        self
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// The close code for the last decoding error.
+    pub fn close_code(&self) -> Option<CloseCode> {
+        self.close_code
+    }
+
+    fn fail(&mut self, code: CloseCode, msg: &str) -> Error {
+        self.close_code = Some(code);
+        Error::protocol(msg)
+    }
+
+    fn next_frame(&mut self, buf: &mut Vec<u8>) -> Result<Option<Frame>> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+
+        let fin = buf[0] & 0x80 != 0;
+        let opcode = buf[0] & 0x0f;
+        let masked = buf[1] & 0x80 != 0;
+
+        if buf[0] & 0x70 != 0 {
+            return Err(self.fail(CloseCode::PROTOCOL_ERROR, "reserved bits set"));
+        }
+
+        if masked != (self.role == Role::Server) {
+            return Err(self.fail(CloseCode::PROTOCOL_ERROR, "wrong masking"));
+        }
+
+        let (len, mut offset) = match buf[1] & 0x7f {
+            126 if buf.len() < 4 => return Ok(None),
+            126 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+            127 if buf.len() < 10 => return Ok(None),
+            127 => (u64::from_be_bytes(buf[2..10].try_into().expect("8 bytes")), 10),
+            len => (len as u64, 2),
+        };
+
+        if opcode & 0x8 != 0 && (!fin || len as usize > MAX_CONTROL_PAYLOAD) {
+            return Err(self.fail(CloseCode::PROTOCOL_ERROR, "invalid control frame"));
+        }
+
+        let buffered = self.fragments.as_ref().map(|(_, p)| p.len()).unwrap_or(0) as u64;
+        if len.saturating_add(buffered) > self.max_message as u64 {
+            return Err(self.fail(CloseCode::TOO_BIG, "message too large"));
+        }
+
+        let mask = match masked {
+            true if buf.len() < offset + 4 => return Ok(None),
+            true => {
+                offset += 4;
+                Some([buf[offset - 4], buf[offset - 3], buf[offset - 2], buf[offset - 1]])
+            }
+            false => None,
+        };
+
+        let len = len as usize;
+        if buf.len() < offset + len {
+            return Ok(None);
+        }
+
+        let mut payload = buf[offset..offset + len].to_vec();
+        buf.drain(..offset + len);
+        if let Some(mask) = mask {
+            apply_mask(&mut payload, mask);
+        }
+
+        Ok(Some(Frame { fin, opcode, payload }))
+    }
+
+    fn message(&mut self, opcode: u8, payload: Vec<u8>) -> Result<Message> {
+        match opcode {
+            TEXT => match String::from_utf8(payload) {
+                Ok(text) => Ok(Message::Text(text)),
+                Err(_) => Err(self.fail(CloseCode::INVALID_DATA, "text is not valid utf-8")),
+            },
+            BINARY => Ok(Message::Binary(payload)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn close(&mut self, payload: Vec<u8>) -> Result<Message> {
+        if payload.is_empty() {
+            return Ok(Message::Close(None));
+        }
+
+        if payload.len() < 2 {
+            return Err(self.fail(CloseCode::PROTOCOL_ERROR, "invalid close frame"));
+        }
+
+        let code = CloseCode(u16::from_be_bytes([payload[0], payload[1]]));
+        if !code.is_valid() {
+            return Err(self.fail(CloseCode::PROTOCOL_ERROR, "invalid close code"));
+        }
+
+        match String::from_utf8(payload[2..].to_vec()) {
+            Ok(reason) => Ok(Message::Close(Some((code, reason)))),
+            Err(_) => Err(self.fail(CloseCode::INVALID_DATA, "close reason is not valid utf-8")),
+        }
+    }
+}
+
+pub(crate) fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+impl Decoder for WebSocketCodec {
+    type Item = Message;
+
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Message>> {
+        loop {
+            let Frame { fin, opcode, payload } = match self.next_frame(buf)? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+
+            match (opcode, self.fragments.take()) {
+                (CLOSE, fragments) => {
+                    self.fragments = fragments;
+                    return self.close(payload).map(Some);
+                }
+                (PING, fragments) => {
+                    self.fragments = fragments;
+                    return Ok(Some(Message::Ping(payload)));
+                }
+                (PONG, fragments) => {
+                    self.fragments = fragments;
+                    return Ok(Some(Message::Pong(payload)));
+                }
+                (TEXT, None) | (BINARY, None) if fin => return self.message(opcode, payload).map(Some),
+                (TEXT, None) | (BINARY, None) => self.fragments = Some((opcode, payload)),
+                (CONTINUATION, Some((first, mut fragments))) => {
+                    fragments.extend(payload);
+                    match fin {
+                        true => return self.message(first, fragments).map(Some),
+                        false => self.fragments = Some((first, fragments)),
+                    }
+                }
+                (TEXT, Some(_)) | (BINARY, Some(_)) | (CONTINUATION, None) => {
+                    return Err(self.fail(CloseCode::PROTOCOL_ERROR, "unexpected fragment"))
+                }
+                _ => return Err(self.fail(CloseCode::PROTOCOL_ERROR, "unknown opcode")),
+            }
+        }
+    }
+}
+
+impl Encoder<Message> for WebSocketCodec {
+    fn encode(&mut self, message: Message, buf: &mut Vec<u8>) -> Result<()> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (TEXT, text.into_bytes()),
+            Message::Binary(data) => (BINARY, data),
+            Message::Ping(data) => (PING, data),
+            Message::Pong(data) => (PONG, data),
+            Message::Close(None) => (CLOSE, Vec::new()),
+            Message::Close(Some((code, reason))) => {
+                let mut payload = code.0.to_be_bytes().to_vec();
+                payload.extend_from_slice(reason.as_bytes());
+                (CLOSE, payload)
+            }
+        };
+
+        if opcode & 0x8 != 0 && payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(Error::protocol("control frame payload too large"));
+        }
+
+        let mask_bit = match self.role {
+            Role::Client => 0x80,
+            Role::Server => 0,
+        };
+
+        buf.push(0x80 | opcode);
+        match payload.len() {
+            len @ 0..=125 => buf.push(mask_bit | len as u8),
+            len @ 126..=0xffff => {
+                buf.push(mask_bit | 126);
+                buf.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                buf.push(mask_bit | 127);
+                buf.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+
+        let start = buf.len();
+        buf.extend_from_slice(&payload);
+        if self.role == Role::Client {
+            let mask = rand::random::<[u8; 4]>();
+            apply_mask(&mut buf[start..], mask);
+            buf.splice(start..start, mask.iter().copied());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn masked_round_trip() {
+        let mut client = WebSocketCodec::new(Role::Client);
+        let mut server = WebSocketCodec::new(Role::Server);
+        let mut buf = Vec::new();
+
+        client.encode(Message::Text("hello".into()), &mut buf).unwrap();
+        client.encode(Message::Binary(vec![1; 70_000]), &mut buf).unwrap();
+        assert_ne!(&buf[6..11], b"hello");
+
+        assert_eq!(server.decode(&mut buf).unwrap(), Some(Message::Text("hello".into())));
+        assert_eq!(server.decode(&mut buf).unwrap(), Some(Message::Binary(vec![1; 70_000])));
+        assert!(buf.is_empty());
+
+        // Servers don't mask, and clients reject masked frames
+        server.encode(Message::Close(Some((CloseCode::NORMAL, "bye".into()))), &mut buf).unwrap();
+        assert_eq!(buf, b"\x88\x05\x03\xe8bye");
+        assert!(server.decode(&mut buf.clone()).is_err());
+        assert_eq!(client.decode(&mut buf).unwrap(), Some(Message::Close(Some((CloseCode::NORMAL, "bye".into())))));
+    }
+
+    #[test]
+    fn fragments_with_interleaved_ping() {
+        let mut client = WebSocketCodec::new(Role::Client);
+        let mut buf = b"\x01\x03hel\x89\x00\x80\x02lo".to_vec();
+        assert_eq!(client.decode(&mut buf).unwrap(), Some(Message::Ping(vec![])));
+        assert_eq!(client.decode(&mut buf).unwrap(), Some(Message::Text("hello".into())));
+
+        let mut buf = b"\x80\x01x".to_vec();
+        assert!(client.decode(&mut buf).is_err());
+        assert_eq!(client.close_code(), Some(CloseCode::PROTOCOL_ERROR));
+    }
+
+    #[test]
+    fn message_too_large() {
+        let mut client = WebSocketCodec::new(Role::Client).max_message_size(4);
+        let mut buf = b"\x01\x03abc\x80\x02de".to_vec();
+        assert!(client.decode(&mut buf).is_err());
+        assert_eq!(client.close_code(), Some(CloseCode::TOO_BIG));
+    }
+}
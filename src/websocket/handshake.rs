@@ -0,0 +1,130 @@
+use super::{base64, sha1::sha1};
+use crate::http::{Request, Response, Version};
+use crate::{Error, Result};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The `Sec-WebSocket-Accept` value for a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    base64::encode(&sha1(format!("{}{}", key.trim(), GUID).as_bytes()))
+}
+
+/// Validate an upgrade request.
+///
+/// Returns the `101` response and the chosen subprotocol (the first one
+/// in `protocols` the client offered), or the response rejecting it.
+pub(crate) fn accept(request: &Request, protocols: &[String]) -> std::result::Result<(Response, Option<String>), Response> {
+    let headers = &request.headers;
+
+    if request.method != "GET" || request.version != Version::Http11 {
+        return Err(Response::new(400));
+    }
+
+    if !headers.has_token("upgrade", "websocket") || !headers.has_token("connection", "upgrade") {
+        return Err(Response::new(426).header("Upgrade", "websocket"));
+    }
+
+    if headers.get("sec-websocket-version") != Some("13") {
+        return Err(Response::new(426).header("Sec-WebSocket-Version", "13"));
+    }
+
+    let key = match headers.get("sec-websocket-key") {
+        Some(key) if base64::decode(key).map(|k| k.len()) == Some(16) => key,
+        _ => return Err(Response::new(400)),
+    };
+
+    let protocol = protocols
+        .iter()
+        .find(|p| headers.has_token("sec-websocket-protocol", p))
+        .cloned();
+
+    let mut response = Response::new(101)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", accept_key(key));
+
+    if let Some(protocol) = &protocol {
+        response.headers.append("Sec-WebSocket-Protocol", protocol.as_str());
+    }
+
+    Ok((response, protocol))
+}
+
+/// A random `Sec-WebSocket-Key`.
+pub(crate) fn generate_key() -> String {
+    base64::encode(&rand::random::<[u8; 16]>())
+}
+
+/// The upgrade request of a client.
+pub(crate) fn client_request(host: &str, path: &str, key: &str) -> Request {
+    Request::new("GET", path)
+        .header("Host", host)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Key", key)
+        .header("Sec-WebSocket-Version", "13")
+}
+
+/// Validate the server's answer to an upgrade request, returning
+/// the subprotocol it chose.
+pub(crate) fn validate(response: &Response, key: &str, protocols: &[String]) -> Result<Option<String>> {
+    let headers = &response.headers;
+
+    if response.status != 101 {
+        return Err(Error::protocol(format!("websocket upgrade refused: {}", response.status)));
+    }
+
+    if !headers.has_token("upgrade", "websocket") || !headers.has_token("connection", "upgrade") {
+        return Err(Error::protocol("response is not a websocket upgrade"));
+    }
+
+    if headers.get("sec-websocket-accept") != Some(accept_key(key).as_str()) {
+        return Err(Error::protocol("invalid Sec-WebSocket-Accept"));
+    }
+
+    match headers.get("sec-websocket-protocol") {
+        None => Ok(None),
+        Some(protocol) if protocols.iter().any(|p| p == protocol) => Ok(Some(protocol.to_string())),
+        Some(_) => Err(Error::protocol("server chose a subprotocol that wasn't offered")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rfc6455_example() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+
+        let request = Request::new("GET", "/chat")
+            .header("Upgrade", "websocket")
+            .header("Connection", "keep-alive, Upgrade")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .header("Sec-WebSocket-Protocol", "chat, superchat")
+            .header("Sec-WebSocket-Version", "13");
+
+        let protocols = vec!["superchat".to_string()];
+        let (response, protocol) = accept(&request, &protocols).unwrap();
+        assert_eq!(response.status, 101);
+        assert_eq!(protocol.as_deref(), Some("superchat"));
+
+        let mut request = request;
+        request.headers.insert("Sec-WebSocket-Version", "8");
+        assert_eq!(accept(&request, &protocols).unwrap_err().status, 426);
+    }
+
+    #[test]
+    fn validate_response() {
+        let key = generate_key();
+        assert_eq!(base64::decode(&key).unwrap().len(), 16);
+
+        let request = client_request("localhost", "/", &key).header("Sec-WebSocket-Protocol", "chat");
+        let (response, _) = accept(&request, &["chat".to_string()]).unwrap();
+
+        let offered = vec!["chat".to_string()];
+        assert_eq!(validate(&response, &key, &offered).unwrap().as_deref(), Some("chat"));
+        assert!(validate(&response, &key, &[]).is_err());
+        assert!(validate(&response, &generate_key(), &offered).is_err());
+    }
+}
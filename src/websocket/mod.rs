@@ -0,0 +1,77 @@
+//! WebSocket (RFC 6455).
+//!
+//! `WebSocket` is a reactor over a single connection, accepted or opened
+//! with `WebSocket::connect`: it performs the HTTP upgrade handshake and
+//! then yields messages. Pings are answered and the close handshake is
+//! completed automatically.
+//!
+//! `WebSocketServer` accepts the connections of a `TcpListener` and hands
+//! every message to a handler:
+//!
+//! ```no_run
+//! # use netlib::System;
+//! # use netlib::net::tcp::TcpListener;
+//! # use netlib::websocket::{Message, WebSocketServer};
+//! # use netlib::Reactor;
+//! System::builder().finish();
+//! let server = TcpListener::bind("127.0.0.1:9000").unwrap()
+//!     .chain(WebSocketServer::new(|ws, msg| {
+//!         if let Message::Text(text) = msg {
+//!             let _ = ws.send(Message::Text(text));
+//!         }
+//!     }));
+//! System::start(server);
+//! ```
+mod base64;
+mod codec;
+mod handshake;
+mod server;
+mod sha1;
+
+pub use codec::{Role, WebSocketCodec};
+pub use handshake::accept_key;
+pub use server::{WebSocket, WebSocketServer};
+
+// -----------------------------------------------------------------------------
+//     - Close code -
+// -----------------------------------------------------------------------------
+/// The status code of a close frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloseCode(pub u16);
+
+impl CloseCode {
+    pub const NORMAL: CloseCode = CloseCode(1000);
+    pub const GOING_AWAY: CloseCode = CloseCode(1001);
+    pub const PROTOCOL_ERROR: CloseCode = CloseCode(1002);
+    pub const UNSUPPORTED_DATA: CloseCode = CloseCode(1003);
+    pub const INVALID_DATA: CloseCode = CloseCode(1007);
+    pub const POLICY_VIOLATION: CloseCode = CloseCode(1008);
+    pub const TOO_BIG: CloseCode = CloseCode(1009);
+    pub const MISSING_EXTENSION: CloseCode = CloseCode(1010);
+    pub const INTERNAL_ERROR: CloseCode = CloseCode(1011);
+
+    /// Codes that may be sent in a close frame.
+    /// 1005, 1006 and 1015 are reserved for reporting, never for sending.
+    pub fn is_valid(self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1011 | 3000..=4999)
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - Message -
+// -----------------------------------------------------------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// A close frame, with its code and reason if it had one.
+    Close(Option<(CloseCode, String)>),
+}
+
+impl Message {
+    pub fn is_control(&self) -> bool {
+        matches!(self, Message::Ping(_) | Message::Pong(_) | Message::Close(_))
+    }
+}
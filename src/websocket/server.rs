@@ -0,0 +1,571 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream as StdTcpStream;
+use std::os::unix::io::AsRawFd;
+
+use super::codec::{Role, WebSocketCodec, DEFAULT_MAX_MESSAGE};
+use super::{handshake, CloseCode, Message};
+use crate::codecs::Framed;
+use crate::http::{ClientCodec, Request, Response, ServerCodec};
+use crate::net::tcp::{Accepted, ConnectionSlot, TcpStream};
+use crate::{Error, Interest, PollReactor, Reaction, Reactor, Result};
+
+// -----------------------------------------------------------------------------
+//     - WebSocket -
+// -----------------------------------------------------------------------------
+enum Inner<S: AsRawFd> {
+    Handshake(Framed<S, ServerCodec>),
+    Connecting(Framed<S, ClientCodec>),
+    Open(Framed<S, WebSocketCodec>),
+    Upgrading,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Handshake,
+    Open,
+    // We sent a close frame and wait for the peer's
+    Closing,
+    // The stream is shut down once everything is written
+    Closed,
+}
+
+/// A WebSocket connection, either accepted (`accept`) or
+/// opened (`connect`, `client`).
+///
+/// The reactor first completes the handshake, then yields every message
+/// received. Messages sent before the handshake is done are queued.
+/// A client masks what it sends and validates the server's accept key
+/// and chosen subprotocol. Pings are answered with a pong and a close frame from the
+/// peer is answered with a close frame, after which the stream is shut
+/// down. A protocol violation closes the connection with the matching
+/// close code and yields the error.
+pub struct WebSocket<S: AsRawFd> {
+    id: u64,
+    inner: Inner<S>,
+    state: State,
+    protocols: Vec<String>,
+    protocol: Option<String>,
+    max_message: usize,
+    shut_down: bool,
+    // Client side: the upgrade request, until sent, and its key
+    request: Option<Request>,
+    key: String,
+    queued: Vec<Message>,
+}
+
+impl<S> WebSocket<S>
+where
+    S: AsRawFd + Read + Write,
+{
+    /// Wait for the upgrade request of a client on `stream`.
+    pub fn accept(stream: PollReactor<S>) -> Self {
+        Self {
+            id: stream.id,
+            inner: Inner::Handshake(Framed::new(stream, ServerCodec::new())),
+            state: State::Handshake,
+            protocols: Vec::new(),
+            protocol: None,
+            max_message: DEFAULT_MAX_MESSAGE,
+            shut_down: false,
+            request: None,
+            key: String::new(),
+            queued: Vec::new(),
+        }
+    }
+
+    /// Open a connection on `stream`, which may still be connecting.
+    /// `host` is used for the `Host` header.
+    pub fn client(stream: PollReactor<S>, host: &str, path: &str) -> Self {
+        let key = handshake::generate_key();
+        Self {
+            id: stream.id,
+            inner: Inner::Connecting(Framed::new(stream, ClientCodec::new())),
+            state: State::Handshake,
+            protocols: Vec::new(),
+            protocol: None,
+            max_message: DEFAULT_MAX_MESSAGE,
+            shut_down: false,
+            request: Some(handshake::client_request(host, path, &key)),
+            key,
+            queued: Vec::new(),
+        }
+    }
+
+    /// Add a header to the upgrade request of a client, e.g. `Origin`.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        if let Some(request) = self.request.take() {
+            self.request = Some(request.header(name, value));
+        }
+        self
+    }
+
+    /// The subprotocols supported (or offered, by a client),
+    /// most preferred first.
+    pub fn protocols(mut self, protocols: &[&str]) -> Self {
+        self.protocols = protocols.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// The largest message accepted, in bytes.
+    /// A larger message closes the connection with `CloseCode::TOO_BIG`.
+    pub fn max_message_size(mut self, max: usize) -> Self {
+        self.max_message = max;
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The negotiated subprotocol.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// True once the handshake is done and until the connection is closing.
+    pub fn is_open(&self) -> bool {
+        self.state == State::Open
+    }
+
+    /// True once the connection is closed and the stream shut down.
+    pub fn is_closed(&self) -> bool {
+        self.shut_down
+    }
+
+    pub fn send(&mut self, message: Message) -> Result<()> {
+        match (&mut self.inner, self.state) {
+            (_, State::Handshake) => {
+                self.queued.push(message);
+                Ok(())
+            }
+            (Inner::Open(framed), State::Open) => framed.send(message),
+            _ => Err(io::Error::from(io::ErrorKind::NotConnected).into()),
+        }
+    }
+
+    /// Start the close handshake.
+    pub fn close(&mut self, code: CloseCode, reason: &str) -> Result<()> {
+        if self.state != State::Open {
+            return Err(io::Error::from(io::ErrorKind::NotConnected).into());
+        }
+
+        self.send(Message::Close(Some((code, reason.to_string()))))?;
+        self.state = State::Closing;
+        Ok(())
+    }
+
+    fn upgrade(&mut self, request: Request) -> Reaction<Result<Message>> {
+        let mut framed = match std::mem::replace(&mut self.inner, Inner::Upgrading) {
+            Inner::Handshake(framed) => framed,
+            _ => unreachable!(),
+        };
+
+        match handshake::accept(&request, &self.protocols) {
+            Ok((response, protocol)) => {
+                let res = framed.send(response);
+                let max = self.max_message;
+                self.inner = Inner::Open(framed.map_codec(|_| WebSocketCodec::new(Role::Server).max_message_size(max)));
+                self.protocol = protocol;
+                self.state = State::Open;
+
+                if let Err(e) = res {
+                    return self.fail(e);
+                }
+
+                for message in std::mem::take(&mut self.queued) {
+                    if let Err(e) = self.send(message) {
+                        return self.fail(e);
+                    }
+                }
+
+                Reaction::Continue
+            }
+            Err(response) => {
+                let res = framed.send(response.header("Connection", "close"));
+                self.inner = Inner::Handshake(framed);
+                self.state = State::Closed;
+                self.queued.clear();
+
+                match res {
+                    Ok(()) => Reaction::Value(Err(Error::protocol("websocket handshake rejected"))),
+                    Err(e) => self.fail(e),
+                }
+            }
+        }
+    }
+
+    fn send_request(&mut self) -> Result<()> {
+        let mut request = match self.request.take() {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+
+        if !self.protocols.is_empty() {
+            request.headers.append("Sec-WebSocket-Protocol", self.protocols.join(", "));
+        }
+
+        match &mut self.inner {
+            Inner::Connecting(framed) => framed.send(request),
+            _ => Ok(()),
+        }
+    }
+
+    fn connected(&mut self, response: Response) -> Reaction<Result<Message>> {
+        let framed = match std::mem::replace(&mut self.inner, Inner::Upgrading) {
+            Inner::Connecting(framed) => framed,
+            _ => unreachable!(),
+        };
+
+        let max = self.max_message;
+        self.inner = Inner::Open(framed.map_codec(|_| WebSocketCodec::new(Role::Client).max_message_size(max)));
+
+        match handshake::validate(&response, &self.key, &self.protocols) {
+            Ok(protocol) => {
+                self.protocol = protocol;
+                self.state = State::Open;
+            }
+            Err(e) => {
+                self.state = State::Closed;
+                return Reaction::Value(Err(e));
+            }
+        }
+
+        for message in std::mem::take(&mut self.queued) {
+            if let Err(e) = self.send(message) {
+                return self.fail(e);
+            }
+        }
+
+        Reaction::Continue
+    }
+
+    fn received(&mut self, message: Message) -> Reaction<Result<Message>> {
+        let framed = match &mut self.inner {
+            Inner::Open(framed) => framed,
+            _ => unreachable!(),
+        };
+
+        let res = match (&message, self.state) {
+            (Message::Ping(payload), State::Open) => framed.send(Message::Pong(payload.clone())),
+            (Message::Close(close), State::Open) => {
+                self.state = State::Closed;
+                let code = close.as_ref().map(|(code, _)| (*code, String::new()));
+                framed.send(Message::Close(code))
+            }
+            (Message::Close(_), _) => {
+                self.state = State::Closed;
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+
+        match res {
+            Ok(()) => Reaction::Value(Ok(message)),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn fail(&mut self, e: Error) -> Reaction<Result<Message>> {
+        if let (Error::Protocol(_), Inner::Open(framed), State::Open) = (&e, &mut self.inner, self.state) {
+            let code = framed.codec().close_code().unwrap_or(CloseCode::PROTOCOL_ERROR);
+            let _ = framed.send(Message::Close(Some((code, String::new()))));
+        }
+
+        self.state = State::Closed;
+        Reaction::Value(Err(e))
+    }
+
+    fn shut_down_when_written(&mut self) {
+        let written = match &self.inner {
+            Inner::Handshake(framed) => framed.write_buffer().is_empty(),
+            Inner::Connecting(framed) => framed.write_buffer().is_empty(),
+            Inner::Open(framed) => framed.write_buffer().is_empty(),
+            Inner::Upgrading => false,
+        };
+
+        if self.state == State::Closed && written && !self.shut_down {
+            unsafe { libc::shutdown(self.raw_fd(), libc::SHUT_RDWR) };
+            self.shut_down = true;
+        }
+    }
+
+    fn raw_fd(&self) -> i32 {
+        match &self.inner {
+            Inner::Handshake(framed) => framed.get_ref().as_raw_fd(),
+            Inner::Connecting(framed) => framed.get_ref().as_raw_fd(),
+            Inner::Open(framed) => framed.get_ref().as_raw_fd(),
+            Inner::Upgrading => -1,
+        }
+    }
+}
+
+impl<S> Reactor for WebSocket<S>
+where
+    S: AsRawFd + Read + Write,
+{
+    type Input = ();
+    type Output = Result<Message>;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        let ev = match reaction {
+            Reaction::Event(ev) if ev.owner != self.id => return Reaction::Event(ev),
+            Reaction::Event(_) if self.shut_down => return Reaction::Continue,
+            Reaction::Event(ev) => ev,
+            _ => return Reaction::Continue,
+        };
+
+        if let Err(e) = self.send_request() {
+            let reaction = self.fail(e);
+            self.shut_down_when_written();
+            return reaction;
+        }
+
+        let reaction = match &mut self.inner {
+            Inner::Connecting(framed) => match framed.react(Reaction::Event(ev)) {
+                Reaction::Value(Ok(response)) => self.connected(response),
+                Reaction::Value(Err(e)) => self.fail(e),
+                _ => Reaction::Continue,
+            },
+            Inner::Handshake(framed) => match framed.react(Reaction::Event(ev)) {
+                Reaction::Value(Ok(_)) if self.state != State::Handshake => Reaction::Continue,
+                Reaction::Value(Ok(request)) => self.upgrade(request),
+                Reaction::Value(Err(e)) => self.fail(e),
+                _ => Reaction::Continue,
+            },
+            Inner::Open(framed) => match framed.react(Reaction::Event(ev)) {
+                Reaction::Value(Ok(_)) if self.state == State::Closed => Reaction::Continue,
+                Reaction::Value(Ok(message)) => self.received(message),
+                Reaction::Value(Err(e)) => self.fail(e),
+                _ => Reaction::Continue,
+            },
+            Inner::Upgrading => Reaction::Continue,
+        };
+
+        self.shut_down_when_written();
+        reaction
+    }
+}
+
+impl WebSocket<StdTcpStream> {
+    /// Connect to `authority` (`host:port`) and request an upgrade for `path`.
+    ///
+    /// ```no_run
+    /// # use netlib::{Reactor, System};
+    /// # use netlib::websocket::{Message, WebSocket};
+    /// System::builder().finish();
+    /// let mut ws = WebSocket::connect("127.0.0.1:9000", "/game").unwrap().protocols(&["v1"]);
+    /// ws.send(Message::Text("hello".into())).unwrap();
+    /// System::start(ws.map(|msg| println!("{:?}", msg)));
+    /// ```
+    pub fn connect(authority: &str, path: &str) -> Result<Self> {
+        Ok(Self::client(TcpStream::connect(authority)?, authority, path))
+    }
+}
+
+// -----------------------------------------------------------------------------
+//     - WebSocket server -
+// -----------------------------------------------------------------------------
+/// Accepts WebSocket connections from a `TcpListener` and calls the
+/// handler with every message and the connection it came from.
+///
+/// A connection that can't be accepted or added, or fails later on,
+/// yields its error, and the server carries on. Events that don't belong
+/// to a connection are passed on.
+pub struct WebSocketServer<F> {
+    handler: F,
+    protocols: Vec<String>,
+    max_message: usize,
+    connections: HashMap<u64, (WebSocket<StdTcpStream>, Option<ConnectionSlot>)>,
+}
+
+impl<F> WebSocketServer<F>
+where
+    F: FnMut(&mut WebSocket<StdTcpStream>, Message),
+{
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            protocols: Vec::new(),
+            max_message: DEFAULT_MAX_MESSAGE,
+            connections: HashMap::new(),
+        }
+    }
+
+    /// The subprotocols supported, most preferred first.
+    pub fn protocols(mut self, protocols: &[&str]) -> Self {
+        self.protocols = protocols.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// The largest message accepted, in bytes.
+    pub fn max_message_size(mut self, max: usize) -> Self {
+        self.max_message = max;
+        self
+    }
+
+    /// The number of open connections.
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    fn add(&mut self, stream: StdTcpStream, slot: Option<ConnectionSlot>) -> Result<()> {
+        let mut ws = WebSocket::accept(TcpStream::new(stream, Interest::Read)?).max_message_size(self.max_message);
+        ws.protocols = self.protocols.clone();
+        self.connections.insert(ws.id(), (ws, slot));
+        Ok(())
+    }
+}
+
+impl<F> Reactor for WebSocketServer<F>
+where
+    F: FnMut(&mut WebSocket<StdTcpStream>, Message),
+{
+    type Input = Result<Accepted>;
+    type Output = Error;
+
+    fn react(&mut self, reaction: Reaction<Self::Input>) -> Reaction<Self::Output> {
+        match reaction {
+            Reaction::Value(Ok((stream, _, slot))) => match self.add(stream, slot) {
+                Ok(()) => Reaction::Continue,
+                Err(e) => Reaction::Value(e),
+            },
+            Reaction::Value(Err(e)) => Reaction::Value(e),
+            Reaction::Event(ev) => {
+                let ws = match self.connections.get_mut(&ev.owner) {
+                    Some((ws, _)) => ws,
+                    None => return Reaction::Event(ev),
+                };
+
+                let reaction = match ws.react(Reaction::Event(ev)) {
+                    Reaction::Value(Ok(message)) => {
+                        (self.handler)(ws, message);
+                        Reaction::Continue
+                    }
+                    Reaction::Value(Err(e)) => Reaction::Value(e),
+                    _ => Reaction::Continue,
+                };
+
+                if ws.is_closed() {
+                    self.connections.remove(&ev.owner);
+                }
+
+                reaction
+            }
+            Reaction::Continue => Reaction::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::os::unix::net::UnixStream as StdUnixStream;
+    use crate::codecs::{Decoder, Encoder};
+    use std::sync::mpsc;
+    use std::thread;
+    use crate::net::tcp::TcpListener;
+    use crate::net::uds::UnixStream;
+    use crate::{Event, System};
+
+    #[test]
+    fn upgrade_echo_and_close() {
+        System::builder().finish();
+        let (a, mut b) = StdUnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let mut ws = WebSocket::accept(UnixStream::try_from(a).unwrap()).protocols(&["game"]);
+        let ev = Event { read: true, write: false, owner: ws.id() };
+
+        let mut client = WebSocketCodec::new(Role::Client);
+        let mut out = b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                        Sec-WebSocket-Protocol: chat, game\r\nSec-WebSocket-Version: 13\r\n\r\n"
+            .to_vec();
+        client.encode(Message::Ping(b"p".to_vec()), &mut out).unwrap();
+        client.encode(Message::Close(Some((CloseCode::GOING_AWAY, "bye".into()))), &mut out).unwrap();
+        b.write_all(&out).unwrap();
+
+        let reactions = (0..4).map(|_| ws.react(Reaction::Event(ev))).collect::<Vec<_>>();
+        assert!(matches!(reactions[0], Reaction::Continue));
+        assert!(matches!(&reactions[1], Reaction::Value(Ok(Message::Ping(p))) if p == b"p"));
+        assert!(matches!(&reactions[2], Reaction::Value(Ok(Message::Close(Some((CloseCode::GOING_AWAY, _)))))));
+        assert_eq!(ws.protocol(), Some("game"));
+        assert!(ws.is_closed());
+
+        let mut response = Vec::new();
+        b.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&response[..end]);
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(head.contains("Sec-WebSocket-Protocol: game\r\n"));
+
+        let mut frames = response[end..].to_vec();
+        assert_eq!(client.decode(&mut frames).unwrap(), Some(Message::Pong(b"p".to_vec())));
+        assert_eq!(client.decode(&mut frames).unwrap(), Some(Message::Close(Some((CloseCode::GOING_AWAY, String::new())))));
+    }
+
+    #[test]
+    fn send_before_upgrade() {
+        System::builder().finish();
+        let (a, mut b) = StdUnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let mut ws = WebSocket::accept(UnixStream::try_from(a).unwrap());
+        ws.send(Message::Text("welcome".into())).unwrap();
+
+        b.write_all(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                      Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+            .unwrap();
+        let ev = Event { read: true, write: false, owner: ws.id() };
+        assert!(matches!(ws.react(Reaction::Event(ev)), Reaction::Continue));
+        assert!(ws.is_open());
+        drop(ws);
+
+        let mut response = Vec::new();
+        b.read_to_end(&mut response).unwrap();
+        let end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let mut frames = response[end..].to_vec();
+        let mut client = WebSocketCodec::new(Role::Client);
+        assert_eq!(client.decode(&mut frames).unwrap(), Some(Message::Text("welcome".into())));
+    }
+
+    #[test]
+    fn client_echo() {
+        let (tx, rx) = mpsc::channel();
+
+        let server = thread::spawn(move || {
+            System::builder().finish();
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+
+            let server = WebSocketServer::new(|ws, msg| {
+                assert_eq!(ws.protocol(), Some("v2"));
+                if let Message::Binary(_) = msg {
+                    System::stop();
+                }
+                ws.send(msg).unwrap();
+            });
+
+            System::start(listener.chain(server.protocols(&["v2"]))).unwrap();
+        });
+
+        System::builder().finish();
+        let addr = rx.recv().unwrap().to_string();
+        let mut ws = WebSocket::connect(&addr, "/").unwrap().protocols(&["v1", "v2"]);
+        ws.send(Message::Text("hello".into())).unwrap();
+        ws.send(Message::Binary(vec![0; 1000])).unwrap();
+
+        let mut received = Vec::new();
+        let ws = ws.filter_map(Result::ok).map(move |msg| {
+            received.push(msg);
+            if received.len() == 2 {
+                assert_eq!(received[0], Message::Text("hello".into()));
+                assert_eq!(received[1], Message::Binary(vec![0; 1000]));
+                System::stop();
+            }
+        });
+        System::start(ws).unwrap();
+        server.join().unwrap();
+    }
+}